@@ -0,0 +1,44 @@
+use derivative::Derivative;
+use serde::Deserialize;
+use std::error::Error;
+
+/// Adaptive sampling: pixels stop being sampled once their estimate is precise enough
+#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct AdaptiveSampling {
+    /// Relative standard error of a pixel under which it is considered converged
+    #[derivative(Default(value = "default_threshold()"))]
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    #[derivative(Default(value = "default_min_samples()"))]
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+    #[derivative(Default(value = "default_max_samples()"))]
+    #[serde(default = "default_max_samples")]
+    pub max_samples: usize,
+}
+
+impl AdaptiveSampling {
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.min_samples > self.max_samples {
+            return Err(format!(
+                "The adaptive sampling minimum ({}) is above the maximum ({})",
+                self.min_samples, self.max_samples
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn default_threshold() -> f32 {
+    0.01
+}
+
+fn default_min_samples() -> usize {
+    16
+}
+
+fn default_max_samples() -> usize {
+    1024
+}
@@ -0,0 +1,76 @@
+use derivative::Derivative;
+use serde::Deserialize;
+
+/// Light above a threshold spread around the bright pixels
+#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct Bloom {
+    /// Luminance (after the exposure) above which the light blooms
+    #[derivative(Default(value = "default_threshold()"))]
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Fraction of the light above the threshold spread around
+    #[derivative(Default(value = "default_intensity()"))]
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    /// Standard deviation of the widest Gaussian, relative to the image width
+    #[derivative(Default(value = "default_radius()"))]
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    /// Number of Gaussians averaged, each one half as wide as the previous one
+    #[derivative(Default(value = "default_scales()"))]
+    #[serde(default = "default_scales")]
+    pub scales: usize,
+    /// Streaks of light starring the bright pixels
+    #[serde(default)]
+    pub glare: Option<Glare>,
+}
+
+fn default_threshold() -> f32 {
+    1.
+}
+
+fn default_intensity() -> f32 {
+    0.1
+}
+
+fn default_radius() -> f32 {
+    0.02
+}
+
+fn default_scales() -> usize {
+    4
+}
+
+/// Star of streaks, from the light above the bloom threshold
+#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct Glare {
+    /// Number of streaks of the star, evenly spread around the bright pixels
+    #[derivative(Default(value = "default_blades()"))]
+    #[serde(default = "default_blades")]
+    pub blades: usize,
+    /// Fraction of the light above the threshold spread along the streaks
+    #[derivative(Default(value = "default_glare_intensity()"))]
+    #[serde(default = "default_glare_intensity")]
+    pub intensity: f32,
+    /// Length over which the streaks fade out, relative to the image width
+    #[derivative(Default(value = "default_length()"))]
+    #[serde(default = "default_length")]
+    pub length: f32,
+    /// Rotation of the star, in degrees
+    #[serde(default)]
+    pub angle: f32,
+}
+
+fn default_blades() -> usize {
+    6
+}
+
+fn default_glare_intensity() -> f32 {
+    0.05
+}
+
+fn default_length() -> f32 {
+    0.1
+}
@@ -0,0 +1,63 @@
+use derivative::Derivative;
+use serde::Deserialize;
+use std::error::Error;
+
+/// Edge-avoiding à-trous denoiser, guided by the albedo, normal and depth of the first hits
+#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct Denoiser {
+    /// Number of filtering passes, each one reaching twice as far as the previous one
+    #[derivative(Default(value = "default_iterations()"))]
+    #[serde(default = "default_iterations")]
+    pub iterations: usize,
+    /// Luminance difference tolerated between two pixels, in standard errors of the estimate
+    #[derivative(Default(value = "default_color_sigma()"))]
+    #[serde(default = "default_color_sigma")]
+    pub color_sigma: f32,
+    #[derivative(Default(value = "default_albedo_sigma()"))]
+    #[serde(default = "default_albedo_sigma")]
+    pub albedo_sigma: f32,
+    #[derivative(Default(value = "default_normal_sigma()"))]
+    #[serde(default = "default_normal_sigma")]
+    pub normal_sigma: f32,
+    /// Depth difference tolerated between two pixels, relative to their depth
+    #[derivative(Default(value = "default_depth_sigma()"))]
+    #[serde(default = "default_depth_sigma")]
+    pub depth_sigma: f32,
+}
+
+impl Denoiser {
+    /// Passes beyond this one would reach further than any image
+    pub const MAX_ITERATIONS: usize = 16;
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.iterations > Self::MAX_ITERATIONS {
+            return Err(format!(
+                "The denoiser can't do more than {} iterations",
+                Self::MAX_ITERATIONS
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn default_iterations() -> usize {
+    5
+}
+
+fn default_color_sigma() -> f32 {
+    2.
+}
+
+fn default_albedo_sigma() -> f32 {
+    0.1
+}
+
+fn default_normal_sigma() -> f32 {
+    0.5
+}
+
+fn default_depth_sigma() -> f32 {
+    0.1
+}
@@ -0,0 +1,33 @@
+use derivative::Derivative;
+use serde::Deserialize;
+
+/// Exposure scaling the log-average luminance of the image to a key value
+#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct AutoExposure {
+    /// Luminance the log-average luminance is mapped to (0.18 being middle grey)
+    #[derivative(Default(value = "default_key()"))]
+    #[serde(default = "default_key")]
+    pub key: f32,
+}
+
+fn default_key() -> f32 {
+    0.18
+}
+
+/// Color of the lighting rendered white
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum WhiteBalance {
+    /// Color temperature (in Kelvin) of the lighting, on the daylight locus above 4000K and on
+    /// the blackbody locus below
+    Temperature {
+        temperature: f32,
+        /// Distance of the lighting from the locus, towards green (positive) or magenta
+        /// (negative), in thousandths of CIE 1960 uv
+        #[serde(default)]
+        tint: f32,
+    },
+    /// Linear color (in the working space) of a surface that should look white
+    Reference { white: [f32; 3] },
+}
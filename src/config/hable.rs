@@ -0,0 +1,77 @@
+use derivative::Derivative;
+use serde::Deserialize;
+use std::error::Error;
+
+/// Parameters of John Hable's filmic curve (Uncharted 2)
+#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derivative(Default)]
+pub struct Hable {
+    /// Scale of the colors before the curve
+    #[derivative(Default(value = "default_exposure_bias()"))]
+    #[serde(default = "default_exposure_bias")]
+    pub exposure_bias: f32,
+    #[derivative(Default(value = "default_shoulder_strength()"))]
+    #[serde(default = "default_shoulder_strength")]
+    pub shoulder_strength: f32,
+    #[derivative(Default(value = "default_linear_strength()"))]
+    #[serde(default = "default_linear_strength")]
+    pub linear_strength: f32,
+    #[derivative(Default(value = "default_linear_angle()"))]
+    #[serde(default = "default_linear_angle")]
+    pub linear_angle: f32,
+    #[derivative(Default(value = "default_toe_strength()"))]
+    #[serde(default = "default_toe_strength")]
+    pub toe_strength: f32,
+    #[derivative(Default(value = "default_toe_numerator()"))]
+    #[serde(default = "default_toe_numerator")]
+    pub toe_numerator: f32,
+    #[derivative(Default(value = "default_toe_denominator()"))]
+    #[serde(default = "default_toe_denominator")]
+    pub toe_denominator: f32,
+    /// Linear value mapped to white
+    #[derivative(Default(value = "default_white()"))]
+    #[serde(default = "default_white")]
+    pub white: f32,
+}
+
+impl Hable {
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // The curve is normalized by its value at white
+        if !(self.white.is_finite() && self.white > 0.) {
+            return Err(format!("The Hable white point ({}) must be positive", self.white).into());
+        }
+        Ok(())
+    }
+}
+
+fn default_exposure_bias() -> f32 {
+    2.
+}
+
+fn default_shoulder_strength() -> f32 {
+    0.15
+}
+
+fn default_linear_strength() -> f32 {
+    0.5
+}
+
+fn default_linear_angle() -> f32 {
+    0.1
+}
+
+fn default_toe_strength() -> f32 {
+    0.2
+}
+
+fn default_toe_numerator() -> f32 {
+    0.02
+}
+
+fn default_toe_denominator() -> f32 {
+    0.3
+}
+
+fn default_white() -> f32 {
+    11.2
+}
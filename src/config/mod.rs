@@ -1,8 +1,18 @@
+mod adaptive;
+mod bloom;
+mod denoiser;
+mod exposure;
+mod hable;
 mod profile;
 mod resolution;
 
+pub use adaptive::AdaptiveSampling;
+pub use bloom::{Bloom, Glare};
 use clap::Parser;
+pub use denoiser::Denoiser;
 use derivative::Derivative;
+pub use exposure::{AutoExposure, WhiteBalance};
+pub use hable::Hable;
 pub use profile::Profile;
 pub use resolution::Resolution;
 use std::path::PathBuf;
@@ -23,7 +33,7 @@ pub enum Config {
 pub struct RenderConfig {
     /// Input file name ISF format
     pub input: PathBuf,
-    /// Output image name
+    /// Output image name (OpenEXR `.exr` and Radiance `.hdr` files get the linear image)
     #[clap(long, short, env, default_value = "render.png")]
     pub output: PathBuf,
     /// No progress bar printed
@@ -39,6 +49,37 @@ pub struct RenderConfig {
     /// A path to the yaml file containing all the rendering profile information
     #[clap(long, short, env)]
     pub profile: Option<PathBuf>,
+    /// Save an image of the number of samples spent on each pixel
+    #[clap(long)]
+    pub sample_map: Option<PathBuf>,
+    /// Periodically save the state of the render to this file
+    #[clap(long)]
+    pub checkpoint: Option<PathBuf>,
+    /// Seconds between two checkpoints
+    #[clap(long, default_value = "60")]
+    #[derivative(Default(value = "60"))]
+    pub checkpoint_interval: u64,
+    /// Resume the render from a checkpoint file
+    #[clap(long)]
+    pub resume: Option<PathBuf>,
+    /// Save the image to the output file as tiles finish
+    #[clap(long)]
+    pub save_tiles: bool,
+    /// Also save the image before denoising to this file
+    #[clap(long)]
+    pub raw_output: Option<PathBuf>,
+    /// Save the render passes of the profile as the layers of this OpenEXR file, or as one
+    /// OpenEXR file per pass in this directory (defaults to the output with a `.passes.exr`
+    /// extension)
+    #[clap(long)]
+    pub passes: Option<PathBuf>,
+    /// Also save the linear image (in the working space) to this file, as OpenEXR or Radiance
+    /// HDR depending on its extension
+    #[clap(long)]
+    pub hdr_output: Option<PathBuf>,
+    /// Save the OpenEXR images with 16-bit half floats instead of 32-bit floats
+    #[clap(long)]
+    pub half: bool,
 }
 
 #[derive(Parser, Debug, Clone, Derivative)]
@@ -1,13 +1,20 @@
-use super::Resolution;
+use super::{AdaptiveSampling, AutoExposure, Bloom, Denoiser, Hable, Resolution, WhiteBalance};
 use crate::renderer::brdf::BrdfType;
+use crate::renderer::color::{DisplaySpace, WorkingSpace};
+use crate::renderer::filter::FilterType;
+use crate::renderer::integrator::IntegratorType;
+use crate::renderer::passes::Passes;
+use crate::renderer::sampler::SamplerType;
+use crate::renderer::tiles::TileOrder;
 use crate::renderer::tonemap::TonemapType;
 use derivative::Derivative;
 use serde::Deserialize;
 use std::error::Error;
 use std::fs::read_to_string;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Copy, Clone, Debug, Deserialize, Derivative)]
+#[derive(Clone, Debug, Deserialize, Derivative)]
 #[derivative(Default)]
 pub struct Profile {
     #[serde(default)]
@@ -18,16 +25,134 @@ pub struct Profile {
     #[derivative(Default(value = "default_samples()"))]
     #[serde(default = "default_samples")]
     pub samples: usize,
+    /// Spend the samples on the noisiest pixels (`samples` per pixel on average)
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Keep adding passes until this many seconds are spent instead of stopping after `samples`
+    #[serde(default)]
+    pub time_limit: Option<f32>,
+    /// Stop once the average relative error of the pixels gets under this level
+    #[serde(default)]
+    pub target_noise: Option<f32>,
+    /// Render the image in square tiles of this size (in pixels) instead of full frame passes
+    #[serde(default)]
+    pub tile_size: Option<u32>,
+    #[serde(default)]
+    pub tile_order: TileOrder,
+    /// Reconstruction filter the samples are splatted with
+    #[serde(default)]
+    pub filter: FilterType,
+    /// Radius of the reconstruction filter (in pixels), defaults to one suited to the filter
+    #[serde(default)]
+    pub filter_radius: Option<f32>,
+    /// Denoise the image before tone mapping it
+    #[serde(default)]
+    pub denoiser: Option<Denoiser>,
+    /// Render passes (AOVs) saved alongside the image
+    #[serde(default)]
+    pub passes: Passes,
     #[serde(default)]
     pub brdf: BrdfType,
+    /// Linear RGB space the lighting is computed in
+    #[serde(default)]
+    pub working_space: WorkingSpace,
+    /// Carry a few wavelengths along the paths instead of RGB colors, for dispersion
+    #[serde(default)]
+    pub spectral: bool,
+    /// Exposure compensation in EV stops (each one doubling the brightness)
+    #[serde(default)]
+    pub exposure: f32,
+    /// Scale the brightness so the log-average luminance of the image matches a key value
+    #[serde(default)]
+    pub auto_exposure: Option<AutoExposure>,
+    /// Render the color of the lighting as white
+    #[serde(default)]
+    pub white_balance: Option<WhiteBalance>,
+    /// Spread the light above a threshold around the bright pixels, before tone mapping
+    #[serde(default)]
+    pub bloom: Option<Bloom>,
     #[serde(default)]
     pub tonemap: TonemapType,
+    /// Parameters of the curve (HABLE tone map)
+    #[serde(default)]
+    pub hable: Hable,
+    /// 3D LUT (`.cube` file, relative to the profile) applied to the tone mapped colors, for a
+    /// look
+    #[serde(default)]
+    pub lut: Option<PathBuf>,
+    /// Primaries and transfer function of the display the image is encoded for
+    #[serde(default)]
+    pub display: DisplaySpace,
+    #[serde(default)]
+    pub integrator: IntegratorType,
+    /// Sequence the sample values are drawn from
+    #[serde(default)]
+    pub sampler: SamplerType,
+    /// Number of Metropolis mutations per pixel (PSSMLT integrator)
+    #[derivative(Default(value = "default_mutations()"))]
+    #[serde(default = "default_mutations")]
+    pub mutations: usize,
+    /// Number of photons traced per sample (PPPM integrator)
+    #[derivative(Default(value = "default_photons()"))]
+    #[serde(default = "default_photons")]
+    pub photons: usize,
+    /// Initial photon gather radius, shrinking with the samples (PPPM integrator)
+    #[derivative(Default(value = "default_photon_radius()"))]
+    #[serde(default = "default_photon_radius")]
+    pub photon_radius: f32,
 }
 
 impl Profile {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let serialized = &read_to_string(path)?;
-        Ok(serde_yaml::from_str(serialized)?)
+        let serialized = &read_to_string(&path)?;
+        let mut profile: Self = serde_yaml::from_str(serialized)?;
+        if let (Some(lut), Some(directory)) = (&mut profile.lut, path.as_ref().parent()) {
+            *lut = directory.join(&lut);
+        }
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Check the settings are consistent
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.resolution.validate()?;
+        if self.mutations == 0 {
+            return Err("The number of mutations must be positive".into());
+        }
+        if self.photons == 0 {
+            return Err("The number of photons must be positive".into());
+        }
+        if !(self.photon_radius.is_finite() && self.photon_radius > 0.) {
+            return Err(format!(
+                "The photon radius ({}) must be positive",
+                self.photon_radius
+            )
+            .into());
+        }
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.validate()?;
+        }
+        if let Some(time_limit) = self.time_limit {
+            // Also rules out the limits too long to be a duration
+            if time_limit <= 0. || Duration::try_from_secs_f32(time_limit).is_err() {
+                return Err(format!("The time limit ({time_limit}s) must be positive").into());
+            }
+        }
+        if let Some(target_noise) = self.target_noise {
+            if target_noise.is_nan() || target_noise <= 0. {
+                return Err(format!("The target noise ({target_noise}) must be positive").into());
+            }
+        }
+        if let Some(radius) = self.filter_radius {
+            if !(radius.is_finite() && radius > 0.) {
+                return Err(format!("The filter radius ({radius}) must be positive").into());
+            }
+        }
+        if let Some(denoiser) = &self.denoiser {
+            denoiser.validate()?;
+        }
+        self.hable.validate()?;
+        Ok(())
     }
 }
 
@@ -38,3 +163,109 @@ fn default_bounces() -> usize {
 fn default_samples() -> usize {
     64
 }
+
+fn default_mutations() -> usize {
+    64
+}
+
+fn default_photons() -> usize {
+    100_000
+}
+
+fn default_photon_radius() -> f32 {
+    0.05
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Profile {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn denoiser_iterations() {
+        assert!(parse("denoiser:\n  iterations: 16").validate().is_ok());
+        assert!(parse("denoiser:\n  iterations: 31").validate().is_err());
+    }
+
+    #[test]
+    fn adaptive_samples() {
+        assert!(parse("adaptive:\n  min_samples: 8\n  max_samples: 8")
+            .validate()
+            .is_ok());
+        assert!(parse("adaptive:\n  min_samples: 16\n  max_samples: 8")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn mutations() {
+        assert!(parse("mutations: 1").validate().is_ok());
+        assert!(parse("mutations: 0").validate().is_err());
+    }
+
+    #[test]
+    fn photons() {
+        assert!(parse("photons: 1\nphoton_radius: 0.1").validate().is_ok());
+        assert!(parse("photons: 0").validate().is_err());
+        // The integrator was first named after stochastic progressive photon mapping
+        assert!(matches!(
+            parse("integrator: SPPM").integrator,
+            IntegratorType::Pppm
+        ));
+        for radius in ["0", "-0.1", ".nan", ".inf"] {
+            assert!(parse(&format!("photon_radius: {radius}"))
+                .validate()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn time_limit() {
+        assert!(parse("time_limit: 0.5\ntarget_noise: 0.01")
+            .validate()
+            .is_ok());
+        for time_limit in ["0", "-1", ".nan", ".inf", "1e30"] {
+            assert!(parse(&format!("time_limit: {time_limit}"))
+                .validate()
+                .is_err());
+        }
+        for target_noise in ["0", "-0.01", ".nan"] {
+            assert!(parse(&format!("target_noise: {target_noise}"))
+                .validate()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn filter_radius() {
+        assert!(parse("filter_radius: 0.1").validate().is_ok());
+        for radius in ["0", "-1", ".nan", ".inf"] {
+            assert!(parse(&format!("filter_radius: {radius}"))
+                .validate()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn hable_white() {
+        assert!(parse("hable:\n  white: 4").validate().is_ok());
+        for white in ["0", "-1", ".nan", ".inf"] {
+            assert!(parse(&format!("hable:\n  white: {white}"))
+                .validate()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn resolution() {
+        assert!(parse("resolution:\n  width: 1\n  height: 1")
+            .validate()
+            .is_ok());
+        assert!(parse("resolution:\n  width: 0\n  height: 600")
+            .validate()
+            .is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::error::Error;
 
 #[derive(Copy, Clone, Debug, Deserialize)]
 pub struct Resolution {
@@ -14,3 +15,16 @@ impl Default for Resolution {
         }
     }
 }
+
+impl Resolution {
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!(
+                "The resolution ({}x{}) has no pixels",
+                self.width, self.height
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
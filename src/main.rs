@@ -5,6 +5,7 @@ mod scene;
 use clap::Parser;
 use config::{Config, ConvertConfig, Profile, RenderConfig};
 use renderer::debug_renderer::debug_render;
+use renderer::hdr::is_hdr;
 use renderer::Renderer;
 use scene::internal::Scene;
 use scene::{convert_gltf_to_isf, load_internal};
@@ -35,7 +36,7 @@ fn run_render(config: RenderConfig) -> Result<(), Box<dyn Error + Send + Sync>>
         None => Default::default(),
     };
 
-    let scene = load_internal(&config.input)?;
+    let scene = load_internal(&config.input, profile.working_space)?;
 
     if config.debug_textures {
         debug_render(&scene, profile.resolution);
@@ -43,11 +44,16 @@ fn run_render(config: RenderConfig) -> Result<(), Box<dyn Error + Send + Sync>>
     }
 
     // Send scene to Renderer
-    let renderer = Renderer::new(&config, profile);
-    let rendered_image = renderer.render(&scene);
+    let mut renderer = Renderer::new(&config, profile)?;
+    if let Some(path) = &config.resume {
+        renderer.resume(path)?;
+    }
+    let rendered_image = renderer.render(&scene)?;
 
-    // Save image
-    rendered_image.save(config.output)?;
+    // Save image (HDR images are saved by the renderer from the linear colors)
+    if !is_hdr(&config.output) {
+        rendered_image.save(config.output)?;
+    }
     Ok(())
 }
 
@@ -61,9 +67,19 @@ mod tests {
     use image::EncodableLayout;
     use sha1::{digest::Update, Digest, Sha1};
 
-    use crate::config::Resolution;
+    use crate::config::{
+        AdaptiveSampling, AutoExposure, Bloom, Denoiser, Glare, Resolution, WhiteBalance,
+    };
+    use crate::renderer::color::{DisplaySpace, WorkingSpace};
+    use crate::renderer::filter::FilterType;
+    use crate::renderer::integrator::IntegratorType;
+    use crate::renderer::passes::Pass;
+    use crate::renderer::sampler::SamplerType;
+    use crate::renderer::tiles::TileOrder;
+    use crate::renderer::tonemap::TonemapType;
 
     use super::*;
+    use std::collections::HashMap;
     use std::path::Path;
     use std::str;
 
@@ -75,12 +91,32 @@ mod tests {
             input: path.as_ref().to_path_buf(),
             ..Default::default()
         };
-        let scene = load_internal(&config.input).unwrap();
-        let image = Renderer::new(&config, profile).render(&scene);
+        let scene = load_internal(&config.input, profile.working_space).unwrap();
+        let image = Renderer::new(&config, profile)
+            .unwrap()
+            .render(&scene)
+            .unwrap();
         let hash = Sha1::new().chain(image.as_bytes()).finalize();
         assert_eq!(format!("{:02x}", &hash), expected_hash);
     }
 
+    /// Samples of the channels of an OpenEXR file, named `layer.channel` (`channel` for unnamed
+    /// layers)
+    fn read_channels<P: AsRef<Path>>(path: P) -> HashMap<String, Vec<f32>> {
+        let image = exr::prelude::read_all_flat_layers_from_file(path).unwrap();
+        let mut channels = HashMap::new();
+        for layer in &image.layer_data {
+            for channel in &layer.channel_data.list {
+                let name = match &layer.attributes.layer_name {
+                    Some(layer) => format!("{layer}.{}", channel.name),
+                    None => channel.name.to_string(),
+                };
+                channels.insert(name, channel.sample_data.values_as_f32().collect());
+            }
+        }
+        channels
+    }
+
     fn test_scene<P>(path: P, expected_hash: &str)
     where
         P: AsRef<Path>,
@@ -101,7 +137,7 @@ mod tests {
     fn cube() {
         test_scene(
             "tests/scenes/cube/scene.isf",
-            "60558456ace7e8063ebfab219ee35a2c7de862f5",
+            "295083c9581c0ac1a6fc8f587482a8c1e0445ff6",
         );
     }
 
@@ -109,7 +145,7 @@ mod tests {
     fn reflection() {
         test_scene(
             "tests/scenes/reflection/scene.isf",
-            "6ccc3b9f20442f15f25c41cf8d342ede5185e3db",
+            "5382dfce3e24fdf514522213d01fd43a14922182",
         );
     }
 
@@ -117,7 +153,7 @@ mod tests {
     fn head() {
         test_scene(
             "tests/scenes/head/scene.isf",
-            "2c90976144ba14fe9f06ec3c812ff30f0a0c9146",
+            "f727c3b11600062091f578e149942af891839a1c",
         );
     }
 
@@ -125,7 +161,7 @@ mod tests {
     fn spheres() {
         test_scene(
             "tests/scenes/spheres/scene.isf",
-            "fe2687e274ac978a4815f202612eca71ee8dd8c9",
+            "caf2d7cb87a22cc2d0e6e57e37938b7b58dba6f3",
         );
     }
 
@@ -133,7 +169,58 @@ mod tests {
     fn alpha_transparency() {
         test_scene(
             "tests/scenes/alpha_transparency/scene.isf",
-            "fdf9ccbe9dc3f3102e3c05b96d2984000e73b62f",
+            "dbad1d13289eb5a01e0fb97d116e17746c842a2b",
+        );
+    }
+
+    #[test]
+    fn emissive() {
+        test_scene(
+            "tests/scenes/emissive/scene.isf",
+            "9b47d4911d1c54b4cd97abf22050a00d5498ed0c",
+        );
+    }
+
+    #[test]
+    fn spot() {
+        test_scene(
+            "tests/scenes/spot/scene.isf",
+            "04c16ce4aa0188b20c711c355d778130faf45b25",
+        );
+    }
+
+    #[test]
+    fn environment() {
+        test_scene(
+            "tests/scenes/environment/scene.isf",
+            "d22e21bafd7ce9a8296f9af20c23538c1a2cc2ad",
+        );
+    }
+
+    #[test]
+    fn glass() {
+        test_scene(
+            "tests/scenes/glass/scene.isf",
+            "b4285cf2a26dc4953e5def96580edbe486415d7a",
+        );
+    }
+
+    #[test]
+    fn fog() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/fog/scene.isf",
+            "2486b7f17fea95ae6f9acc1a13d6f6bbf64e3ee2",
+            profile,
         );
     }
 
@@ -141,7 +228,7 @@ mod tests {
     fn white_furnace_indirect() {
         test_scene(
             "tests/scenes/white_furnace_indirect/scene.isf",
-            "80dd0598ced75660b80170e69cad1a74fba26a15",
+            "bd81fba60d626956025eff86d9663382ad093e49",
         );
     }
 
@@ -159,8 +246,538 @@ mod tests {
 
         test_scene_with_profile(
             "tests/scenes/white_furnace_direct/scene.isf",
-            "6838e727798bd33f2f796be3edaa893445087159",
+            "c116ad14cea2ad2a879c8b7f94da0ada4c178965",
+            profile,
+        );
+    }
+
+    #[test]
+    fn white_furnace_subsurface() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/white_furnace_subsurface/scene.isf",
+            "d5d67bae6402220801b8e8eb40e2e9486461d42c",
+            profile,
+        );
+    }
+
+    #[test]
+    fn bdpt() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            integrator: IntegratorType::Bdpt,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/emissive/scene.isf",
+            "c8b5cb6ed330d622ce04406ec324bbbbc56fd7d2",
+            profile,
+        );
+    }
+
+    #[test]
+    fn pssmlt() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            mutations: 16,
+            integrator: IntegratorType::Pssmlt,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/glass/scene.isf",
+            "2a8b2d7fe8838adb2d2588d250a6209dd560d6a8",
+            profile,
+        );
+    }
+
+    #[test]
+    fn pppm() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            photons: 20_000,
+            integrator: IntegratorType::Pppm,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/glass/scene.isf",
+            "1baaf7bd9ed0f9b55928227b6145b7e9a7df8ea9",
+            profile,
+        );
+    }
+
+    #[test]
+    fn adaptive() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            adaptive: Some(AdaptiveSampling {
+                threshold: 0.05,
+                min_samples: 4,
+                max_samples: 64,
+            }),
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/spheres/scene.isf",
+            "d02a5650e7f9235a812852599c0f38fb0147b843",
+            profile,
+        );
+    }
+
+    #[test]
+    fn target_noise() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 64,
+            target_noise: Some(0.05),
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/spheres/scene.isf",
+            "137d5eaa46f6ad5c0f70ad5b067882104d7d60a3",
+            profile,
+        );
+    }
+
+    #[test]
+    fn resume() {
+        let path = "tests/scenes/spheres/scene.isf";
+        let checkpoint = std::env::temp_dir().join("path-tracer-resume-test.ckpt");
+        let profile = |samples| Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples,
+            ..Default::default()
+        };
+        let config = RenderConfig {
+            input: path.into(),
+            checkpoint: Some(checkpoint.clone()),
+            ..Default::default()
+        };
+        let scene = load_internal(path, Default::default()).unwrap();
+        Renderer::new(&config, profile(8))
+            .unwrap()
+            .render(&scene)
+            .unwrap();
+
+        let config = RenderConfig {
+            input: path.into(),
+            ..Default::default()
+        };
+        let mut renderer = Renderer::new(&config, profile(16)).unwrap();
+        renderer.resume(&checkpoint).unwrap();
+        let resumed = renderer.render(&scene).unwrap();
+        let uninterrupted = Renderer::new(&config, profile(16))
+            .unwrap()
+            .render(&scene)
+            .unwrap();
+        std::fs::remove_file(checkpoint).unwrap();
+        assert!(resumed == uninterrupted);
+    }
+
+    #[test]
+    fn tiles() {
+        let path = "tests/scenes/spheres/scene.isf";
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            ..Default::default()
+        };
+        let config = RenderConfig {
+            input: path.into(),
+            ..Default::default()
+        };
+        let scene = load_internal(path, Default::default()).unwrap();
+        let tiled = Renderer::new(
+            &config,
+            Profile {
+                tile_size: Some(32),
+                tile_order: TileOrder::Hilbert,
+                ..profile.clone()
+            },
+        )
+        .unwrap()
+        .render(&scene)
+        .unwrap();
+        let full_frame = Renderer::new(&config, profile)
+            .unwrap()
+            .render(&scene)
+            .unwrap();
+        assert!(tiled == full_frame);
+    }
+
+    #[test]
+    fn filter() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            filter: FilterType::Mitchell,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/spheres/scene.isf",
+            "fff2e09542657f56e8ca81fda08d3b8ddb0ecab6",
             profile,
         );
     }
+
+    #[test]
+    fn sobol() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            sampler: SamplerType::Sobol,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/spheres/scene.isf",
+            "fc29cd35a7be12b847168311a592e0b3639d0c89",
+            profile,
+        );
+    }
+
+    #[test]
+    fn denoiser() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            denoiser: Some(Denoiser::default()),
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/environment/scene.isf",
+            "4b67987b20e5b612ab1805af2c0a0d307cc1f6db",
+            profile,
+        );
+    }
+
+    #[test]
+    fn exposure() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            exposure: -0.5,
+            auto_exposure: Some(AutoExposure::default()),
+            white_balance: Some(WhiteBalance::Temperature {
+                temperature: 4500.,
+                tint: 5.,
+            }),
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/environment/scene.isf",
+            "b904ff0f211979fee43afb6ad13028eaefc390bd",
+            profile,
+        );
+    }
+
+    #[test]
+    fn bloom() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            bloom: Some(Bloom {
+                glare: Some(Glare::default()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/spheres/scene.isf",
+            "167e1de65da973e3d88106e53071e44fe933854f",
+            profile,
+        );
+    }
+
+    #[test]
+    fn agx() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            tonemap: TonemapType::Agx,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/spheres/scene.isf",
+            "82fbf38fa9fe77cd4917d7c384699dfdedad4636",
+            profile,
+        );
+    }
+
+    #[test]
+    fn lut() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            tonemap: TonemapType::Hable,
+            lut: Some("tests/luts/warm.cube".into()),
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/environment/scene.isf",
+            "977c420833adcbd7614fd7f458bcca6a7dda63e7",
+            profile,
+        );
+    }
+
+    #[test]
+    fn color_spaces() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            working_space: WorkingSpace::AcesCg,
+            display: DisplaySpace::DisplayP3,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/head/scene.isf",
+            "e9851839b97adde2ce3645cd633a0151235bd886",
+            profile,
+        );
+    }
+
+    #[test]
+    fn spectral() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 6,
+            samples: 16,
+            spectral: true,
+            ..Default::default()
+        };
+
+        test_scene_with_profile(
+            "tests/scenes/dispersion/scene.isf",
+            "f269a489fe692173641a7357977253068cbde743",
+            profile,
+        );
+    }
+
+    #[test]
+    fn passes() {
+        let path = "tests/scenes/environment/scene.isf";
+        let passes = std::env::temp_dir().join("path-tracer-passes-test.exr");
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            ..Default::default()
+        };
+        let config = RenderConfig {
+            input: path.into(),
+            passes: Some(passes.clone()),
+            ..Default::default()
+        };
+        let scene = load_internal(path, Default::default()).unwrap();
+        let with_passes = Renderer::new(
+            &config,
+            Profile {
+                passes: Pass::ALL.to_vec().into(),
+                ..profile.clone()
+            },
+        )
+        .unwrap()
+        .render(&scene)
+        .unwrap();
+        let without_passes = Renderer::new(&config, profile)
+            .unwrap()
+            .render(&scene)
+            .unwrap();
+        std::fs::remove_file(passes).unwrap();
+        assert!(with_passes == without_passes);
+    }
+
+    #[test]
+    fn geometry_passes() {
+        let path = "tests/scenes/plane/scene.isf";
+        let passes = std::env::temp_dir().join("path-tracer-geometry-passes-test.exr");
+        let profile = Profile {
+            resolution: Resolution {
+                width: 40,
+                height: 30,
+            },
+            samples: 4,
+            passes: Pass::ALL.to_vec().into(),
+            ..Default::default()
+        };
+        let config = RenderConfig {
+            input: path.into(),
+            passes: Some(passes.clone()),
+            ..Default::default()
+        };
+        let scene = load_internal(path, Default::default()).unwrap();
+        Renderer::new(&config, profile)
+            .unwrap()
+            .render(&scene)
+            .unwrap();
+        let layers = read_channels(&passes);
+        std::fs::remove_file(passes).unwrap();
+
+        // The camera looks down -Z at a plane covering the image, 2 meters away
+        let expected = [
+            ("depth.Z", 2.),
+            ("position.B", -2.),
+            ("normal.R", 0.),
+            ("normal.G", 0.),
+            ("normal.B", 1.),
+            ("albedo.R", 0.2),
+            ("albedo.G", 0.4),
+            ("albedo.B", 0.6),
+            ("object_id.Y", 1.),
+            ("material_id.Y", 1.),
+        ];
+        for (channel, value) in expected {
+            for &sample in &layers[channel] {
+                assert!(
+                    (sample - value).abs() < 1e-4,
+                    "{channel} is {sample} instead of {value}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hdr_output() {
+        let path = "tests/scenes/environment/scene.isf";
+        let output = std::env::temp_dir().join("path-tracer-hdr-test.exr");
+        let profile = Profile {
+            resolution: Resolution {
+                width: 200,
+                height: 150,
+            },
+            bounces: 4,
+            samples: 16,
+            ..Default::default()
+        };
+        let config = RenderConfig {
+            input: path.into(),
+            hdr_output: Some(output.clone()),
+            ..Default::default()
+        };
+        let scene = load_internal(path, Default::default()).unwrap();
+        Renderer::new(&config, profile)
+            .unwrap()
+            .render(&scene)
+            .unwrap();
+        let image = image::open(&output).unwrap().into_rgb32f();
+        std::fs::remove_file(output).unwrap();
+        let hash = Sha1::new().chain(image.as_bytes()).finalize();
+        assert_eq!(
+            format!("{:02x}", &hash),
+            "7c45e93d286e126ec106c5e1531092a80d2d4b96"
+        );
+    }
+
+    #[test]
+    fn hdr_output_error() {
+        let path = "tests/scenes/cube/scene.isf";
+        let profile = Profile {
+            resolution: Resolution {
+                width: 20,
+                height: 15,
+            },
+            samples: 1,
+            ..Default::default()
+        };
+        let config = RenderConfig {
+            input: path.into(),
+            quiet: true,
+            hdr_output: Some(std::env::temp_dir().join("path-tracer-missing/image.exr")),
+            ..Default::default()
+        };
+        let scene = load_internal(path, Default::default()).unwrap();
+        assert!(Renderer::new(&config, profile)
+            .unwrap()
+            .render(&scene)
+            .is_err());
+    }
 }
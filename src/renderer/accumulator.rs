@@ -0,0 +1,285 @@
+use super::checkpoint::{read_u32, read_u64};
+use crate::config::AdaptiveSampling;
+use cgmath::*;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::ops::AddAssign;
+
+/// Sample taken at a position of the film (in pixels), before it is splatted on the pixels
+#[derive(Clone)]
+pub struct FilmSample {
+    pub film_x: f32,
+    pub film_y: f32,
+    pub color: Vector3<f32>,
+    /// Values of the render passes (`None` for the passes the sample doesn't reach)
+    pub passes: Vec<Option<Vector3<f32>>>,
+}
+
+/// Samples splatted on a pixel, weighted by the reconstruction filter
+#[derive(Clone, Copy)]
+pub struct FilmPixel {
+    color: Vector3<f32>,
+    weight: f32,
+}
+
+impl Default for FilmPixel {
+    fn default() -> Self {
+        Self {
+            color: Vector3::zero(),
+            weight: 0.,
+        }
+    }
+}
+
+impl AddAssign for FilmPixel {
+    fn add_assign(&mut self, other: Self) {
+        self.color += other.color;
+        self.weight += other.weight;
+    }
+}
+
+impl FilmPixel {
+    /// Whether no sample reached the pixel
+    pub fn is_empty(&self) -> bool {
+        self.weight == 0.
+    }
+
+    pub fn splat(&mut self, color: Vector3<f32>, weight: f32) {
+        self.color += color * weight;
+        self.weight += weight;
+    }
+
+    /// Replace the splats by a value if its weight is the heaviest so far
+    pub fn keep_heaviest(&mut self, value: Vector3<f32>, weight: f32) {
+        if weight > self.weight {
+            self.color = value * weight;
+            self.weight = weight;
+        }
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Weighted average of the values splatted
+    pub fn average(&self) -> Vector3<f32> {
+        if self.weight <= 0. {
+            return Vector3::zero();
+        }
+        self.color / self.weight
+    }
+
+    /// Weighted average of the samples. Negative lobes can ring below zero around bright edges.
+    pub fn color(&self) -> Vector3<f32> {
+        let color = self.average();
+        Vector3::new(color.x.max(0.), color.y.max(0.), color.z.max(0.))
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for value in [self.color.x, self.color.y, self.color.z, self.weight] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut read_f32 = || read_u32(reader).map(f32::from_bits);
+        Ok(Self {
+            color: Vector3::new(read_f32()?, read_f32()?, read_f32()?),
+            weight: read_f32()?,
+        })
+    }
+}
+
+/// Filtered color of a pixel, with running statistics of the luminance of its own samples
+#[derive(Clone)]
+pub struct PixelAccumulator {
+    film: FilmPixel,
+    /// Render passes of the profile
+    passes: Vec<FilmPixel>,
+    count: usize,
+    /// Running mean of the luminance
+    mean: f32,
+    /// Sum of squared differences to the mean of the luminance (Welford's algorithm)
+    m2: f32,
+}
+
+impl Default for PixelAccumulator {
+    fn default() -> Self {
+        Self {
+            film: FilmPixel::default(),
+            passes: vec![],
+            count: 0,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+}
+
+impl PixelAccumulator {
+    /// Darkest luminance the relative error is computed against, so black pixels don't hold samples
+    const MIN_LUMINANCE: f32 = 0.01;
+
+    pub fn with_passes(passes: usize) -> Self {
+        Self {
+            passes: vec![FilmPixel::default(); passes],
+            ..Default::default()
+        }
+    }
+
+    /// Count a sample of the given luminance taken in the pixel. Its color reaches the film when
+    /// splatted.
+    pub fn add(&mut self, luminance: f32) {
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn film(&self) -> FilmPixel {
+        self.film
+    }
+
+    pub fn film_mut(&mut self) -> &mut FilmPixel {
+        &mut self.film
+    }
+
+    pub fn passes(&self) -> &[FilmPixel] {
+        &self.passes
+    }
+
+    pub fn passes_mut(&mut self) -> &mut [FilmPixel] {
+        &mut self.passes
+    }
+
+    /// Filtered color of the pixel
+    pub fn color(&self) -> Vector3<f32> {
+        self.film.color()
+    }
+
+    /// Variance of the mean luminance, unknown under two samples
+    pub fn variance(&self) -> Option<f32> {
+        if self.count < 2 {
+            return None;
+        }
+        Some(self.m2 / (self.count - 1) as f32 / self.count as f32)
+    }
+
+    /// Standard error of the mean luminance relative to the mean
+    pub fn relative_error(&self) -> f32 {
+        match self.variance() {
+            Some(variance) => variance.sqrt() / self.mean.max(Self::MIN_LUMINANCE),
+            None => f32::INFINITY,
+        }
+    }
+
+    /// Whether the pixel should receive more samples
+    pub fn needs_samples(&self, adaptive: &AdaptiveSampling) -> bool {
+        self.count < adaptive.min_samples
+            || (self.count < adaptive.max_samples && self.relative_error() > adaptive.threshold)
+    }
+
+    /// Write the raw state (little endian), so resumed renders continue bit for bit
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.film.write(writer)?;
+        writer.write_all(&(self.passes.len() as u32).to_le_bytes())?;
+        for pass in &self.passes {
+            pass.write(writer)?;
+        }
+        writer.write_all(&(self.count as u64).to_le_bytes())?;
+        writer.write_all(&self.mean.to_le_bytes())?;
+        writer.write_all(&self.m2.to_le_bytes())
+    }
+
+    /// Read the raw state of a pixel with this number of render passes
+    pub fn read<R: Read>(reader: &mut R, passes: usize) -> Result<Self> {
+        let film = FilmPixel::read(reader)?;
+        let pass_count = read_u32(reader)? as usize;
+        if pass_count != passes {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("The pixel has {pass_count} render passes instead of {passes}"),
+            ));
+        }
+        let passes = (0..pass_count)
+            .map(|_| FilmPixel::read(reader))
+            .collect::<Result<_>>()?;
+        let count = read_u64(reader)? as usize;
+        let mut read_f32 = || read_u32(reader).map(f32::from_bits);
+        Ok(Self {
+            film,
+            passes,
+            count,
+            mean: read_f32()?,
+            m2: read_f32()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accumulate(values: &[f32]) -> PixelAccumulator {
+        let mut pixel = PixelAccumulator::default();
+        for &value in values {
+            pixel.add(value);
+        }
+        pixel
+    }
+
+    #[test]
+    fn welford_statistics() {
+        let values = [0.5, 1.5, 0.25, 2., 0.75, 1.];
+        let pixel = accumulate(&values);
+        let n = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / n;
+        let sample_variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / (n - 1.);
+
+        assert_eq!(pixel.count(), values.len());
+        assert!((pixel.mean - mean).abs() < 1e-6);
+        // Variance of the mean
+        assert!((pixel.variance().unwrap() - sample_variance / n).abs() < 1e-6);
+        let relative_error = (sample_variance / n).sqrt() / mean;
+        assert!((pixel.relative_error() - relative_error).abs() < 1e-6);
+    }
+
+    #[test]
+    fn statistics_need_two_samples() {
+        assert_eq!(accumulate(&[]).variance(), None);
+        assert_eq!(accumulate(&[1.]).variance(), None);
+        assert_eq!(accumulate(&[1.]).relative_error(), f32::INFINITY);
+        assert_eq!(accumulate(&[1., 1.]).variance(), Some(0.));
+    }
+
+    #[test]
+    fn dark_pixels_converge() {
+        // The error of black pixels is relative to the minimum luminance instead of zero
+        let pixel = accumulate(&[0., 0., 0., 0.02]);
+        let expected = pixel.variance().unwrap().sqrt() / PixelAccumulator::MIN_LUMINANCE;
+        assert!((pixel.relative_error() - expected).abs() < 1e-6);
+        assert_eq!(accumulate(&[0., 0.]).relative_error(), 0.);
+    }
+
+    #[test]
+    fn stop_criterion() {
+        let adaptive = AdaptiveSampling {
+            threshold: 0.1,
+            min_samples: 4,
+            max_samples: 8,
+        };
+        // Under the minimum, even when converged
+        assert!(accumulate(&[1., 1., 1.]).needs_samples(&adaptive));
+        // Converged
+        assert!(!accumulate(&[1., 1., 1., 1.]).needs_samples(&adaptive));
+        // Noisy
+        let noisy = [0., 2., 0., 2.];
+        assert!(accumulate(&noisy).relative_error() > adaptive.threshold);
+        assert!(accumulate(&noisy).needs_samples(&adaptive));
+        // Noisy but at the maximum
+        assert!(!accumulate(&[noisy, noisy].concat()).needs_samples(&adaptive));
+    }
+}
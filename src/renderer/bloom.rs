@@ -0,0 +1,259 @@
+use super::color::WorkingSpace;
+use super::utils::luminance;
+use crate::config::{Bloom, Glare, Resolution};
+use cgmath::*;
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Number of box blurs approximating a Gaussian blur
+const BOX_PASSES: usize = 3;
+/// Samples of each pass of the streak filter, each pass reaching this many times farther
+const STREAK_TAPS: usize = 4;
+/// Fraction of the light left at the end of a streak
+const STREAK_FALLOFF: f32 = 0.01;
+
+/// Add the bloom and the glare of the light above the threshold to linear colors
+pub fn bloom(
+    settings: &Bloom,
+    resolution: Resolution,
+    working_space: WorkingSpace,
+    colors: &[Vector3<f32>],
+) -> Vec<Vector3<f32>> {
+    let width = resolution.width as f32;
+    // Part of the colors above the threshold, keeping their hue
+    let bright: Vec<_> = colors
+        .par_iter()
+        .map(|color| {
+            let luminance = luminance(color, working_space);
+            if luminance <= settings.threshold {
+                return Vector3::zero();
+            }
+            color * ((luminance - settings.threshold) / luminance)
+        })
+        .collect();
+
+    let mut colors = colors.to_vec();
+    if settings.scales > 0 {
+        let weight = settings.intensity / settings.scales as f32;
+        for scale in 0..settings.scales {
+            let sigma = settings.radius * width / 2f32.powi(scale as i32);
+            add(
+                &mut colors,
+                &gaussian_blur(resolution, &bright, sigma),
+                weight,
+            );
+        }
+    }
+    if let Some(glare) = &settings.glare {
+        if glare.blades > 0 {
+            let weight = glare.intensity / glare.blades as f32;
+            for blade in 0..glare.blades {
+                add(
+                    &mut colors,
+                    &streak(glare, resolution, &bright, blade),
+                    weight,
+                );
+            }
+        }
+    }
+    colors
+}
+
+fn add(colors: &mut [Vector3<f32>], other: &[Vector3<f32>], weight: f32) {
+    colors
+        .par_iter_mut()
+        .zip(other)
+        .for_each(|(color, other)| *color += other * weight);
+}
+
+/// Gaussian blur of standard deviation `sigma` (in pixels), approximated by successive box blurs
+fn gaussian_blur(resolution: Resolution, colors: &[Vector3<f32>], sigma: f32) -> Vec<Vector3<f32>> {
+    let width = resolution.width as usize;
+    let height = resolution.height as usize;
+    let radii = box_radii(sigma);
+    let mut colors = colors.to_vec();
+    for radius in radii {
+        colors = box_blur_rows(&colors, width, radius);
+    }
+    let mut colors = transpose(&colors, width, height);
+    for radius in radii {
+        colors = box_blur_rows(&colors, height, radius);
+    }
+    transpose(&colors, height, width)
+}
+
+/// Radii of the box blurs whose succession has the variance of a Gaussian (Kutskir)
+fn box_radii(sigma: f32) -> [usize; BOX_PASSES] {
+    let passes = BOX_PASSES as f32;
+    let variance = 12. * sigma * sigma;
+    let mut lower_width = (variance / passes + 1.).sqrt().floor() as usize;
+    if lower_width.is_multiple_of(2) {
+        lower_width -= 1;
+    }
+    let lower = lower_width as f32;
+    // Number of boxes of the lower width, the others being 2 pixels wider
+    let lower_count = ((variance - passes * lower * lower - 4. * passes * lower - 3. * passes)
+        / (-4. * lower - 4.))
+        .round()
+        .max(0.) as usize;
+    let mut radii = [0; BOX_PASSES];
+    for (i, radius) in radii.iter_mut().enumerate() {
+        let box_width = if i < lower_count {
+            lower_width
+        } else {
+            lower_width + 2
+        };
+        *radius = (box_width - 1) / 2;
+    }
+    radii
+}
+
+/// Average of the colors within `radius` pixels on each row, the pixels outside being black
+fn box_blur_rows(colors: &[Vector3<f32>], width: usize, radius: usize) -> Vec<Vector3<f32>> {
+    let scale = 1. / (2 * radius + 1) as f32;
+    let mut blurred = vec![Vector3::zero(); colors.len()];
+    blurred
+        .par_chunks_mut(width)
+        .zip(colors.par_chunks(width))
+        .for_each(|(blurred, row)| {
+            // Running sum over the window [x - radius, x + radius]
+            let mut sum: Vector3<f32> = row[..radius.min(width)].iter().sum();
+            for x in 0..width {
+                if x + radius < width {
+                    sum += row[x + radius];
+                }
+                blurred[x] = sum * scale;
+                if x >= radius {
+                    sum -= row[x - radius];
+                }
+            }
+        });
+    blurred
+}
+
+fn transpose(colors: &[Vector3<f32>], width: usize, height: usize) -> Vec<Vector3<f32>> {
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| colors[i / height + (i % height) * width])
+        .collect()
+}
+
+/// Light dragged along a blade of the star, with an exponential falloff. Each pass of the
+/// filter (Kawase) sums a few samples, reaching farther than the previous one.
+fn streak(
+    glare: &Glare,
+    resolution: Resolution,
+    colors: &[Vector3<f32>],
+    blade: usize,
+) -> Vec<Vector3<f32>> {
+    let width = resolution.width as usize;
+    let height = resolution.height as usize;
+    let angle = glare.angle.to_radians() + 2. * PI * blade as f32 / glare.blades as f32;
+    let direction = Vector2::new(angle.cos(), -angle.sin());
+    let length = (glare.length * width as f32).max(1.);
+    let decay = STREAK_FALLOFF.powf(1. / length);
+    let passes = length.log(STREAK_TAPS as f32).ceil().max(1.) as u32;
+
+    let mut colors = colors.to_vec();
+    for pass in 0..passes {
+        let step = STREAK_TAPS.pow(pass) as f32;
+        let weights: Vec<_> = (0..STREAK_TAPS)
+            .map(|tap| decay.powf(tap as f32 * step))
+            .collect();
+        let total_weight: f32 = weights.iter().sum();
+        colors = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let position = Vector2::new((i % width) as f32, (i / width) as f32);
+                let sum: Vector3<f32> = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, weight)| {
+                        let offset = direction * (tap as f32 * step);
+                        sample_bilinear(&colors, width, height, position - offset) * *weight
+                    })
+                    .sum();
+                sum / total_weight
+            })
+            .collect();
+    }
+    colors
+}
+
+/// Color at a position (in pixels) between pixel centers, the pixels outside being black
+fn sample_bilinear(
+    colors: &[Vector3<f32>],
+    width: usize,
+    height: usize,
+    position: Vector2<f32>,
+) -> Vector3<f32> {
+    let (x0, y0) = (position.x.floor(), position.y.floor());
+    let (fx, fy) = (position.x - x0, position.y - y0);
+    let pixel = |x: f32, y: f32| {
+        if x < 0. || y < 0. || x >= width as f32 || y >= height as f32 {
+            return Vector3::zero();
+        }
+        colors[x as usize + y as usize * width]
+    };
+    let top = pixel(x0, y0) * (1. - fx) + pixel(x0 + 1., y0) * fx;
+    let bottom = pixel(x0, y0 + 1.) * (1. - fx) + pixel(x0 + 1., y0 + 1.) * fx;
+    top * (1. - fy) + bottom * fy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTION: Resolution = Resolution {
+        width: 64,
+        height: 64,
+    };
+
+    fn total(colors: &[Vector3<f32>]) -> Vector3<f32> {
+        colors.iter().sum()
+    }
+
+    #[test]
+    fn below_threshold() {
+        let settings = Bloom {
+            glare: Some(Glare::default()),
+            ..Default::default()
+        };
+        let colors: Vec<_> = (0..RESOLUTION.width * RESOLUTION.height)
+            .map(|i| Vector3::new(0.9, 0.5, 0.1) * (i % 7) as f32 / 6.)
+            .collect();
+        let bloomed = bloom(&settings, RESOLUTION, WorkingSpace::Rec709, &colors);
+        assert_eq!(bloomed, colors);
+    }
+
+    #[test]
+    fn energy() {
+        // A bright pixel at the center of a black image spreads the light above the threshold around it
+        let mut colors = vec![Vector3::zero(); (RESOLUTION.width * RESOLUTION.height) as usize];
+        let center = (RESOLUTION.width / 2 + RESOLUTION.height / 2 * RESOLUTION.width) as usize;
+        colors[center] = Vector3::new(8., 4., 2.);
+        let luminance = luminance(&colors[center], WorkingSpace::Rec709);
+        let bright = colors[center] * ((luminance - 1.) / luminance);
+
+        let mut settings = Bloom::default();
+        let bloomed = bloom(&settings, RESOLUTION, WorkingSpace::Rec709, &colors);
+        let added = total(&bloomed) - total(&colors);
+        assert!(
+            (added - bright * settings.intensity).magnitude() < 1e-3,
+            "{added:?}"
+        );
+        // The bright pixel keeps its own color
+        assert!(bloomed[center].x > colors[center].x);
+
+        // The glare adds its own part of the light
+        let glare = Glare::default();
+        settings.glare = Some(glare);
+        let bloomed = bloom(&settings, RESOLUTION, WorkingSpace::Rec709, &colors);
+        let added = total(&bloomed) - total(&colors);
+        let expected = bright * (settings.intensity + glare.intensity);
+        assert!(
+            (added - expected).magnitude() < 1e-3 * expected.magnitude(),
+            "{added:?}"
+        );
+    }
+}
@@ -1,19 +1,21 @@
-use super::transform_to_world;
+use super::sample_ggx_normal;
 use crate::renderer::brdf::Brdf;
-use crate::renderer::utils::reflection;
+use crate::renderer::color::WorkingSpace;
+use crate::renderer::sampler::Sampler;
+use crate::renderer::utils::{cosine_hemisphere, luminance, reflection};
 use crate::renderer::MaterialSample;
 use cgmath::*;
-use rand::rngs::StdRng;
-use rand::Rng;
 use std::f32::consts::PI;
 
 pub struct CookTorrance {
     metalness: f32,
     roughness: f32,
     albedo: Vector3<f32>,
-    emissive: Vector3<f32>,
     f0: Vector3<f32>, // surface reflection at zero incidence from Fresnel-Schlick approximation
     microfacet_normal: Vector3<f32>, // a.k.a. Wm
+    specular_probability: f32, // probability of sampling the specular lobe for the last sample
+    diffuse_sampled: bool, // whether the last sample comes from the diffuse lobe
+    working_space: WorkingSpace, // space of the colors, weighting the lobes by luminance
 }
 
 impl Brdf for CookTorrance {
@@ -21,10 +23,20 @@ impl Brdf for CookTorrance {
         &mut self,
         geometric_normal: Vector3<f32>,
         v: Vector3<f32>,
-        rand_gen: &mut StdRng,
+        sampler: &mut dyn Sampler,
     ) -> Vector3<f32> {
+        // Choose the lobe to sample proportionally to its expected contribution
+        self.specular_probability = self.specular_probability(geometric_normal, v);
+        self.diffuse_sampled = sampler.next_1d() >= self.specular_probability;
+
+        if self.diffuse_sampled {
+            let sample_dir = cosine_hemisphere(geometric_normal, sampler);
+            self.microfacet_normal = (v + sample_dir).normalize();
+            return sample_dir;
+        }
+
         // Compute a new random microfacet normal
-        self.compute_microfacet_normal(geometric_normal, rand_gen);
+        self.compute_microfacet_normal(geometric_normal, sampler);
 
         // Compute direction by reflecting v about the microfacet normal
         let sample_dir = reflection(&v, &self.microfacet_normal);
@@ -42,6 +54,13 @@ impl Brdf for CookTorrance {
         let f = self.fresnel_schlick(halfway.dot(view_direction).max(0.));
         let g = self.geometry_smith(geometric_normal, view_direction, light_direction);
 
+        // Diffuse
+        let diffuse = self.compute_diffuse(f, geometric_normal, light_direction);
+        if self.is_smooth() {
+            // Delta distribution can't be reached by light sampling
+            return diffuse;
+        }
+
         // Specular
         let specular = (d * f * g)
             / (4.
@@ -51,10 +70,7 @@ impl Brdf for CookTorrance {
         let cosine_term = geometric_normal.dot(light_direction).max(0.);
         let specular = specular * cosine_term;
 
-        // Diffuse
-        let diffuse = self.compute_diffuse(f, geometric_normal, light_direction);
-
-        diffuse + specular + self.emissive
+        diffuse + specular
     }
 
     fn eval_indirect(
@@ -67,40 +83,108 @@ impl Brdf for CookTorrance {
         let f = self.fresnel_schlick(halfway.dot(view_direction).max(0.));
         let g = self.geometry_smith(geometric_normal, view_direction, light_direction);
 
-        // Specular
-        let specular = if geometric_normal.dot(light_direction) > 0. {
-            let weight_num = view_direction.dot(self.microfacet_normal).abs();
-            let weight_denom = view_direction.dot(geometric_normal).abs()
-                * self.microfacet_normal.dot(geometric_normal).abs();
-            let weight = weight_num / weight_denom;
-            f * g * weight // NDF and cosine factor are canceled by PDF
-        } else {
+        if geometric_normal.dot(light_direction) <= 0. {
             // If our sample is not in the upper hemisphere
-            Zero::zero()
-        };
+            return Zero::zero();
+        }
 
-        // Diffuse
-        let diffuse = self.compute_diffuse(f, geometric_normal, light_direction);
+        if self.diffuse_sampled {
+            // Cosine and 1/π terms are canceled by the PDF
+            let kd = Vector3::new(1. - f.x, 1. - f.y, 1. - f.z) * (1. - self.metalness);
+            return kd.mul_element_wise(self.albedo) / (1. - self.specular_probability);
+        }
 
-        diffuse + specular
+        // Specular
+        let weight_num = view_direction.dot(self.microfacet_normal).abs();
+        let weight_denom = view_direction.dot(geometric_normal).abs()
+            * self.microfacet_normal.dot(geometric_normal).abs();
+        let weight = weight_num / weight_denom.max(0.0001);
+        f * g * weight / self.specular_probability // NDF and cosine factor are canceled by PDF
     }
 
     fn pdf(&self) -> f32 {
         // We simplify the PDF by canceling the NDF term in the BRDF
         1.
     }
+
+    fn sampling_pdf(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> f32 {
+        if geometric_normal.dot(light_direction) <= 0. {
+            return 0.;
+        }
+        let specular_pdf = if self.is_smooth() {
+            // Delta distribution can't be reached by light sampling
+            0.
+        } else {
+            // Density of the microfacet normal converted to the reflected direction
+            let halfway = (view_direction + light_direction).normalize();
+            let d = self.distribution_ggx(geometric_normal, halfway);
+            let n_dot_h = geometric_normal.dot(halfway).max(0.);
+            d * n_dot_h / (4. * view_direction.dot(halfway).abs()).max(0.0001)
+        };
+        let diffuse_pdf = geometric_normal.dot(light_direction) / PI;
+
+        let specular_probability = self.specular_probability(geometric_normal, view_direction);
+        specular_probability * specular_pdf + (1. - specular_probability) * diffuse_pdf
+    }
+
+    fn is_delta_sample(&self) -> bool {
+        !self.diffuse_sampled && self.is_smooth()
+    }
+
+    fn eval_direct_diffuse(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        let halfway = (view_direction + light_direction).normalize();
+        let f = self.fresnel_schlick(halfway.dot(view_direction).max(0.));
+        self.compute_diffuse(f, geometric_normal, light_direction)
+    }
+
+    fn is_diffuse_sample(&self) -> bool {
+        self.diffuse_sampled
+    }
 }
 
 impl CookTorrance {
+    /// Roughness below which the specular lobe is considered perfectly smooth
+    const SMOOTH_ROUGHNESS: f32 = 0.001;
+
     pub fn new(material: &MaterialSample) -> Self {
         Self {
             metalness: material.metalness,
             roughness: material.roughness,
             albedo: material.albedo,
-            emissive: material.emissive,
             f0: Self::compute_f0(material.metalness, material.albedo),
             microfacet_normal: Zero::zero(),
+            specular_probability: 1.,
+            diffuse_sampled: false,
+            working_space: material.working_space,
+        }
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.roughness <= Self::SMOOTH_ROUGHNESS
+    }
+
+    // Probability of sampling the specular lobe, based on the Fresnel ratio seen from the viewer
+    fn specular_probability(&self, geometric_normal: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        let f = self.fresnel_schlick(geometric_normal.dot(v).max(0.));
+        let specular = luminance(&f, self.working_space);
+        let diffuse = luminance(
+            &(Vector3::new(1., 1., 1.) - f).mul_element_wise(self.albedo),
+            self.working_space,
+        ) * (1. - self.metalness);
+        if specular + diffuse <= 0. {
+            return 1.;
         }
+        specular / (specular + diffuse)
     }
 
     // Lambertian diffuse
@@ -116,28 +200,12 @@ impl CookTorrance {
         diffuse * cosine_term
     }
 
-    fn compute_microfacet_normal(&mut self, geometric_normal: Vector3<f32>, rand_gen: &mut StdRng) {
-        let a = self.roughness * self.roughness;
-        let a2 = a * a;
-        // Generate uniform random variables between 0 and 1
-        let r1: f32 = rand_gen.gen();
-        let r2: f32 = rand_gen.gen();
-
-        // Compute spherical coordinates of the normal
-        // Theta depends on the roughness according to the NDF (due to importance sampling on microfacet model)
-        let theta = (((1. - r1) / (r1 * (a2 - 1.) + 1.)).sqrt()).acos();
-        // Phi can be sampled uniformly because the NDF is isotropic
-        let phi = 2. * PI * r2;
-
-        // Convert to cartesian coordinates
-        let sin_theta = theta.sin();
-        let x = sin_theta * phi.cos();
-        let y = theta.cos();
-        let z = sin_theta * phi.sin();
-        let microfacet_normal = Vector3::new(x, y, z).normalize();
-
-        self.microfacet_normal =
-            transform_to_world(microfacet_normal, geometric_normal).normalize();
+    fn compute_microfacet_normal(
+        &mut self,
+        geometric_normal: Vector3<f32>,
+        sampler: &mut dyn Sampler,
+    ) {
+        self.microfacet_normal = sample_ggx_normal(self.roughness, geometric_normal, sampler);
     }
 
     fn fresnel_schlick(&self, cos_theta: f32) -> Vector3<f32> {
@@ -0,0 +1,366 @@
+use super::sample_ggx_normal;
+use crate::renderer::brdf::Brdf;
+use crate::renderer::sampler::Sampler;
+use crate::renderer::MaterialSample;
+use cgmath::*;
+use std::f32::consts::PI;
+
+/// Transmissive dielectric interface (glass, water...) handling Fresnel weighted
+/// reflection and refraction. Rough surfaces use GGX microfacets ("frosted" glass).
+#[derive(Clone, Copy)]
+pub struct Dielectric {
+    roughness: f32,
+    /// Index of refraction on the side of the normal
+    eta_i: f32,
+    /// Index of refraction on the other side
+    eta_t: f32,
+    /// Color filter applied to the transmitted light
+    tint: Vector3<f32>,
+    /// Weight of the last sampled direction
+    sample_weight: Vector3<f32>,
+}
+
+impl Brdf for Dielectric {
+    fn sample(
+        &mut self,
+        geometric_normal: Vector3<f32>,
+        v: Vector3<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        let this = self.seen_from(geometric_normal, v);
+        let normal = Self::facing(geometric_normal, v);
+        let microfacet_normal = if self.is_smooth() {
+            normal
+        } else {
+            sample_ggx_normal(self.roughness, normal, sampler)
+        };
+
+        let cos_i = v.dot(microfacet_normal);
+        let fresnel = this.fresnel(cos_i);
+
+        // Choose between reflection and refraction according to Fresnel
+        let (direction, tint, is_refraction) = match this.refract(v, microfacet_normal) {
+            Some(refracted) if sampler.next_1d() >= fresnel => (refracted, self.tint, true),
+            _ => (
+                2. * cos_i * microfacet_normal - v,
+                Vector3::new(1., 1., 1.),
+                false,
+            ),
+        };
+
+        // Discard directions ending on the wrong side of the surface
+        let is_below = direction.dot(normal) < 0.;
+        if cos_i <= 0. || is_below != is_refraction {
+            self.sample_weight = Zero::zero();
+            return direction.normalize();
+        }
+
+        self.sample_weight = if self.is_smooth() {
+            tint
+        } else {
+            // NDF and Fresnel terms are canceled by the PDF
+            let weight = this.geometry_smith(normal, v, direction) * cos_i.abs()
+                / (v.dot(normal).abs() * microfacet_normal.dot(normal).abs()).max(0.0001);
+            tint * weight
+        };
+        direction.normalize()
+    }
+
+    fn eval_direct(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        if self.is_smooth() {
+            // Delta distribution can't be reached by light sampling
+            return Zero::zero();
+        }
+        let this = self.seen_from(geometric_normal, view_direction);
+        let normal = Self::facing(geometric_normal, view_direction);
+        let n_dot_v = normal.dot(view_direction).abs().max(0.0001);
+        let n_dot_l = normal.dot(light_direction);
+
+        if n_dot_l > 0. {
+            // Reflection
+            let halfway = (view_direction + light_direction).normalize();
+            let d = this.distribution_ggx(normal, halfway);
+            let f = this.fresnel(view_direction.dot(halfway));
+            let g = this.geometry_smith(normal, view_direction, light_direction);
+            Vector3::new(1., 1., 1.) * (d * f * g / (4. * n_dot_v))
+        } else {
+            // Refraction
+            let Some(halfway) = this.refraction_halfway(normal, view_direction, light_direction)
+            else {
+                return Zero::zero();
+            };
+            let v_dot_h = view_direction.dot(halfway);
+            let l_dot_h = light_direction.dot(halfway);
+            let d = this.distribution_ggx(normal, halfway);
+            let f = this.fresnel(v_dot_h);
+            let g = this.geometry_smith(normal, view_direction, light_direction);
+            let denom = this.eta_i * v_dot_h + this.eta_t * l_dot_h;
+            let value = v_dot_h.abs() * l_dot_h.abs() * this.eta_t * this.eta_t * (1. - f) * g * d
+                / (n_dot_v * denom * denom).max(0.0001);
+            self.tint * value
+        }
+    }
+
+    fn eval_indirect(
+        &self,
+        _geometric_normal: Vector3<f32>,
+        _view_direction: Vector3<f32>,
+        _light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        // The weight is computed while sampling since it depends on the chosen event
+        self.sample_weight
+    }
+
+    fn pdf(&self) -> f32 {
+        // NDF and Fresnel terms are already canceled in the sample weight
+        1.
+    }
+
+    fn sampling_pdf(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> f32 {
+        if self.is_smooth() {
+            return f32::INFINITY;
+        }
+        let this = self.seen_from(geometric_normal, view_direction);
+        let normal = Self::facing(geometric_normal, view_direction);
+
+        if normal.dot(light_direction) > 0. {
+            let halfway = (view_direction + light_direction).normalize();
+            let v_dot_h = view_direction.dot(halfway);
+            let d = this.distribution_ggx(normal, halfway) * normal.dot(halfway).abs();
+            this.fresnel(v_dot_h) * d / (4. * v_dot_h.abs()).max(0.0001)
+        } else {
+            let Some(halfway) = this.refraction_halfway(normal, view_direction, light_direction)
+            else {
+                return 0.;
+            };
+            let v_dot_h = view_direction.dot(halfway);
+            let l_dot_h = light_direction.dot(halfway);
+            let d = this.distribution_ggx(normal, halfway) * normal.dot(halfway).abs();
+            let denom = this.eta_i * v_dot_h + this.eta_t * l_dot_h;
+            (1. - this.fresnel(v_dot_h)) * d * this.eta_t * this.eta_t * l_dot_h.abs()
+                / (denom * denom).max(0.0001)
+        }
+    }
+
+    fn is_delta_sample(&self) -> bool {
+        self.is_smooth()
+    }
+}
+
+impl Dielectric {
+    /// Roughness below which the interface is considered perfectly smooth
+    const SMOOTH_ROUGHNESS: f32 = 0.001;
+
+    pub fn new(material: &MaterialSample, tint: Vector3<f32>, is_backface: bool) -> Self {
+        // Backface hits are exiting the medium
+        let (eta_i, eta_t) = if is_backface {
+            (material.ior, 1.)
+        } else {
+            (1., material.ior)
+        };
+        Self {
+            roughness: material.roughness,
+            eta_i,
+            eta_t,
+            tint,
+            sample_weight: Zero::zero(),
+        }
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.roughness <= Self::SMOOTH_ROUGHNESS
+    }
+
+    /// The interface seen from the side of `v`: indices of refraction are swapped
+    /// when `v` is on the other side of the normal (e.g. for light subpaths)
+    fn seen_from(&self, normal: Vector3<f32>, v: Vector3<f32>) -> Self {
+        if normal.dot(v) < 0. {
+            Self {
+                eta_i: self.eta_t,
+                eta_t: self.eta_i,
+                ..*self
+            }
+        } else {
+            *self
+        }
+    }
+
+    // Make sure the normal is on the same side as the viewer
+    fn facing(normal: Vector3<f32>, v: Vector3<f32>) -> Vector3<f32> {
+        if normal.dot(v) < 0. {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    fn fresnel(&self, cos_i: f32) -> f32 {
+        fresnel(cos_i, self.eta_i, self.eta_t)
+    }
+
+    /// Refract `v` (pointing away from the surface) about `n`, None on total internal reflection
+    fn refract(&self, v: Vector3<f32>, n: Vector3<f32>) -> Option<Vector3<f32>> {
+        let eta = self.eta_i / self.eta_t;
+        let cos_i = v.dot(n);
+        let sin2_t = eta * eta * (1. - cos_i * cos_i).max(0.);
+        if sin2_t >= 1. {
+            return None;
+        }
+        let cos_t = (1. - sin2_t).sqrt();
+        Some(-eta * v + (eta * cos_i - cos_t) * n)
+    }
+
+    /// Microfacet normal producing a refraction from `v` to `l`, oriented like `normal`
+    fn refraction_halfway(
+        &self,
+        normal: Vector3<f32>,
+        v: Vector3<f32>,
+        l: Vector3<f32>,
+    ) -> Option<Vector3<f32>> {
+        let halfway = -(self.eta_i * v + self.eta_t * l);
+        if halfway.magnitude2() == 0. {
+            return None;
+        }
+        let halfway = Self::facing(halfway.normalize(), normal);
+        // The microfacet must be seen from both sides
+        if v.dot(halfway) <= 0. || l.dot(halfway) >= 0. {
+            return None;
+        }
+        Some(halfway)
+    }
+
+    fn distribution_ggx(&self, n: Vector3<f32>, h: Vector3<f32>) -> f32 {
+        let a = self.roughness * self.roughness;
+        let a2 = a * a;
+        let n_dot_h = n.dot(h).max(0.);
+        let n_dot_h_2 = n_dot_h * n_dot_h;
+
+        let mut denom = n_dot_h_2 * (a2 - 1.) + 1.;
+        denom = PI * denom * denom;
+
+        a2 / denom
+    }
+
+    // Smith masking term for GGX, both directions may lie on any side of the surface
+    fn geometry_smith(&self, n: Vector3<f32>, v: Vector3<f32>, l: Vector3<f32>) -> f32 {
+        let a = self.roughness * self.roughness;
+        let a2 = a * a;
+        let g1 = |cos: f32| {
+            let cos = cos.abs();
+            2. * cos / (cos + (a2 + (1. - a2) * cos * cos).sqrt())
+        };
+        g1(n.dot(v)) * g1(n.dot(l))
+    }
+}
+
+/// Exact Fresnel reflectance of a dielectric interface, for light coming from the side of the
+/// index of refraction `eta_i`
+pub fn fresnel(cos_i: f32, eta_i: f32, eta_t: f32) -> f32 {
+    let cos_i = cos_i.clamp(-1., 1.).abs();
+    let eta = eta_i / eta_t;
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t >= 1. {
+        // Total internal reflection
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let r_perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::sampler::IndependentSampler;
+
+    /// Smooth glass seen from outside, or from inside on backface hits
+    fn glass(is_backface: bool) -> Dielectric {
+        let material = MaterialSample {
+            roughness: 0.0001,
+            ior: 1.5,
+            ..Default::default()
+        };
+        Dielectric::new(&material, Vector3::new(1., 1., 1.), is_backface)
+    }
+
+    /// Direction making an angle (in degrees) with the normal (0, 1, 0)
+    fn direction(angle: f32) -> Vector3<f32> {
+        let angle = angle.to_radians();
+        Vector3::new(angle.sin(), angle.cos(), 0.)
+    }
+
+    #[test]
+    fn normal_incidence() {
+        let reflectance = ((1.5f32 - 1.) / (1.5 + 1.)).powi(2);
+        assert!((glass(false).fresnel(1.) - reflectance).abs() < 1e-6);
+        assert!((glass(true).fresnel(1.) - reflectance).abs() < 1e-6);
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        let normal = Vector3::new(0., 1., 0.);
+        let critical = (1f32 / 1.5).asin().to_degrees();
+        let mut dielectric = glass(true);
+
+        // Below the critical angle, light leaving the glass is partly refracted
+        assert!(dielectric.fresnel(direction(critical - 1.).y) < 1.);
+        assert!(dielectric
+            .refract(direction(critical - 1.), normal)
+            .is_some());
+
+        // Beyond it, every sample is reflected
+        let v = direction(critical + 1.);
+        assert_eq!(dielectric.fresnel(v.y), 1.);
+        assert!(dielectric.refract(v, normal).is_none());
+        let mut sampler = IndependentSampler::new(0);
+        for _ in 0..100 {
+            let l = dielectric.sample(normal, v, &mut sampler);
+            assert!(l.dot(normal) > 0.);
+            assert!((l - Vector3::new(-v.x, v.y, v.z)).magnitude() < 1e-5);
+            assert_eq!(
+                dielectric.eval_indirect(normal, v, l),
+                Vector3::new(1., 1., 1.)
+            );
+        }
+    }
+
+    #[test]
+    fn energy_conservation() {
+        // The smooth interface reflects the Fresnel part of the light and refracts the rest
+        let normal = Vector3::new(0., 1., 0.);
+        let mut sampler = IndependentSampler::new(0);
+        for is_backface in [false, true] {
+            let mut dielectric = glass(is_backface);
+            for angle in [0., 30., 60., 80.] {
+                let v = direction(angle);
+                let samples = 100_000;
+                let (mut reflected, mut refracted) = (0., 0.);
+                for _ in 0..samples {
+                    let l = dielectric.sample(normal, v, &mut sampler);
+                    let weight = dielectric.eval_indirect(normal, v, l).x / dielectric.pdf();
+                    if l.dot(normal) > 0. {
+                        reflected += weight;
+                    } else {
+                        refracted += weight;
+                    }
+                }
+                let (reflected, refracted) =
+                    (reflected / samples as f32, refracted / samples as f32);
+                let fresnel = dielectric.fresnel(v.y);
+                assert!((reflected + refracted - 1.).abs() < 1e-4);
+                assert!((reflected - fresnel).abs() < 0.01, "{reflected} {fresnel}");
+            }
+        }
+    }
+}
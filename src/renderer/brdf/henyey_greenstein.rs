@@ -0,0 +1,100 @@
+use super::transform_to_world;
+use crate::renderer::brdf::Brdf;
+use crate::renderer::sampler::Sampler;
+use cgmath::*;
+use std::f32::consts::PI;
+
+/// Henyey-Greenstein phase function of a participating medium.
+/// Media have no surface so the normal given to the brdf methods is ignored.
+pub struct HenyeyGreenstein {
+    /// Asymmetry parameter (positive values scatter forward)
+    g: f32,
+}
+
+impl Brdf for HenyeyGreenstein {
+    fn sample(
+        &mut self,
+        _geometric_normal: Vector3<f32>,
+        v: Vector3<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        let (r1, r2) = sampler.next_2d();
+
+        // Cosine of the angle between the propagation direction and the scattered one
+        let cos_theta = if self.g.abs() < 0.001 {
+            1. - 2. * r1
+        } else {
+            let g2 = self.g * self.g;
+            let sqr = (1. - g2) / (1. + self.g - 2. * self.g * r1);
+            ((1. + g2 - sqr * sqr) / (2. * self.g)).clamp(-1., 1.)
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * r2;
+        let direction = Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+        transform_to_world(direction, -v).normalize()
+    }
+
+    fn eval_direct(
+        &self,
+        _geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        Vector3::new(1., 1., 1.) * self.phase(view_direction, light_direction)
+    }
+
+    fn eval_indirect(
+        &self,
+        _geometric_normal: Vector3<f32>,
+        _view_direction: Vector3<f32>,
+        _light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        // The phase function is perfectly importance sampled
+        Vector3::new(1., 1., 1.)
+    }
+
+    fn pdf(&self) -> f32 {
+        1.
+    }
+
+    fn sampling_pdf(
+        &self,
+        _geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> f32 {
+        self.phase(view_direction, light_direction)
+    }
+
+    fn is_delta_sample(&self) -> bool {
+        false
+    }
+
+    // Scattering in media counts as diffuse in the render passes
+    fn eval_direct_diffuse(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        self.eval_direct(geometric_normal, view_direction, light_direction)
+    }
+
+    fn is_diffuse_sample(&self) -> bool {
+        true
+    }
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f32) -> Self {
+        Self { g }
+    }
+
+    fn phase(&self, view_direction: Vector3<f32>, light_direction: Vector3<f32>) -> f32 {
+        // The light travels from `light_direction` towards `view_direction`
+        let cos_theta = (-light_direction).dot(view_direction);
+        let g2 = self.g * self.g;
+        let denom = 1. + g2 - 2. * self.g * cos_theta;
+        (1. - g2) / (4. * PI * denom * denom.sqrt())
+    }
+}
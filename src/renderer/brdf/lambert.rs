@@ -0,0 +1,89 @@
+use super::transform_to_world;
+use crate::renderer::brdf::Brdf;
+use crate::renderer::sampler::Sampler;
+use cgmath::*;
+use std::f32::consts::PI;
+
+/// Perfectly diffuse surface (also used for the light leaving subsurface media)
+pub struct Lambert {
+    albedo: Vector3<f32>,
+}
+
+impl Brdf for Lambert {
+    fn sample(
+        &mut self,
+        geometric_normal: Vector3<f32>,
+        _v: Vector3<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        // Cosine weighted hemisphere sampling
+        let (r1, r2) = sampler.next_2d();
+        let sin_theta = r1.sqrt();
+        let phi = 2. * PI * r2;
+        let direction = Vector3::new(
+            sin_theta * phi.cos(),
+            (1. - r1).max(0.).sqrt(),
+            sin_theta * phi.sin(),
+        );
+        transform_to_world(direction, geometric_normal).normalize()
+    }
+
+    fn eval_direct(
+        &self,
+        geometric_normal: Vector3<f32>,
+        _view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        self.albedo / PI * geometric_normal.dot(light_direction).max(0.)
+    }
+
+    fn eval_indirect(
+        &self,
+        geometric_normal: Vector3<f32>,
+        _view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        if geometric_normal.dot(light_direction) > 0. {
+            // Cosine and 1/π terms are canceled by the PDF
+            self.albedo
+        } else {
+            Zero::zero()
+        }
+    }
+
+    fn pdf(&self) -> f32 {
+        1.
+    }
+
+    fn sampling_pdf(
+        &self,
+        geometric_normal: Vector3<f32>,
+        _view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> f32 {
+        geometric_normal.dot(light_direction).max(0.) / PI
+    }
+
+    fn is_delta_sample(&self) -> bool {
+        false
+    }
+
+    fn eval_direct_diffuse(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        self.eval_direct(geometric_normal, view_direction, light_direction)
+    }
+
+    fn is_diffuse_sample(&self) -> bool {
+        true
+    }
+}
+
+impl Lambert {
+    pub fn new(albedo: Vector3<f32>) -> Self {
+        Self { albedo }
+    }
+}
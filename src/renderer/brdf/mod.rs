@@ -1,17 +1,24 @@
 mod cook_torrance;
+mod dielectric;
+mod henyey_greenstein;
+mod lambert;
 
+use super::sampler::Sampler;
 use super::MaterialSample;
 use cgmath::*;
 pub use cook_torrance::CookTorrance;
-use rand::rngs::StdRng;
+pub use dielectric::{fresnel, Dielectric};
+pub use henyey_greenstein::HenyeyGreenstein;
+pub use lambert::Lambert;
 use serde::Deserialize;
+use std::f32::consts::PI;
 
 pub trait Brdf {
     fn sample(
         &mut self,
         geometric_normal: Vector3<f32>,
         v: Vector3<f32>,
-        rand_gen: &mut StdRng,
+        sampler: &mut dyn Sampler,
     ) -> Vector3<f32>;
 
     fn eval_direct(
@@ -29,6 +36,32 @@ pub trait Brdf {
     ) -> Vector3<f32>;
 
     fn pdf(&self) -> f32;
+
+    /// Solid angle density of `sample` generating `light_direction` (used for MIS)
+    fn sampling_pdf(
+        &self,
+        geometric_normal: Vector3<f32>,
+        view_direction: Vector3<f32>,  // from hit point to the viewer
+        light_direction: Vector3<f32>, // from hit point to the light
+    ) -> f32;
+
+    /// Whether the last sampled direction comes from a delta distribution (perfectly smooth surface)
+    fn is_delta_sample(&self) -> bool;
+
+    /// Part of `eval_direct` reflected by the diffuse lobe, for the render passes
+    fn eval_direct_diffuse(
+        &self,
+        _geometric_normal: Vector3<f32>,
+        _view_direction: Vector3<f32>,
+        _light_direction: Vector3<f32>,
+    ) -> Vector3<f32> {
+        Vector3::zero()
+    }
+
+    /// Whether the last sampled direction comes from the diffuse lobe
+    fn is_diffuse_sample(&self) -> bool {
+        false
+    }
 }
 
 // Transform any coordinate system to world coordinates
@@ -47,6 +80,33 @@ pub fn transform_to_world(vec: Vector3<f32>, n: Vector3<f32>) -> Vector3<f32> {
     )
 }
 
+// Sample a microfacet normal according to the GGX distribution
+pub fn sample_ggx_normal(
+    roughness: f32,
+    geometric_normal: Vector3<f32>,
+    sampler: &mut dyn Sampler,
+) -> Vector3<f32> {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    // Generate uniform random variables between 0 and 1
+    let (r1, r2) = sampler.next_2d();
+
+    // Compute spherical coordinates of the normal
+    // Theta depends on the roughness according to the NDF (due to importance sampling on microfacet model)
+    let theta = (((1. - r1) / (r1 * (a2 - 1.) + 1.)).sqrt()).acos();
+    // Phi can be sampled uniformly because the NDF is isotropic
+    let phi = 2. * PI * r2;
+
+    // Convert to cartesian coordinates
+    let sin_theta = theta.sin();
+    let x = sin_theta * phi.cos();
+    let y = theta.cos();
+    let z = sin_theta * phi.sin();
+    let microfacet_normal = Vector3::new(x, y, z).normalize();
+
+    transform_to_world(microfacet_normal, geometric_normal).normalize()
+}
+
 #[derive(Default, Copy, Debug, Clone, Deserialize)]
 pub enum BrdfType {
     #[serde(rename = "COOK_TORRANCE")]
@@ -54,7 +114,24 @@ pub enum BrdfType {
     CookTorrance,
 }
 
-pub fn get_brdf(material_sample: &MaterialSample, brdf_type: BrdfType) -> Box<dyn Brdf> {
+pub fn get_brdf(
+    material_sample: &MaterialSample,
+    brdf_type: BrdfType,
+    is_backface: bool,
+    sampler: &mut dyn Sampler,
+) -> Box<dyn Brdf> {
+    // Light enters subsurface media through a colorless dielectric interface
+    if material_sample.subsurface.is_some() {
+        let tint = Vector3::new(1., 1., 1.);
+        return Box::new(Dielectric::new(material_sample, tint, is_backface));
+    }
+
+    // Transmissive materials pick the dielectric lobe proportionally to their transmission
+    if material_sample.transmission > 0. && sampler.next_1d() < material_sample.transmission {
+        let tint = material_sample.albedo;
+        return Box::new(Dielectric::new(material_sample, tint, is_backface));
+    }
+
     match brdf_type {
         BrdfType::CookTorrance => Box::new(CookTorrance::new(material_sample)),
     }
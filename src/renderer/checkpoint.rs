@@ -0,0 +1,274 @@
+use super::accumulator::PixelAccumulator;
+use crate::config::Profile;
+use crate::scene::scene_files;
+use sha1::{digest::Update, Digest, Sha1};
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs::{read, rename, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Identifies checkpoint files (followed by the format version)
+const MAGIC: &[u8; 4] = b"PTCK";
+const VERSION: u32 = 3;
+
+pub type Hash = [u8; 20];
+
+/// State of an interrupted render: the raw accumulation of every pixel and the number of passes
+/// done, with hashes of the profile and the scene it was rendered with.
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    /// Number of passes already accumulated
+    pub pass: usize,
+    pub profile_hash: Hash,
+    pub scene_hash: Hash,
+    pub pixels: Vec<PixelAccumulator>,
+}
+
+impl Checkpoint {
+    /// Hash of the profile settings the samples depend on. The stopping criteria are left out so
+    /// a render can be resumed with more samples or time, and so are the tiles and the post
+    /// processing.
+    pub fn hash_profile(profile: &Profile) -> Hash {
+        // Every setting is listed, so new ones have to be sorted out
+        let Profile {
+            resolution,
+            bounces,
+            samples: _,
+            adaptive,
+            time_limit: _,
+            target_noise: _,
+            tile_size: _,
+            tile_order: _,
+            filter,
+            filter_radius,
+            denoiser: _,
+            passes,
+            brdf,
+            working_space,
+            spectral,
+            exposure: _,
+            auto_exposure: _,
+            white_balance: _,
+            bloom: _,
+            tonemap: _,
+            hable: _,
+            lut: _,
+            display: _,
+            integrator,
+            sampler,
+            mutations,
+            photons,
+            photon_radius,
+        } = profile;
+        let settings: [&dyn Debug; 14] = [
+            &resolution,
+            &bounces,
+            &adaptive,
+            &filter,
+            &filter_radius,
+            &passes,
+            &brdf,
+            &working_space,
+            &spectral,
+            &integrator,
+            &sampler,
+            &mutations,
+            &photons,
+            &photon_radius,
+        ];
+        settings
+            .iter()
+            .fold(Sha1::new(), |hasher, setting| {
+                hasher.chain(format!("{setting:?};").as_bytes())
+            })
+            .finalize()
+            .into()
+    }
+
+    /// Hash of the scene description file and the textures it uses
+    pub fn hash_scene<P: AsRef<Path>>(path: P) -> Result<Hash, Box<dyn Error + Send + Sync>> {
+        let mut hasher = Sha1::new();
+        for file in scene_files(path)? {
+            let bytes = read(&file).map_err(|e| format!("{}: {e}", file.display()))?;
+            hasher = hasher
+                .chain((bytes.len() as u64).to_le_bytes())
+                .chain(bytes);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Load a checkpoint to resume with this profile and scene. The header is checked before the
+    /// pixels are read, so they match the resolution and the passes of the profile.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        profile: &Profile,
+        scene_hash: &Hash,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err("Not a checkpoint file".into());
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(format!("Unsupported checkpoint version {version}").into());
+        }
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let pass = read_u64(&mut reader)? as usize;
+        let mut profile_hash = Hash::default();
+        reader.read_exact(&mut profile_hash)?;
+        let mut file_scene_hash = Hash::default();
+        reader.read_exact(&mut file_scene_hash)?;
+        let mut checkpoint = Self {
+            width,
+            height,
+            pass,
+            profile_hash,
+            scene_hash: file_scene_hash,
+            pixels: vec![],
+        };
+        checkpoint.check(profile, scene_hash)?;
+
+        let pixels = width
+            .checked_mul(height)
+            .ok_or_else(|| format!("The checkpoint resolution ({width}x{height}) is too large"))?;
+        checkpoint.pixels = (0..pixels)
+            .map(|_| PixelAccumulator::read(&mut reader, profile.passes.len()))
+            .collect::<Result<_, _>>()?;
+        Ok(checkpoint)
+    }
+
+    /// Write the checkpoint next to its destination first, so an interrupted save doesn't
+    /// destroy the previous checkpoint
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        {
+            let mut writer = BufWriter::new(File::create(&partial)?);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            writer.write_all(&self.width.to_le_bytes())?;
+            writer.write_all(&self.height.to_le_bytes())?;
+            writer.write_all(&(self.pass as u64).to_le_bytes())?;
+            writer.write_all(&self.profile_hash)?;
+            writer.write_all(&self.scene_hash)?;
+            for pixel in &self.pixels {
+                pixel.write(&mut writer)?;
+            }
+            writer.flush()?;
+        }
+        rename(partial, path)?;
+        Ok(())
+    }
+
+    /// Check the checkpoint can be resumed with this profile and scene
+    fn check(
+        &self,
+        profile: &Profile,
+        scene_hash: &Hash,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if (self.width, self.height) != (profile.resolution.width, profile.resolution.height) {
+            return Err(format!(
+                "The checkpoint resolution ({}x{}) doesn't match the profile",
+                self.width, self.height
+            )
+            .into());
+        }
+        if self.profile_hash != Self::hash_profile(profile) {
+            return Err("The checkpoint was rendered with a different profile".into());
+        }
+        if &self.scene_hash != scene_hash {
+            return Err("The checkpoint was rendered from a different scene".into());
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(super) fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Resolution;
+    use crate::renderer::passes::Pass;
+    use std::fs::{copy, create_dir_all, remove_dir_all, remove_file, write};
+
+    #[test]
+    fn load_checks_the_header() {
+        let path = std::env::temp_dir().join("path-tracer-checkpoint-header-test.ckpt");
+        let profile = Profile {
+            resolution: Resolution {
+                width: 2,
+                height: 1,
+            },
+            ..Default::default()
+        };
+        let scene_hash = Hash::default();
+        let checkpoint = |profile: &Profile, width, height| Checkpoint {
+            width,
+            height,
+            pass: 1,
+            profile_hash: Checkpoint::hash_profile(profile),
+            scene_hash,
+            pixels: vec![PixelAccumulator::with_passes(0); 2],
+        };
+        checkpoint(&profile, 2, 1).save(&path).unwrap();
+        assert!(Checkpoint::load(&path, &profile, &scene_hash).is_ok());
+
+        // A corrupt resolution is rejected before reading the pixels
+        checkpoint(&profile, u32::MAX, u32::MAX)
+            .save(&path)
+            .unwrap();
+        assert!(Checkpoint::load(&path, &profile, &scene_hash).is_err());
+
+        // So are pixels without the render passes of the profile
+        let with_passes = Profile {
+            passes: Pass::ALL.to_vec().into(),
+            ..profile.clone()
+        };
+        checkpoint(&with_passes, 2, 1).save(&path).unwrap();
+        let loaded = Checkpoint::load(&path, &with_passes, &scene_hash);
+        remove_file(path).unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn scene_hash_covers_textures() {
+        let directory = std::env::temp_dir().join("path-tracer-scene-hash-test");
+        create_dir_all(&directory).unwrap();
+        for file in ["scene.isf", "sky.hdr"] {
+            copy(
+                Path::new("tests/scenes/environment").join(file),
+                directory.join(file),
+            )
+            .unwrap();
+        }
+        let scene = directory.join("scene.isf");
+        let hash = Checkpoint::hash_scene(&scene).unwrap();
+        assert_eq!(Checkpoint::hash_scene(&scene).unwrap(), hash);
+
+        // Editing the environment map changes the hash
+        let mut sky = read(directory.join("sky.hdr")).unwrap();
+        *sky.last_mut().unwrap() ^= 1;
+        write(directory.join("sky.hdr"), sky).unwrap();
+        let edited = Checkpoint::hash_scene(&scene).unwrap();
+        remove_dir_all(directory).unwrap();
+        assert_ne!(edited, hash);
+    }
+}
@@ -0,0 +1,261 @@
+use cgmath::*;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+/// Chromaticity of the D65 white point
+const D65: (f32, f32) = (0.3127, 0.329);
+
+/// Chromaticity of the ACES white point (close to D60)
+const ACES_WHITE: (f32, f32) = (0.321_68, 0.337_67);
+
+/// Bradford chromatic adaptation, from CIE XYZ to cone responses
+const BRADFORD: [[f32; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+/// Chromaticities of the primaries and of the white point of an RGB color space
+struct Primaries {
+    red: (f32, f32),
+    green: (f32, f32),
+    blue: (f32, f32),
+    white: (f32, f32),
+}
+
+const REC709: Primaries = Primaries {
+    red: (0.64, 0.33),
+    green: (0.3, 0.6),
+    blue: (0.15, 0.06),
+    white: D65,
+};
+
+/// ACES AP1 primaries
+const ACESCG: Primaries = Primaries {
+    red: (0.713, 0.293),
+    green: (0.165, 0.83),
+    blue: (0.128, 0.044),
+    white: ACES_WHITE,
+};
+
+const DISPLAY_P3: Primaries = Primaries {
+    red: (0.68, 0.32),
+    green: (0.265, 0.69),
+    blue: (0.15, 0.06),
+    white: D65,
+};
+
+const REC2020: Primaries = Primaries {
+    red: (0.708, 0.292),
+    green: (0.17, 0.797),
+    blue: (0.131, 0.046),
+    white: D65,
+};
+
+static REC709_TO_ACESCG: Lazy<Matrix3<f32>> = Lazy::new(|| conversion(&REC709, &ACESCG));
+static REC709_LUMINANCE: Lazy<Vector3<f32>> = Lazy::new(|| REC709.rgb_to_xyz().row(1));
+static ACESCG_LUMINANCE: Lazy<Vector3<f32>> = Lazy::new(|| ACESCG.rgb_to_xyz().row(1));
+
+impl Primaries {
+    /// Linear RGB to CIE XYZ, the white of the color space having a luminance of 1
+    fn rgb_to_xyz(&self) -> Matrix3<f32> {
+        let primaries = Matrix3::from_cols(
+            xy_to_xyz(self.red),
+            xy_to_xyz(self.green),
+            xy_to_xyz(self.blue),
+        );
+        let scale = primaries.invert().unwrap() * xy_to_xyz(self.white);
+        primaries * Matrix3::from_diagonal(scale)
+    }
+}
+
+/// Linear RGB space the scene is rendered in. The colors of the scenes are given in linear
+/// Rec.709, and converted to it.
+#[derive(Default, Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum WorkingSpace {
+    #[serde(rename = "REC709")]
+    #[default]
+    Rec709,
+    /// ACES AP1 primaries, with the ACES white point
+    #[serde(rename = "ACESCG")]
+    AcesCg,
+}
+
+impl WorkingSpace {
+    fn primaries(self) -> &'static Primaries {
+        match self {
+            WorkingSpace::Rec709 => &REC709,
+            WorkingSpace::AcesCg => &ACESCG,
+        }
+    }
+
+    /// Linear RGB of the working space to CIE XYZ
+    pub fn rgb_to_xyz(self) -> Matrix3<f32> {
+        self.primaries().rgb_to_xyz()
+    }
+
+    /// Weights of the channels in the luminance, the Y row of the conversion to CIE XYZ
+    pub fn luminance_weights(self) -> Vector3<f32> {
+        match self {
+            WorkingSpace::Rec709 => *REC709_LUMINANCE,
+            WorkingSpace::AcesCg => *ACESCG_LUMINANCE,
+        }
+    }
+
+    /// CIE XYZ of the white of the working space, of luminance 1
+    pub fn white(self) -> Vector3<f32> {
+        xy_to_xyz(self.primaries().white)
+    }
+
+    /// Linear Rec.709 color in the working space
+    pub fn convert_rec709(self, color: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            WorkingSpace::Rec709 => color,
+            WorkingSpace::AcesCg => *REC709_TO_ACESCG * color,
+        }
+    }
+
+    /// Linear Rec.709 lengths in the working space, converting the coefficients they are the
+    /// inverse of (like the mean free paths of a medium)
+    pub fn convert_rec709_inverse(self, lengths: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            WorkingSpace::Rec709 => lengths,
+            // The coefficients of the conversion are positive, a length of 0 stays 0
+            WorkingSpace::AcesCg => (*REC709_TO_ACESCG * lengths.map(|x| 1. / x)).map(|x| 1. / x),
+        }
+    }
+}
+
+/// Color space of the display the image is encoded for
+#[derive(Default, Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum DisplaySpace {
+    #[serde(rename = "SRGB")]
+    #[default]
+    Srgb,
+    /// DCI-P3 primaries with the D65 white point and the sRGB transfer function
+    #[serde(rename = "DISPLAY_P3")]
+    DisplayP3,
+    /// Rec.2020 primaries with the BT.1886 transfer function (2.4 gamma)
+    #[serde(rename = "REC2020")]
+    Rec2020,
+}
+
+impl DisplaySpace {
+    fn primaries(self) -> &'static Primaries {
+        match self {
+            DisplaySpace::Srgb => &REC709,
+            DisplaySpace::DisplayP3 => &DISPLAY_P3,
+            DisplaySpace::Rec2020 => &REC2020,
+        }
+    }
+
+    /// Conversion of linear colors from the working space to the primaries of the display
+    pub fn conversion_from(self, working_space: WorkingSpace) -> Matrix3<f32> {
+        conversion(working_space.primaries(), self.primaries())
+    }
+
+    /// Non-linear display value of a linear value between 0 and 1
+    pub fn encode(self, value: f32) -> f32 {
+        match self {
+            DisplaySpace::Srgb | DisplaySpace::DisplayP3 => linear_to_srgb(value),
+            DisplaySpace::Rec2020 => value.max(0.).powf(1. / 2.4),
+        }
+    }
+}
+
+/// Conversion of linear RGB between two color spaces, adapting their white points
+fn conversion(from: &Primaries, to: &Primaries) -> Matrix3<f32> {
+    let adaptation = chromatic_adaptation(xy_to_xyz(from.white), xy_to_xyz(to.white));
+    to.rgb_to_xyz().invert().unwrap() * adaptation * from.rgb_to_xyz()
+}
+
+/// Bradford chromatic adaptation of CIE XYZ colors from a white to another
+pub fn chromatic_adaptation(source: Vector3<f32>, destination: Vector3<f32>) -> Matrix3<f32> {
+    let bradford = Matrix3::from(BRADFORD).transpose();
+    let scale =
+        Matrix3::from_diagonal((bradford * destination).div_element_wise(bradford * source));
+    bradford.invert().unwrap() * scale * bradford
+}
+
+/// CIE XYZ color of luminance 1 with a chromaticity
+pub fn xy_to_xyz((x, y): (f32, f32)) -> Vector3<f32> {
+    Vector3::new(x / y, 1., (1. - x - y) / y)
+}
+
+/// sRGB electro-optical transfer function, decoding an sRGB value to linear
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of the sRGB electro-optical transfer function, encoding a linear value to sRGB
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=100 {
+            let value = i as f32 / 100.;
+            assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-5);
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5);
+        }
+        // Both sides of the linear segment
+        assert!((linear_to_srgb(0.002) - 0.002 * 12.92).abs() < 1e-7);
+        assert!((srgb_to_linear(0.5) - 0.214_041).abs() < 1e-5);
+    }
+
+    #[test]
+    fn white_maps_to_white() {
+        let white = Vector3::new(1., 1., 1.);
+        for working_space in [WorkingSpace::Rec709, WorkingSpace::AcesCg] {
+            for display_space in [
+                DisplaySpace::Srgb,
+                DisplaySpace::DisplayP3,
+                DisplaySpace::Rec2020,
+            ] {
+                let color = display_space.conversion_from(working_space) * white;
+                assert!(
+                    (color - white).magnitude() < 1e-4,
+                    "{working_space:?} to {display_space:?}: {color:?}"
+                );
+            }
+            assert!((working_space.luminance_weights().sum() - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn rec709_to_acescg() {
+        // Published Rec.709 to ACEScg matrix, with the Bradford adaptation from D65
+        let expected = [
+            [0.613_097, 0.339_523, 0.047_379],
+            [0.070_194, 0.916_356, 0.013_452],
+            [0.020_616, 0.109_570, 0.869_815],
+        ];
+        for (i, row) in expected.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                assert!((REC709_TO_ACESCG.row(i)[j] - value).abs() < 1e-3);
+            }
+        }
+
+        let acescg = WorkingSpace::AcesCg;
+        let white = Vector3::new(1., 1., 1.);
+        assert!((acescg.convert_rec709(white) - white).magnitude() < 1e-4);
+        assert!((acescg.convert_rec709_inverse(white * 2.) - white * 2.).magnitude() < 1e-3);
+        assert_eq!(
+            acescg.convert_rec709_inverse(Vector3::new(0., 1., 1.)).x,
+            0.
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use crate::renderer::material_sample::MaterialSample;
 use crate::renderer::utils::*;
+use crate::scene::internal::Model;
 use crate::{config::Resolution, scene::internal::Scene};
 use cgmath::*;
 use image::{Rgb, RgbImage};
+use rayon::prelude::*;
 use std::collections::HashMap;
 
 use super::Hit;
 use super::Ray;
+use super::Renderer;
 
 pub fn debug_render(scene: &Scene, resolution: Resolution) {
     // Create buffers
@@ -61,18 +64,93 @@ pub fn debug_render(scene: &Scene, resolution: Resolution) {
     }
 }
 
-fn render_debug_pixels(scene: &Scene, ray: &Ray) -> HashMap<&'static str, Vector3<f32>> {
-    let mut result = HashMap::new();
-    // Cast ray
-    let (hit, model) = match ray_cast(scene, ray) {
-        res if res.is_empty() => return result,
-        res => res[0].clone(),
-    };
+/// Features of the first surface seen by a ray, guiding the denoiser
+#[derive(Clone, Copy)]
+pub struct Features {
+    pub albedo: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    /// Distance to the camera
+    pub depth: f32,
+}
 
+impl Default for Features {
+    /// Features of the background: no normal nor depth, and a white albedo
+    fn default() -> Self {
+        Self {
+            albedo: Vector3::new(1., 1., 1.),
+            normal: Vector3::zero(),
+            depth: 0.,
+        }
+    }
+}
+
+/// Average features of each pixel over a grid of rays, so that they are anti-aliased like the
+/// rendered image and thin objects aren't missed
+pub fn render_features(scene: &Scene, resolution: Resolution) -> Vec<Features> {
+    const GRID: u32 = 4;
+    let width = resolution.width;
+    (0..width * resolution.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut features = Features {
+                albedo: Vector3::zero(),
+                normal: Vector3::zero(),
+                depth: 0.,
+            };
+            for j in 0..GRID * GRID {
+                let film_x = x as f32 + ((j % GRID) as f32 + 0.5) / GRID as f32;
+                let film_y = y as f32 + ((j / GRID) as f32 + 0.5) / GRID as f32;
+                let ray = Renderer::camera_ray(scene, resolution, film_x, film_y);
+                let hit = first_hit(scene, &ray);
+                let surface_dist = hit
+                    .as_ref()
+                    .map_or(f32::INFINITY, |(hit, ..)| hit.get_dist());
+                // Light spheres are only told apart by their depth
+                let sample = match Renderer::get_visible_light(scene, &ray, surface_dist, None) {
+                    Some((depth, _)) => Features {
+                        depth,
+                        ..Default::default()
+                    },
+                    None => match hit {
+                        Some((hit, model, material)) => Features {
+                            albedo: material.albedo,
+                            normal: hit.get_normal(model.get_material()),
+                            depth: hit.get_dist(),
+                        },
+                        None => Features::default(),
+                    },
+                };
+                features.albedo += sample.albedo;
+                features.normal += sample.normal;
+                features.depth += sample.depth;
+            }
+            let count = (GRID * GRID) as f32;
+            Features {
+                albedo: features.albedo / count,
+                normal: features.normal / count,
+                depth: features.depth / count,
+            }
+        })
+        .collect()
+}
+
+/// Closest hit of a ray, with the material at the hit point
+fn first_hit<'a>(scene: &'a Scene, ray: &Ray) -> Option<(Hit, &'a Model, MaterialSample)> {
+    let (hit, model) = ray_cast(scene, ray).into_iter().next()?;
     let material = match hit {
         Hit::Sphere { .. } => MaterialSample::simple(model.get_material()),
         Hit::Triangle { tex_coords, .. } => MaterialSample::new(model.get_material(), &tex_coords),
     };
+    Some((hit, model, material))
+}
+
+fn render_debug_pixels(scene: &Scene, ray: &Ray) -> HashMap<&'static str, Vector3<f32>> {
+    let mut result = HashMap::new();
+    // Cast ray
+    let Some((hit, model, material)) = first_hit(scene, ray) else {
+        return result;
+    };
 
     // Normal
     let normal = hit.get_normal(model.get_material());
@@ -98,6 +176,9 @@ fn render_debug_pixels(scene: &Scene, ray: &Ray) -> HashMap<&'static str, Vector
     // emissive
     result.insert("emissive", material.emissive);
 
+    // transmission
+    result.insert("transmission", one * material.transmission);
+
     // ior
     result.insert("ior", one * material.ior / 3.);
 
@@ -0,0 +1,236 @@
+use super::color::WorkingSpace;
+use super::debug_renderer::Features;
+use super::utils::luminance;
+use crate::config::{Denoiser, Resolution};
+use cgmath::*;
+use rayon::prelude::*;
+
+/// Weights of the B3 spline the à-trous kernel is built from
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Denoise the linear colors of an image with an edge-avoiding à-trous wavelet filter. The
+/// neighbours are weighted by how much their albedo, normal, depth and color differ, the latter
+/// relatively to the noise level of the pixels (their variance of the mean luminance).
+pub fn denoise(
+    settings: &Denoiser,
+    resolution: Resolution,
+    working_space: WorkingSpace,
+    colors: &[Vector3<f32>],
+    variances: &[f32],
+    features: &[Features],
+) -> Vec<Vector3<f32>> {
+    let mut colors = colors.to_vec();
+    let mut variances = variances.to_vec();
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        (colors, variances) = filter_step(
+            settings,
+            resolution,
+            working_space,
+            step,
+            &colors,
+            &variances,
+            features,
+        );
+    }
+    colors
+}
+
+/// One pass of the filter, with the kernel taps `step` pixels apart
+fn filter_step(
+    settings: &Denoiser,
+    resolution: Resolution,
+    working_space: WorkingSpace,
+    step: i32,
+    colors: &[Vector3<f32>],
+    variances: &[f32],
+    features: &[Features],
+) -> (Vec<Vector3<f32>>, Vec<f32>) {
+    let width = resolution.width as i32;
+    let height = resolution.height as i32;
+    // Standard errors of the pixels, steadier once averaged with their neighbours'
+    let deviations: Vec<_> = blur(resolution, variances)
+        .iter()
+        .map(|variance| variance.sqrt())
+        .collect();
+
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let center = &features[i as usize];
+            let luminance_center = luminance(&colors[i as usize], working_space);
+
+            let mut color = Vector3::zero();
+            let mut variance = 0.;
+            let mut total_weight = 0.;
+            for (dy, kernel_y) in KERNEL.iter().enumerate() {
+                for (dx, kernel_x) in KERNEL.iter().enumerate() {
+                    let neighbour_x = x + (dx as i32 - 2) * step;
+                    let neighbour_y = y + (dy as i32 - 2) * step;
+                    if neighbour_x < 0
+                        || neighbour_x >= width
+                        || neighbour_y < 0
+                        || neighbour_y >= height
+                    {
+                        continue;
+                    }
+                    let j = (neighbour_x + neighbour_y * width) as usize;
+                    let neighbour = &features[j];
+
+                    let color_distance =
+                        (luminance(&colors[j], working_space) - luminance_center).abs();
+                    let albedo_distance = (neighbour.albedo - center.albedo).magnitude2();
+                    let normal_distance = (neighbour.normal - center.normal).magnitude2();
+                    let depth_distance = (neighbour.depth - center.depth).abs()
+                        / (neighbour.depth.max(center.depth) + f32::EPSILON);
+                    // Both pixels must be noisy for their colors to be told apart loosely
+                    let color_tolerance =
+                        settings.color_sigma * deviations[i as usize].min(deviations[j]);
+                    let exponent = color_distance / (color_tolerance + f32::EPSILON)
+                        + albedo_distance / settings.albedo_sigma.powi(2)
+                        + normal_distance / settings.normal_sigma.powi(2)
+                        + depth_distance / settings.depth_sigma;
+                    let weight = kernel_x * kernel_y * (-exponent).exp();
+
+                    color += colors[j] * weight;
+                    variance += variances[j] * weight * weight;
+                    total_weight += weight;
+                }
+            }
+            // The center pixel always has a weight, so the total can't be zero
+            (
+                color / total_weight,
+                variance / (total_weight * total_weight),
+            )
+        })
+        .unzip()
+}
+
+/// Variances averaged over 3x3 pixels, steadier for the color weights
+fn blur(resolution: Resolution, variances: &[f32]) -> Vec<f32> {
+    let width = resolution.width as i32;
+    let height = resolution.height as i32;
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut sum = 0.;
+            let mut total_weight = 0.;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (neighbour_x, neighbour_y) = (x + dx, y + dy);
+                    if neighbour_x < 0
+                        || neighbour_x >= width
+                        || neighbour_y < 0
+                        || neighbour_y >= height
+                    {
+                        continue;
+                    }
+                    let weight = KERNEL[(dx + 2) as usize] * KERNEL[(dy + 2) as usize];
+                    sum += variances[(neighbour_x + neighbour_y * width) as usize] * weight;
+                    total_weight += weight;
+                }
+            }
+            sum / total_weight
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTION: Resolution = Resolution {
+        width: 32,
+        height: 32,
+    };
+
+    /// Features of a flat wall facing the camera
+    fn wall() -> Vec<Features> {
+        vec![
+            Features {
+                albedo: Vector3::new(0.8, 0.8, 0.8),
+                normal: Vector3::new(0., 0., 1.),
+                depth: 1.,
+            };
+            (RESOLUTION.width * RESOLUTION.height) as usize
+        ]
+    }
+
+    fn denoise_image(colors: &[Vector3<f32>], features: &[Features]) -> Vec<Vector3<f32>> {
+        // The pixels are as noisy as they are bright
+        let variances: Vec<_> = colors
+            .iter()
+            .map(|color| luminance(color, WorkingSpace::Rec709).powi(2))
+            .collect();
+        denoise(
+            &Denoiser::default(),
+            RESOLUTION,
+            WorkingSpace::Rec709,
+            colors,
+            &variances,
+            features,
+        )
+    }
+
+    #[test]
+    fn constant() {
+        let color = Vector3::new(0.6, 0.3, 0.1);
+        let colors = vec![color; (RESOLUTION.width * RESOLUTION.height) as usize];
+        for denoised in denoise_image(&colors, &wall()) {
+            assert!((denoised - color).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn edges() {
+        // The left half of the image is dark and the right half bright, along an edge of the
+        // normals or of the albedo
+        let left = |i: usize| i as u32 % RESOLUTION.width < RESOLUTION.width / 2;
+        let colors: Vec<_> = (0..(RESOLUTION.width * RESOLUTION.height) as usize)
+            .map(|i| {
+                if left(i) {
+                    Vector3::new(0.1, 0.1, 0.1)
+                } else {
+                    Vector3::new(1., 1., 1.)
+                }
+            })
+            .collect();
+        let normal_edge: Vec<_> = wall()
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut features)| {
+                if left(i) {
+                    features.normal = Vector3::new(1., 0., 0.);
+                }
+                features
+            })
+            .collect();
+        let albedo_edge: Vec<_> = wall()
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut features)| {
+                if left(i) {
+                    features.albedo = Vector3::new(0.1, 0.1, 0.1);
+                }
+                features
+            })
+            .collect();
+
+        for features in [normal_edge, albedo_edge] {
+            let denoised = denoise_image(&colors, &features);
+            for (denoised, color) in denoised.iter().zip(&colors) {
+                assert!((denoised - color).magnitude() < 0.01 * color.magnitude());
+            }
+        }
+
+        // Without the features, the edge is only kept by the color weights of noisy pixels
+        let denoised = denoise_image(&colors, &wall());
+        let blurred = denoised
+            .iter()
+            .zip(&colors)
+            .any(|(denoised, color)| (denoised - color).magnitude() >= 0.01 * color.magnitude());
+        assert!(blurred);
+    }
+}
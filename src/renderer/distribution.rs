@@ -0,0 +1,115 @@
+/// Piecewise constant 1D distribution used for importance sampling
+#[derive(Debug, Clone, Default)]
+pub struct Distribution1D {
+    /// Cumulative distribution function, `n + 1` entries from 0 to 1
+    cdf: Vec<f32>,
+    /// Integral of the function over [0, 1]
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[f32]) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.) / n as f32;
+        }
+        let integral = cdf[n];
+
+        if integral > 0. {
+            for value in cdf.iter_mut() {
+                *value /= integral;
+            }
+        } else {
+            // Fallback to a uniform distribution
+            for (i, value) in cdf.iter_mut().enumerate() {
+                *value = i as f32 / n as f32;
+            }
+        }
+
+        Self { cdf, integral }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cdf.len().saturating_sub(1)
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Find the segment containing `u`
+    fn find_segment(&self, u: f32) -> usize {
+        let index = self.cdf.partition_point(|&value| value <= u);
+        index.saturating_sub(1).min(self.len() - 1)
+    }
+
+    /// Sample a segment index given a uniform random variable `u` in [0, 1).
+    /// Returns the index and its probability mass.
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32) {
+        let index = self.find_segment(u);
+        (index, self.pmf(index))
+    }
+
+    /// Sample a continuous value in [0, 1) given a uniform random variable `u`.
+    /// Returns the value, its density and the segment index.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let index = self.find_segment(u);
+        let segment = self.pmf(index);
+        let du = if segment > 0. {
+            (u - self.cdf[index]) / segment
+        } else {
+            0.
+        };
+        let value = (index as f32 + du.clamp(0., 1.)) / self.len() as f32;
+        (value, segment * self.len() as f32, index)
+    }
+
+    /// Probability mass of a segment
+    pub fn pmf(&self, index: usize) -> f32 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+
+    /// Density of a continuous value in [0, 1)
+    pub fn pdf(&self, value: f32) -> f32 {
+        let index = ((value * self.len() as f32) as usize).min(self.len() - 1);
+        self.pmf(index) * self.len() as f32
+    }
+}
+
+/// Piecewise constant 2D distribution over [0, 1]², stored row by row
+#[derive(Debug, Clone, Default)]
+pub struct Distribution2D {
+    /// Distribution of `u` for each row
+    conditionals: Vec<Distribution1D>,
+    /// Distribution of the rows (`v`)
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let conditionals: Vec<_> = func
+            .chunks(width)
+            .take(height)
+            .map(Distribution1D::new)
+            .collect();
+        let marginal_func: Vec<_> = conditionals.iter().map(|c| c.integral()).collect();
+        Self {
+            conditionals,
+            marginal: Distribution1D::new(&marginal_func),
+        }
+    }
+
+    /// Sample a point given two uniform random variables. Returns the point and its density.
+    pub fn sample_continuous(&self, u1: f32, u2: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u2);
+        let (u, pdf_u, _) = self.conditionals[row].sample_continuous(u1);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    /// Density of a point in [0, 1]²
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = ((v * self.marginal.len() as f32) as usize).min(self.marginal.len() - 1);
+        self.conditionals[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
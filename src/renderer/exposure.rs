@@ -0,0 +1,177 @@
+use super::color::{chromatic_adaptation, xy_to_xyz, WorkingSpace};
+use crate::config::{Profile, WhiteBalance};
+use cgmath::*;
+
+/// Offset of the luminances averaged in log space, so black pixels don't zero the average
+const LOG_AVERAGE_DELTA: f32 = 1e-4;
+
+/// Linear transform applied to the colors before the tone curve: the white balance, scaled by the
+/// exposure compensation and the automatic exposure
+pub fn adjustment(profile: &Profile, colors: &[Vector3<f32>]) -> Matrix3<f32> {
+    let working_space = profile.working_space;
+    let white_balance = profile
+        .white_balance
+        .map_or(Matrix3::identity(), |settings| {
+            white_balance(settings, working_space)
+        });
+    let mut scale = 2f32.powf(profile.exposure);
+    if let Some(auto_exposure) = &profile.auto_exposure {
+        // Luminance of the white balanced colors
+        let luminance = white_balance.transpose() * working_space.luminance_weights();
+        let log_sum: f32 = colors
+            .iter()
+            .map(|color| (luminance.dot(*color).max(0.) + LOG_AVERAGE_DELTA).ln())
+            .sum();
+        let log_average = (log_sum / colors.len().max(1) as f32).exp();
+        scale *= auto_exposure.key / log_average;
+    }
+    white_balance * scale
+}
+
+/// Chromatic adaptation of the white of the lighting to the white point of the image
+fn white_balance(settings: WhiteBalance, working_space: WorkingSpace) -> Matrix3<f32> {
+    let rgb_to_xyz = working_space.rgb_to_xyz();
+    let xyz_to_rgb = rgb_to_xyz.invert().unwrap();
+    let source = match settings {
+        WhiteBalance::Temperature { temperature, tint } => {
+            xy_to_xyz(temperature_chromaticity(temperature, tint))
+        }
+        WhiteBalance::Reference { white } => {
+            let white = rgb_to_xyz * Vector3::from(white);
+            if white.y <= 0. {
+                return Matrix3::identity();
+            }
+            white / white.y
+        }
+    };
+
+    let adaptation = chromatic_adaptation(source, working_space.white());
+    xyz_to_rgb * adaptation * rgb_to_xyz
+}
+
+/// Chromaticity of a light of a color temperature (in Kelvin), moved away from the locus by the
+/// tint
+fn temperature_chromaticity(temperature: f32, tint: f32) -> (f32, f32) {
+    let locus = |temperature: f32| xy_to_uv(locus_chromaticity(temperature));
+    let (u, v) = locus(temperature);
+    if tint == 0. {
+        return uv_to_xy((u, v));
+    }
+    // Normal to the locus, pointing towards green
+    let (next_u, next_v) = locus(temperature * 1.01);
+    let tangent = Vector2::new(next_u - u, next_v - v).normalize();
+    let mut normal = Vector2::new(-tangent.y, tangent.x);
+    if normal.y < 0. {
+        normal = -normal;
+    }
+    let shift = normal * tint / 1000.;
+    uv_to_xy((u + shift.x, v + shift.y))
+}
+
+/// Chromaticity of the daylight locus (CIE illuminant D) above 4000K, and of the blackbody
+/// (Planckian) locus below, as approximated by Kim et al.
+fn locus_chromaticity(temperature: f32) -> (f32, f32) {
+    let t = temperature.clamp(1667., 25000.);
+    let (t2, t3) = (t * t, t * t * t);
+    if t >= 4000. {
+        let x = if t <= 7000. {
+            -4.607e9 / t3 + 2.9678e6 / t2 + 0.09911e3 / t + 0.244_063
+        } else {
+            -2.0064e9 / t3 + 1.9018e6 / t2 + 0.24748e3 / t + 0.237_04
+        };
+        (x, -3. * x * x + 2.87 * x - 0.275)
+    } else {
+        let x = -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_91;
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t <= 2222. {
+            -1.106_381_4 * x3 - 1.348_110_2 * x2 + 2.185_558_3 * x - 0.202_196_83
+        } else {
+            -0.954_947_6 * x3 - 1.374_185_9 * x2 + 2.091_37 * x - 0.167_488_67
+        };
+        (x, y)
+    }
+}
+
+/// CIE 1931 xy to CIE 1960 uv chromaticity
+fn xy_to_uv((x, y): (f32, f32)) -> (f32, f32) {
+    let denominator = -2. * x + 12. * y + 3.;
+    (4. * x / denominator, 6. * y / denominator)
+}
+
+/// CIE 1960 uv to CIE 1931 xy chromaticity
+fn uv_to_xy((u, v): (f32, f32)) -> (f32, f32) {
+    let denominator = 2. * u - 8. * v + 4.;
+    (3. * u / denominator, 2. * v / denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AutoExposure;
+
+    fn assert_close(a: Matrix3<f32>, b: Matrix3<f32>, epsilon: f32) {
+        for (a, b) in [(a.x, b.x), (a.y, b.y), (a.z, b.z)] {
+            assert!((a - b).magnitude() < epsilon, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn exposure_compensation() {
+        let colors = [Vector3::new(0.5, 1., 2.)];
+        let profile = |exposure| Profile {
+            exposure,
+            white_balance: Some(WhiteBalance::Temperature {
+                temperature: 4500.,
+                tint: 5.,
+            }),
+            ..Default::default()
+        };
+        let reference = adjustment(&profile(0.), &colors);
+        // Each stop doubles the linear radiance
+        assert_close(adjustment(&profile(1.), &colors), reference * 2., 1e-6);
+        assert_close(adjustment(&profile(-2.), &colors), reference / 4., 1e-6);
+    }
+
+    #[test]
+    fn auto_exposure() {
+        let color = Vector3::new(0.5, 1., 2.);
+        for working_space in [WorkingSpace::Rec709, WorkingSpace::AcesCg] {
+            let profile = Profile {
+                working_space,
+                auto_exposure: Some(AutoExposure { key: 0.18 }),
+                ..Default::default()
+            };
+            // A uniform image is scaled to the key
+            let scale = adjustment(&profile, &[color; 4]);
+            let luminance = working_space.luminance_weights().dot(scale * color);
+            assert!((luminance - 0.18).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn white_balance_reference() {
+        for working_space in [WorkingSpace::Rec709, WorkingSpace::AcesCg] {
+            let white = Vector3::new(1., 0.8, 0.5);
+            let balance = white_balance(
+                WhiteBalance::Reference {
+                    white: white.into(),
+                },
+                working_space,
+            );
+            // The reference becomes the white of the working space, keeping its luminance
+            let luminance = working_space.luminance_weights().dot(white);
+            let balanced = balance * white;
+            assert!((balanced - Vector3::new(1., 1., 1.) * luminance).magnitude() < 1e-5);
+        }
+
+        // The lighting of the sRGB white point needs no correction
+        let d65 = white_balance(
+            WhiteBalance::Temperature {
+                temperature: 6504.,
+                tint: 0.,
+            },
+            WorkingSpace::Rec709,
+        );
+        assert_close(d65, Matrix3::identity(), 1e-2);
+    }
+}
@@ -0,0 +1,217 @@
+use super::accumulator::{FilmPixel, FilmSample};
+use super::tiles::Tile;
+use crate::config::Profile;
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+/// Reconstruction filter weighting the samples splatted on the pixels around them
+#[derive(Default, Copy, Debug, Clone, Deserialize)]
+pub enum FilterType {
+    #[serde(rename = "BOX")]
+    #[default]
+    Box,
+    #[serde(rename = "TRIANGLE")]
+    Triangle,
+    #[serde(rename = "GAUSSIAN")]
+    Gaussian,
+    /// Mitchell-Netravali (B = C = 1/3)
+    #[serde(rename = "MITCHELL")]
+    Mitchell,
+    #[serde(rename = "BLACKMAN_HARRIS")]
+    BlackmanHarris,
+    /// Windowed sinc, with as many lobes as the radius
+    #[serde(rename = "LANCZOS")]
+    Lanczos,
+}
+
+impl FilterType {
+    /// Radius (in pixels) used when the profile doesn't give one
+    fn default_radius(self) -> f32 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Triangle => 1.,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell | FilterType::BlackmanHarris => 2.,
+            FilterType::Lanczos => 3.,
+        }
+    }
+}
+
+/// Separable reconstruction filter
+#[derive(Copy, Debug, Clone)]
+pub struct Filter {
+    filter_type: FilterType,
+    radius: f32,
+}
+
+impl Filter {
+    /// Falloff of the Gaussian filter
+    const GAUSSIAN_ALPHA: f32 = 2.;
+    const MITCHELL_B: f32 = 1. / 3.;
+    const MITCHELL_C: f32 = 1. / 3.;
+
+    pub fn new(profile: &Profile) -> Self {
+        let radius = profile
+            .filter_radius
+            .unwrap_or_else(|| profile.filter.default_radius());
+        Self {
+            filter_type: profile.filter,
+            radius,
+        }
+    }
+
+    /// Number of pixels on each side of a pixel its samples can reach
+    pub fn extent(&self) -> u32 {
+        ((self.radius + 0.5).ceil() as u32).saturating_sub(1)
+    }
+
+    /// Whether the samples only reach the pixel they fall in, with the same weight
+    pub fn is_pixel_box(&self) -> bool {
+        matches!(self.filter_type, FilterType::Box) && self.radius == 0.5
+    }
+
+    /// Weight of a sample at an offset (in pixels) from the center of a pixel
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        let r = self.radius;
+        match self.filter_type {
+            FilterType::Box => f32::from(u8::from(x <= r)),
+            FilterType::Triangle => (r - x).max(0.),
+            FilterType::Gaussian => {
+                let gaussian = |x: f32| (-Self::GAUSSIAN_ALPHA * x * x).exp();
+                (gaussian(x) - gaussian(r)).max(0.)
+            }
+            FilterType::Mitchell => mitchell(2. * x / r, Self::MITCHELL_B, Self::MITCHELL_C),
+            FilterType::BlackmanHarris => {
+                if x >= r {
+                    return 0.;
+                }
+                let t = 2. * PI * (x + r) / (2. * r);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2. * t).cos() - 0.01168 * (3. * t).cos()
+            }
+            FilterType::Lanczos => {
+                if x >= r {
+                    return 0.;
+                }
+                sinc(x) * sinc(x / r)
+            }
+        }
+    }
+
+    /// Contribution to a pixel of the samples of a pass, one per pixel of `grid` (row by row,
+    /// `None` for pixels without sample). The samples are summed in a fixed order so the result
+    /// doesn't depend on how the pixels are scheduled.
+    pub fn gather(&self, samples: &[Option<FilmSample>], grid: &Tile, x: u32, y: u32) -> FilmPixel {
+        let mut film = FilmPixel::default();
+        for (sample, weight) in self.splats(samples, grid, x, y) {
+            film.splat(sample.color, weight);
+        }
+        film
+    }
+
+    /// Samples of a pass reaching a pixel with their weights, in the order `gather` sums them
+    pub fn splats<'a>(
+        &'a self,
+        samples: &'a [Option<FilmSample>],
+        grid: &'a Tile,
+        x: u32,
+        y: u32,
+    ) -> impl Iterator<Item = (&'a FilmSample, f32)> {
+        let extent = self.extent();
+        let min_x = x.saturating_sub(extent).max(grid.x);
+        let min_y = y.saturating_sub(extent).max(grid.y);
+        let max_x = (x + extent).min(grid.x + grid.width - 1);
+        let max_y = (y + extent).min(grid.y + grid.height - 1);
+        (min_y..=max_y)
+            .flat_map(move |sample_y| (min_x..=max_x).map(move |sample_x| (sample_x, sample_y)))
+            .filter_map(move |(sample_x, sample_y)| {
+                let sample = samples[grid.index(sample_x, sample_y)].as_ref()?;
+                let weight = self.weight(
+                    sample.film_x - (x as f32 + 0.5),
+                    sample.film_y - (y as f32 + 0.5),
+                );
+                (weight != 0.).then_some((sample, weight))
+            })
+    }
+}
+
+/// Mitchell-Netravali cubic over [-2, 2]
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+    if x < 1. {
+        ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b))
+            / 6.
+    } else if x < 2. {
+        ((-b - 6. * c) * x * x * x
+            + (6. * b + 30. * c) * x * x
+            + (-12. * b - 48. * c) * x
+            + (8. * b + 24. * c))
+            / 6.
+    } else {
+        0.
+    }
+}
+
+/// Normalized sinc
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(filter_type: FilterType, radius: Option<f32>) -> Filter {
+        Filter::new(&Profile {
+            filter: filter_type,
+            filter_radius: radius,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn mitchell() {
+        let filter = filter(FilterType::Mitchell, None);
+        for (x, weight) in [(0., 8. / 9.), (0.5, 77. / 144.), (1., 1. / 18.), (2., 0.)] {
+            assert!((filter.weight_1d(x) - weight).abs() < 1e-6);
+            assert!((filter.weight_1d(-x) - weight).abs() < 1e-6);
+        }
+        assert!((filter.weight(1., 0.) - 8. / 9. / 18.).abs() < 1e-6);
+
+        // The cubic integrates to 1 over its support
+        let steps = 4000;
+        let dx = 4. / steps as f32;
+        let integral: f32 = (0..steps)
+            .map(|i| filter.weight_1d(-2. + (i as f32 + 0.5) * dx) * dx)
+            .sum();
+        assert!((integral - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn support() {
+        for filter_type in [
+            FilterType::Box,
+            FilterType::Triangle,
+            FilterType::Gaussian,
+            FilterType::Mitchell,
+            FilterType::BlackmanHarris,
+            FilterType::Lanczos,
+        ] {
+            for radius in [None, Some(0.5), Some(1.7)] {
+                let filter = filter(filter_type, radius);
+                let r = filter.radius;
+                assert!(filter.weight(0., 0.) > 0.);
+                assert_eq!(filter.weight_1d(r + 0.01), 0.);
+                // Samples reach the pixels whose center is within the radius
+                assert!(filter.extent() as f32 + 0.5 >= r);
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::config::Resolution;
+use cgmath::*;
+use exr::prelude::{f16, write_rgb_file};
+use image::codecs::hdr::HdrEncoder;
+use image::Rgb;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Format of a linear image file, from its extension
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum HdrFormat {
+    OpenExr,
+    /// Radiance RGBE
+    Radiance,
+}
+
+impl HdrFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "exr" => Some(HdrFormat::OpenExr),
+            "hdr" => Some(HdrFormat::Radiance),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a file gets the linear image instead of the post processed one
+pub fn is_hdr<P: AsRef<Path>>(path: P) -> bool {
+    HdrFormat::from_path(path.as_ref()).is_some()
+}
+
+/// Save linear colors as OpenEXR (32-bit floats, or 16-bit halves when `half` is set) or Radiance
+/// HDR depending on the extension of the path
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    resolution: Resolution,
+    colors: &[Vector3<f32>],
+    half: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let path = path.as_ref();
+    let width = resolution.width as usize;
+    let height = resolution.height as usize;
+    let color = |x: usize, y: usize| colors[x + y * width];
+
+    match HdrFormat::from_path(path) {
+        Some(HdrFormat::OpenExr) if half => write_rgb_file(path, width, height, |x, y| {
+            let color = color(x, y);
+            (
+                f16::from_f32(color.x),
+                f16::from_f32(color.y),
+                f16::from_f32(color.z),
+            )
+        })?,
+        Some(HdrFormat::OpenExr) => write_rgb_file(path, width, height, |x, y| {
+            let color = color(x, y);
+            (color.x, color.y, color.z)
+        })?,
+        Some(HdrFormat::Radiance) => {
+            let pixels: Vec<_> = colors
+                .iter()
+                .map(|color| Rgb([color.x, color.y, color.z]))
+                .collect();
+            let writer = BufWriter::new(File::create(path)?);
+            HdrEncoder::new(writer).encode(&pixels, width, height)?;
+        }
+        None => {
+            return Err(format!(
+                "{} isn't an OpenEXR nor a Radiance HDR file",
+                path.display()
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
@@ -31,8 +31,11 @@ pub enum Hit {
         /// Position of the intersection
         position: Vector3<f32>,
 
-        /// Normal vector of the triangle at the hit point
+        /// Normal vector of the sphere at the hit point (facing the ray origin)
         normal: Vector3<f32>,
+
+        /// Ray origin is inside the sphere
+        is_backface: bool,
     },
 }
 
@@ -81,6 +84,46 @@ impl Hit {
         }
     }
 
+    /// The same hit seen from the outer side of the surface
+    pub fn to_front_face(&self) -> Self {
+        match self.clone() {
+            Hit::Triangle {
+                dist,
+                position,
+                normal,
+                tangent,
+                tex_coords,
+                ..
+            } => Hit::Triangle {
+                dist,
+                position,
+                normal,
+                tangent,
+                tex_coords,
+                is_backface: false,
+            },
+            Hit::Sphere {
+                dist,
+                position,
+                normal,
+                is_backface,
+            } => Hit::Sphere {
+                dist,
+                position,
+                normal: if is_backface { -normal } else { normal },
+                is_backface: false,
+            },
+        }
+    }
+
+    /// Whether the ray hit the inner side of the surface
+    pub fn is_backface(&self) -> bool {
+        match self {
+            Hit::Triangle { is_backface, .. } => *is_backface,
+            Hit::Sphere { is_backface, .. } => *is_backface,
+        }
+    }
+
     pub fn get_material_sample(&self, model: &Model) -> MaterialSample {
         match self {
             Hit::Sphere { .. } => MaterialSample::simple(model.get_material()),
@@ -0,0 +1,678 @@
+use super::{supports_surfaces_only, Integrator};
+use crate::config::Profile;
+use crate::renderer::brdf::{get_brdf, transform_to_world, Brdf};
+use crate::renderer::sampler::Sampler;
+use crate::renderer::spectrum::Spectrum;
+use crate::renderer::utils::*;
+use crate::renderer::{Hit, Ray, Renderer};
+use crate::scene::internal::{Light, Model};
+use crate::Scene;
+use cgmath::*;
+use std::f32::consts::PI;
+
+/// Emitter a light subpath can start from.
+/// Infinite lights (directional, environment, background) are only reached from the camera.
+#[derive(Clone, Copy)]
+pub(super) enum Emitter<'a> {
+    /// Point or spot light
+    Point(&'a Light),
+    /// Surface of a spherical point light
+    Sphere(&'a Light),
+    /// Emissive geometry with its radiance
+    Surface(Vector3<f32>),
+}
+
+enum VertexKind<'a> {
+    Camera,
+    Light(Emitter<'a>),
+    Surface {
+        model: &'a Model,
+        brdf: Box<dyn Brdf>,
+        emissive: Vector3<f32>,
+    },
+}
+
+/// Vertex of a camera or light subpath
+struct PathVertex<'a> {
+    kind: VertexKind<'a>,
+    position: Vector3<f32>,
+    /// Shading normal (zero for points without surface)
+    normal: Vector3<f32>,
+    /// Geometric normal (zero for points without surface)
+    geometric_normal: Vector3<f32>,
+    /// Direction towards the previous vertex of the subpath
+    wo: Vector3<f32>,
+    throughput: Vector3<f32>,
+    /// Area density of sampling this vertex from the previous one
+    pdf_fwd: f32,
+    /// Area density of sampling this vertex from the next one
+    pdf_rev: f32,
+    /// The next vertex was sampled from a delta distribution (smooth reflection or refraction)
+    delta: bool,
+}
+
+impl<'a> Emitter<'a> {
+    /// Radiance (intensity for point lights) emitted towards a direction, including the cosine at the emitter
+    pub(super) fn emission(&self, normal: Vector3<f32>, direction: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            Emitter::Point(Light::Spot {
+                direction: spot_direction,
+                color,
+                inner_cone_angle,
+                outer_cone_angle,
+                ..
+            }) => {
+                let falloff = Light::spot_falloff(
+                    *spot_direction,
+                    direction,
+                    *inner_cone_angle,
+                    *outer_cone_angle,
+                );
+                color * (falloff / (4. * PI))
+            }
+            Emitter::Point(Light::Point { color, .. }) => color / (4. * PI),
+            Emitter::Point(Light::Directional { .. }) => Zero::zero(),
+            Emitter::Sphere(light) => light.surface_radiance() * normal.dot(direction).max(0.),
+            Emitter::Surface(radiance) => radiance * normal.dot(direction).abs(),
+        }
+    }
+
+    /// Solid angle density of `sample_direction`
+    pub(super) fn direction_pdf(&self, normal: Vector3<f32>, direction: Vector3<f32>) -> f32 {
+        match self {
+            Emitter::Point(Light::Spot {
+                direction: spot_direction,
+                outer_cone_angle,
+                ..
+            }) => {
+                let cos_outer = outer_cone_angle.cos();
+                if spot_direction.dot(direction) < cos_outer {
+                    0.
+                } else {
+                    1. / (2. * PI * (1. - cos_outer))
+                }
+            }
+            Emitter::Point(_) => 1. / (4. * PI),
+            Emitter::Sphere(_) => normal.dot(direction).max(0.) / PI,
+            Emitter::Surface(_) => normal.dot(direction).abs() / (2. * PI),
+        }
+    }
+
+    /// Sample the direction of the light leaving the emitter
+    pub(super) fn sample_direction(
+        &self,
+        normal: Vector3<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        match self {
+            Emitter::Point(Light::Spot {
+                direction,
+                outer_cone_angle,
+                ..
+            }) => {
+                // Uniform sampling of the outer cone
+                let (r1, r2) = sampler.next_2d();
+                let cos_theta = 1. - r1 * (1. - outer_cone_angle.cos());
+                let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+                let phi = 2. * PI * r2;
+                let local = Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                transform_to_world(local, *direction).normalize()
+            }
+            Emitter::Point(_) => uniform_sphere(sampler),
+            Emitter::Sphere(_) => cosine_hemisphere(normal, sampler),
+            Emitter::Surface(_) => {
+                // Emissive surfaces emit on both sides
+                let side = if sampler.next_1d() < 0.5 { 1. } else { -1. };
+                cosine_hemisphere(normal * side, sampler)
+            }
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        matches!(self, Emitter::Point(_))
+    }
+}
+
+impl<'a> PathVertex<'a> {
+    fn new(kind: VertexKind<'a>, position: Vector3<f32>, throughput: Vector3<f32>) -> Self {
+        Self {
+            kind,
+            position,
+            normal: Zero::zero(),
+            geometric_normal: Zero::zero(),
+            wo: Zero::zero(),
+            throughput,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            delta: false,
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.geometric_normal != Zero::zero()
+    }
+
+    /// Emitter found at this vertex
+    fn emitter(&self) -> Option<Emitter<'a>> {
+        match &self.kind {
+            VertexKind::Light(emitter) => Some(*emitter),
+            VertexKind::Surface { emissive, .. } if *emissive != Zero::zero() => {
+                Some(Emitter::Surface(*emissive))
+            }
+            _ => None,
+        }
+    }
+
+    /// Convert a solid angle density at this vertex into an area density at `next`
+    fn convert_density(&self, pdf: f32, next: &PathVertex) -> f32 {
+        let to_next = next.position - self.position;
+        let dist2 = to_next.magnitude2();
+        if dist2 == 0. {
+            return 0.;
+        }
+        let mut pdf = pdf / dist2;
+        if next.is_on_surface() {
+            pdf *= next.geometric_normal.dot(to_next / dist2.sqrt()).abs();
+        }
+        pdf
+    }
+
+    /// Light scattered (or emitted) towards another vertex, including the cosine at this vertex
+    fn eval(&self, next: &PathVertex) -> Vector3<f32> {
+        let direction = (next.position - self.position).normalize();
+        match &self.kind {
+            VertexKind::Surface { brdf, .. } => brdf.eval_direct(self.normal, self.wo, direction),
+            VertexKind::Light(emitter) => emitter.emission(self.normal, direction),
+            VertexKind::Camera => Zero::zero(),
+        }
+    }
+
+    /// Light scattered towards another vertex when this vertex belongs to a light subpath.
+    /// The brdfs are written for camera paths, so they are evaluated with the directions swapped
+    fn eval_light(&self, next: &PathVertex) -> Vector3<f32> {
+        let direction = (next.position - self.position).normalize();
+        match &self.kind {
+            VertexKind::Surface { brdf, .. } => {
+                transposed_eval(brdf.as_ref(), self.normal, self.wo, direction)
+            }
+            _ => self.eval(next),
+        }
+    }
+
+    /// Area density of sampling `next` from this vertex, `prev` being the vertex before this one
+    fn pdf(&self, prev: Option<&PathVertex>, next: &PathVertex) -> f32 {
+        let direction = (next.position - self.position).normalize();
+        let pdf = match &self.kind {
+            VertexKind::Surface { brdf, .. } => {
+                let wo = prev.map_or(self.wo, |prev| (prev.position - self.position).normalize());
+                brdf.sampling_pdf(self.normal, wo, direction)
+            }
+            VertexKind::Light(emitter) => emitter.direction_pdf(self.normal, direction),
+            VertexKind::Camera => 0.,
+        };
+        if !pdf.is_finite() {
+            return 0.;
+        }
+        self.convert_density(pdf, next)
+    }
+
+    /// Area density of sampling this vertex as the origin of a light subpath
+    fn light_origin_pdf(&self, scene: &Scene) -> f32 {
+        let position_pdf = match &self.kind {
+            VertexKind::Light(Emitter::Sphere(Light::Point { size, .. })) => {
+                1. / (4. * PI * size * size)
+            }
+            VertexKind::Surface { model, .. } => scene.emissive_lights.pdf(model),
+            _ => 0.,
+        };
+        position_pdf / light_count(scene) as f32
+    }
+
+    /// Origin of a ray leaving the vertex in a given direction
+    fn ray_origin(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let mut normal = self.geometric_normal;
+        if normal.dot(direction) < 0. {
+            normal = -normal;
+        }
+        self.position + normal * Renderer::NORMAL_BIAS
+    }
+}
+
+/// Bidirectional path tracing.
+/// Light subpaths are connected to every vertex of the camera subpath. Connections of
+/// light subpaths to the camera itself (light tracing) are not used.
+pub struct Bdpt;
+
+impl Integrator for Bdpt {
+    fn supports(&self, scene: &Scene) -> bool {
+        supports_surfaces_only(scene)
+    }
+
+    fn radiance(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        let one = Vector3::new(1., 1., 1.);
+        let mut camera_vertices = vec![PathVertex::new(VertexKind::Camera, ray.origin, one)];
+        // The density of the camera ray is never needed since light tracing isn't used
+        let mut color = random_walk(scene, profile, ray, one, 1., &mut camera_vertices, sampler);
+
+        let light_vertices = light_subpath(scene, profile, sampler);
+
+        // A full path has at most `bounces + 1` surface vertices between the camera and the light
+        let max_vertices = profile.bounces + 3;
+        for t in 2..=camera_vertices.len() {
+            for s in 0..=light_vertices.len().min(max_vertices - t) {
+                color += connect(scene, &light_vertices, &camera_vertices, s, t);
+            }
+        }
+        color
+    }
+}
+
+/// Number of emitters light subpaths are started from (emissive geometry counts as one)
+fn light_count(scene: &Scene) -> usize {
+    let lights = scene
+        .lights
+        .iter()
+        .filter(|light| !matches!(light, Light::Directional { .. }))
+        .count();
+    lights + usize::from(!scene.emissive_lights.is_empty())
+}
+
+/// Sample the origin of a light subpath
+fn sample_light_origin<'a>(scene: &'a Scene, sampler: &mut dyn Sampler) -> Option<PathVertex<'a>> {
+    let count = light_count(scene);
+    if count == 0 {
+        return None;
+    }
+    let index = ((sampler.next_1d() * count as f32) as usize).min(count - 1);
+    let light = scene
+        .lights
+        .iter()
+        .filter(|light| !matches!(light, Light::Directional { .. }))
+        .nth(index);
+
+    let (emitter, position, normal, position_pdf) = match light {
+        Some(light @ Light::Point { position, size, .. }) if *size > 0. => {
+            let normal = uniform_sphere(sampler);
+            let pdf = 1. / (4. * PI * size * size);
+            (
+                Emitter::Sphere(light),
+                position + normal * *size,
+                normal,
+                pdf,
+            )
+        }
+        Some(light) => {
+            let position = light.position().unwrap_or_else(Vector3::zero);
+            (Emitter::Point(light), position, Zero::zero(), 1.)
+        }
+        None => {
+            let sample = scene.emissive_lights.sample(&scene.models, sampler)?;
+            let emitter = Emitter::Surface(sample.radiance);
+            (emitter, sample.position, sample.normal, sample.pdf)
+        }
+    };
+
+    let pdf = position_pdf / count as f32;
+    if pdf <= 0. {
+        return None;
+    }
+    let mut vertex = PathVertex::new(
+        VertexKind::Light(emitter),
+        position,
+        Vector3::new(1., 1., 1.) / pdf,
+    );
+    vertex.normal = normal;
+    vertex.geometric_normal = normal;
+    vertex.pdf_fwd = pdf;
+    Some(vertex)
+}
+
+/// Trace a subpath starting from a light
+fn light_subpath<'a>(
+    scene: &'a Scene,
+    profile: &Profile,
+    sampler: &mut dyn Sampler,
+) -> Vec<PathVertex<'a>> {
+    let Some(origin) = sample_light_origin(scene, sampler) else {
+        return vec![];
+    };
+    let VertexKind::Light(emitter) = origin.kind else {
+        unreachable!("A light subpath starts on a light");
+    };
+
+    let direction = emitter.sample_direction(origin.normal, sampler);
+    let pdf = emitter.direction_pdf(origin.normal, direction);
+    let emission = emitter.emission(origin.normal, direction);
+    if pdf <= 0. || emission == Zero::zero() {
+        return vec![origin];
+    }
+
+    let throughput = origin.throughput.mul_element_wise(emission) / pdf;
+    let ray = Ray::new(origin.ray_origin(direction), direction);
+    let mut vertices = vec![origin];
+    random_walk(scene, profile, ray, throughput, pdf, &mut vertices, sampler);
+    vertices
+}
+
+/// Extend a subpath by sampling the brdf at each vertex.
+/// For camera subpaths, returns the light received from infinite lights (directional lights,
+/// environment and background) that are not handled by the bidirectional strategies.
+fn random_walk<'a>(
+    scene: &'a Scene,
+    profile: &Profile,
+    mut ray: Ray,
+    mut throughput: Vector3<f32>,
+    mut pdf_dir: f32,
+    vertices: &mut Vec<PathVertex<'a>>,
+    sampler: &mut dyn Sampler,
+) -> Vector3<f32> {
+    let is_camera = matches!(vertices[0].kind, VertexKind::Camera);
+    let max_vertices = profile.bounces + if is_camera { 2 } else { 1 } + 1;
+    let mut infinite_light = Vector3::zero();
+    let mut last_pdf = None;
+
+    loop {
+        // Infinite lights are gathered over as many bounces as in the path integrator
+        let gathers_infinite_light = is_camera && vertices.len() <= profile.bounces + 1;
+
+        let mut surface = None;
+        for (hit, model) in ray_cast(scene, &ray) {
+            let material_sample = hit.get_material_sample(model);
+            let opacity = material_sample.opacity;
+            surface = Some((hit, model, material_sample));
+
+            // Alpha transparency
+            if opacity >= 1. || (opacity > 0.001 && sampler.next_1d() < opacity) {
+                break;
+            }
+        }
+        let surface_dist = surface
+            .as_ref()
+            .map_or(f32::INFINITY, |(hit, _, _)| hit.get_dist());
+
+        // Spherical lights stop the subpaths like in the path integrator
+        let light_hit = scene
+            .lights
+            .iter()
+            .filter_map(|light| light.intersect(&ray).map(|dist| (light, dist)))
+            .filter(|(_, dist)| *dist < surface_dist)
+            .min_by(|(_, dist1), (_, dist2)| dist1.partial_cmp(dist2).unwrap());
+        if let Some((light, dist)) = light_hit {
+            if is_camera {
+                let position = ray.origin + ray.direction * dist;
+                let center = light.position().unwrap_or(position);
+                let mut vertex = PathVertex::new(
+                    VertexKind::Light(Emitter::Sphere(light)),
+                    position,
+                    throughput,
+                );
+                vertex.normal = (position - center).normalize();
+                vertex.geometric_normal = vertex.normal;
+                vertex.wo = -ray.direction;
+                vertex.pdf_fwd = vertices.last().unwrap().convert_density(pdf_dir, &vertex);
+                vertices.push(vertex);
+            }
+            break;
+        }
+
+        let Some((hit, model, material_sample)) = surface else {
+            if gathers_infinite_light {
+                let background = Renderer::get_background(scene, &ray, last_pdf);
+                infinite_light += throughput.mul_element_wise(background);
+            }
+            break;
+        };
+
+        let normal = hit.get_normal(model.get_material());
+        let brdf = get_brdf(&material_sample, profile.brdf, hit.is_backface(), sampler);
+        let view_direction = -1. * ray.direction;
+
+        if gathers_infinite_light {
+            infinite_light += throughput.mul_element_wise(direct_infinite_light(
+                scene,
+                brdf.as_ref(),
+                &hit,
+                normal,
+                view_direction,
+                sampler,
+            ));
+        }
+
+        let kind = VertexKind::Surface {
+            model,
+            brdf,
+            emissive: material_sample.emissive,
+        };
+        let mut vertex = PathVertex::new(kind, hit.get_position(), throughput);
+        vertex.normal = normal;
+        vertex.geometric_normal = hit.get_geometric_normal();
+        vertex.wo = view_direction;
+        vertex.pdf_fwd = vertices.last().unwrap().convert_density(pdf_dir, &vertex);
+        vertices.push(vertex);
+        if vertices.len() >= max_vertices {
+            break;
+        }
+
+        // Sample the next direction
+        let vertex = vertices.last_mut().unwrap();
+        let VertexKind::Surface { brdf, .. } = &mut vertex.kind else {
+            unreachable!();
+        };
+        let direction = brdf.sample(vertex.normal, vertex.wo, sampler);
+        let mut weight = brdf.eval_indirect(vertex.normal, vertex.wo, direction) / brdf.pdf();
+        let pdf_fwd = brdf.sampling_pdf(vertex.normal, vertex.wo, direction);
+        let pdf_rev = brdf.sampling_pdf(vertex.normal, direction, vertex.wo);
+
+        vertex.delta = brdf.is_delta_sample() || !pdf_fwd.is_finite();
+        if !is_camera && !vertex.delta {
+            weight = if pdf_fwd > 0. {
+                transposed_eval(brdf.as_ref(), vertex.normal, vertex.wo, direction) / pdf_fwd
+            } else {
+                Zero::zero()
+            };
+        }
+        let pdf_rev = if vertex.delta {
+            last_pdf = Some(f32::INFINITY);
+            pdf_dir = 0.;
+            0.
+        } else {
+            last_pdf = Some(pdf_fwd);
+            pdf_dir = pdf_fwd;
+            pdf_rev
+        };
+        throughput = throughput.mul_element_wise(weight);
+        ray = Ray::new(vertex.ray_origin(direction), direction);
+
+        let count = vertices.len();
+        vertices[count - 2].pdf_rev =
+            vertices[count - 1].convert_density(pdf_rev, &vertices[count - 2]);
+
+        if throughput == Zero::zero() {
+            break;
+        }
+    }
+    infinite_light
+}
+
+/// Brdf (times the cosine) of light arriving from `wo` and leaving towards `direction`
+fn transposed_eval(
+    brdf: &dyn Brdf,
+    normal: Vector3<f32>,
+    wo: Vector3<f32>,
+    direction: Vector3<f32>,
+) -> Vector3<f32> {
+    let cos_wo = normal.dot(wo).abs();
+    if cos_wo == 0. {
+        return Zero::zero();
+    }
+    brdf.eval_direct(normal, direction, wo) * (normal.dot(direction).abs() / cos_wo)
+}
+
+/// Light received at a camera subpath vertex from infinite lights
+fn direct_infinite_light(
+    scene: &Scene,
+    brdf: &dyn Brdf,
+    hit: &Hit,
+    normal: Vector3<f32>,
+    view_direction: Vector3<f32>,
+    sampler: &mut dyn Sampler,
+) -> Vector3<f32> {
+    let mut color = Vector3::zero();
+    for light in scene.lights.iter() {
+        if let Light::Directional { .. } = light {
+            let (radiance, direction, _) =
+                Renderer::get_light_info(light, hit, scene, None, &Spectrum::Rgb, sampler);
+            color += brdf
+                .eval_direct(normal, view_direction, -1. * direction)
+                .mul_element_wise(radiance);
+        }
+    }
+    color
+        + Renderer::compute_environment_light(
+            scene,
+            brdf,
+            hit,
+            normal,
+            view_direction,
+            None,
+            &Spectrum::Rgb,
+            sampler,
+        )
+        .color
+}
+
+/// Contribution of the path made of the `s` first light vertices and `t` first camera vertices (MIS weighted)
+fn connect(
+    scene: &Scene,
+    light_vertices: &[PathVertex],
+    camera_vertices: &[PathVertex],
+    s: usize,
+    t: usize,
+) -> Vector3<f32> {
+    let pt = &camera_vertices[t - 1];
+    let contribution = if s == 0 {
+        // The camera subpath reached an emitter
+        match pt.emitter() {
+            Some(Emitter::Sphere(light)) => {
+                pt.throughput.mul_element_wise(light.surface_radiance())
+            }
+            Some(Emitter::Surface(radiance)) => pt.throughput.mul_element_wise(radiance),
+            _ => return Zero::zero(),
+        }
+    } else {
+        let qs = &light_vertices[s - 1];
+        if !matches!(pt.kind, VertexKind::Surface { .. }) {
+            return Zero::zero();
+        }
+        let dist2 = (qs.position - pt.position).magnitude2();
+        let contribution = qs
+            .throughput
+            .mul_element_wise(qs.eval_light(pt))
+            .mul_element_wise(pt.eval(qs))
+            .mul_element_wise(pt.throughput)
+            / dist2;
+        if contribution == Zero::zero() {
+            return Zero::zero();
+        }
+        contribution * visibility(scene, pt, qs)
+    };
+    if contribution == Zero::zero() {
+        return Zero::zero();
+    }
+    contribution * mis_weight(scene, light_vertices, camera_vertices, s, t)
+}
+
+/// Fraction of light going from `from` to `to` through the occluders
+fn visibility(scene: &Scene, from: &PathVertex, to: &PathVertex) -> f32 {
+    let direction = (to.position - from.position).normalize();
+    let origin = from.ray_origin(direction);
+    let target = to.ray_origin(-direction);
+    let to_target = target - origin;
+    let dist = to_target.magnitude();
+    if dist == 0. {
+        return 1.;
+    }
+    let ray = Ray::new(origin, to_target / dist);
+    Renderer::shadow_transmittance(scene, &ray, dist * 0.999)
+}
+
+/// Power heuristic weight of a strategy among all the ones able to generate the same path
+fn mis_weight(
+    scene: &Scene,
+    light_vertices: &[PathVertex],
+    camera_vertices: &[PathVertex],
+    s: usize,
+    t: usize,
+) -> f32 {
+    if s + t == 2 {
+        return 1.;
+    }
+
+    // Densities (forward, reverse) and delta flag of the vertices once connected
+    let mut camera: Vec<_> = camera_vertices[..t]
+        .iter()
+        .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+        .collect();
+    let mut light: Vec<_> = light_vertices[..s]
+        .iter()
+        .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+        .collect();
+
+    let pt = &camera_vertices[t - 1];
+    let pt_minus = &camera_vertices[t - 2];
+    if s > 0 {
+        let qs = &light_vertices[s - 1];
+        let qs_minus = (s > 1).then(|| &light_vertices[s - 2]);
+        camera[t - 1].1 = qs.pdf(qs_minus, pt);
+        camera[t - 2].1 = pt.pdf(Some(qs), pt_minus);
+        light[s - 1].1 = pt.pdf(Some(pt_minus), qs);
+        light[s - 1].2 = false;
+        if let Some(qs_minus) = qs_minus {
+            light[s - 2].1 = qs.pdf(Some(pt), qs_minus);
+        }
+    } else {
+        let Some(emitter) = pt.emitter() else {
+            return 0.;
+        };
+        let direction = (pt_minus.position - pt.position).normalize();
+        camera[t - 1].1 = pt.light_origin_pdf(scene);
+        let pdf = emitter.direction_pdf(pt.geometric_normal, direction);
+        camera[t - 2].1 = pt.convert_density(pdf, pt_minus);
+    }
+    camera[t - 1].2 = false;
+
+    let remap = |pdf: f32| if pdf != 0. { pdf } else { 1. };
+    let mut sum = 0.;
+
+    // Strategies with fewer camera vertices (connections to the camera itself are not used)
+    let mut ratio = 1.;
+    for i in (2..t).rev() {
+        ratio *= remap(camera[i].1) / remap(camera[i].0);
+        if !camera[i].2 && !camera[i - 1].2 {
+            sum += ratio * ratio;
+        }
+    }
+
+    // Strategies with fewer light vertices
+    let mut ratio = 1.;
+    for i in (0..s).rev() {
+        ratio *= remap(light[i].1) / remap(light[i].0);
+        let is_delta_light = match i {
+            0 => {
+                matches!(&light_vertices[0].kind, VertexKind::Light(emitter) if emitter.is_delta())
+            }
+            _ => light[i - 1].2,
+        };
+        if !light[i].2 && !is_delta_light {
+            sum += ratio * ratio;
+        }
+    }
+
+    1. / (1. + sum)
+}
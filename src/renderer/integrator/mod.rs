@@ -0,0 +1,118 @@
+mod bdpt;
+mod path;
+mod photon_map;
+mod pppm;
+mod pssmlt;
+
+use super::passes::LightPasses;
+use super::sampler::Sampler;
+use super::Ray;
+use crate::config::Profile;
+use crate::Scene;
+pub use bdpt::Bdpt;
+use cgmath::*;
+pub use path::PathTracer;
+pub use pppm::Pppm;
+pub use pssmlt::Pssmlt;
+use serde::Deserialize;
+
+/// Light transport algorithm computing the radiance received along camera rays
+pub trait Integrator: Sync {
+    /// Whether the integrator handles every feature used by the scene
+    fn supports(&self, _scene: &Scene) -> bool {
+        true
+    }
+
+    /// Whether the integrator carries sampled wavelengths along the paths in spectral rendering
+    fn spectral(&self) -> bool {
+        false
+    }
+
+    /// Prepare a sample pass (starting at 1), called before the pixels of the pass are rendered
+    fn start_pass(&mut self, _profile: &Profile, _scene: &Scene, _pass: usize) {}
+
+    /// Whether the radiance doesn't depend on the pass, so pixels can be rendered independently
+    fn independent_passes(&self) -> bool {
+        true
+    }
+
+    /// Radiance received by the camera along a ray
+    fn radiance(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32>;
+
+    /// Whether the integrator splits the light into the light passes
+    fn light_passes(&self) -> bool {
+        false
+    }
+
+    /// Radiance received by the camera along a ray, also split into the light passes
+    fn radiance_passes(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+        _passes: &mut LightPasses,
+    ) -> Vector3<f32> {
+        self.radiance(profile, scene, ray, sampler)
+    }
+
+    /// Number of rounds of an integrator rendering the whole frame at once instead of sampling the
+    /// pixels by passes (None). Such renders have no passes, tiles nor checkpoints.
+    fn frame_rounds(&self, _profile: &Profile) -> Option<usize> {
+        None
+    }
+
+    /// Render the whole frame of an integrator with frame rounds, calling `progress` with the
+    /// current estimate after each round
+    fn render_frame(
+        &self,
+        _profile: &Profile,
+        _scene: &Scene,
+        _progress: &mut dyn FnMut(&[Vector3<f32>]),
+    ) -> Vec<Vector3<f32>> {
+        unreachable!("the integrator samples the pixels by passes")
+    }
+}
+
+#[derive(Default, Copy, Debug, Clone, Deserialize, PartialEq, Eq)]
+pub enum IntegratorType {
+    /// Unidirectional path tracing
+    #[serde(rename = "PATH")]
+    #[default]
+    Path,
+    /// Bidirectional path tracing
+    #[serde(rename = "BDPT")]
+    Bdpt,
+    /// Primary sample space Metropolis light transport
+    #[serde(rename = "PSSMLT")]
+    Pssmlt,
+    /// Path tracing with caustics from probabilistic progressive photon mapping. It replaces
+    /// stochastic progressive photon mapping, whose `SPPM` name is still accepted.
+    #[serde(rename = "PPPM", alias = "SPPM")]
+    Pppm,
+}
+
+pub fn get_integrator(integrator_type: IntegratorType) -> Box<dyn Integrator> {
+    match integrator_type {
+        IntegratorType::Path => Box::new(PathTracer),
+        IntegratorType::Bdpt => Box::new(Bdpt),
+        // Metropolis light transport mutates the paths of the path tracer
+        IntegratorType::Pssmlt => Box::new(Pssmlt::new(Box::new(PathTracer))),
+        IntegratorType::Pppm => Box::new(Pppm::default()),
+    }
+}
+
+/// Whether the scene has no media nor subsurface scattering
+fn supports_surfaces_only(scene: &Scene) -> bool {
+    !scene.has_media
+        && scene
+            .models
+            .iter()
+            .all(|model| model.get_material().subsurface.is_none())
+}
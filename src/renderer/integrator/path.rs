@@ -0,0 +1,640 @@
+use super::Integrator;
+use crate::config::Profile;
+use crate::renderer::brdf::*;
+use crate::renderer::material_sample::MaterialSample;
+use crate::renderer::passes::{LightPasses, VertexLight};
+use crate::renderer::sampler::Sampler;
+use crate::renderer::spectrum::Spectrum;
+use crate::renderer::utils::*;
+use crate::renderer::{subsurface, DirectLight, Hit, Ray, Renderer};
+use crate::scene::internal::{Medium, Model, Subsurface};
+use crate::Scene;
+use cgmath::*;
+use derivative::Derivative;
+
+/// Unidirectional path tracing with next event estimation
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn spectral(&self) -> bool {
+        true
+    }
+
+    fn radiance(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        render_pixel(profile, scene, ray, None, sampler, None)
+    }
+
+    fn light_passes(&self) -> bool {
+        true
+    }
+
+    fn radiance_passes(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+        passes: &mut LightPasses,
+    ) -> Vector3<f32> {
+        render_pixel(profile, scene, ray, None, sampler, Some(passes))
+    }
+}
+
+/// Light of the caustic paths estimated by another technique, the path tracer then stops tracing
+/// the specular bounces following a diffuse one towards the lights
+pub(super) trait Caustics {
+    /// Caustics reflected by the brdf towards the view direction, carried by at most `bounces`
+    /// bounces so the whole path stays within the bounces of the profile
+    fn gather(
+        &self,
+        brdf: &dyn Brdf,
+        position: Vector3<f32>,
+        normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        bounces: usize,
+    ) -> Vector3<f32>;
+}
+
+#[derive(Derivative)]
+#[derivative(Default)]
+struct RadianceInfo {
+    #[derivative(Default(value = "Vector3::zero()"))]
+    color: Vector3<f32>,
+    #[derivative(Default(value = "Vector3::new(1., 1., 1.)"))]
+    throughput: Vector3<f32>,
+    /// Solid angle pdf of the brdf sample that generated the current ray (None for camera rays)
+    last_pdf: Option<f32>,
+    /// Light gathered at the last scattering event, for the render passes
+    vertex: VertexLight,
+    /// Colors or wavelengths the light is carried as
+    spectrum: Spectrum,
+}
+
+struct SurfaceInfo<'a> {
+    hit: Hit,
+    model: &'a Model,
+    material: MaterialSample,
+    normal: Vector3<f32>,
+    /// Medium crossed by the ray to reach the surface
+    medium: Option<&'a Medium>,
+}
+
+/// Render the color of a pixel given a ray and the scene.
+/// Caustics are gathered from the estimator when given.
+/// The light is also split into the light passes when given.
+/// In spectral rendering, the light of the sampled wavelengths is converted to RGB.
+pub(super) fn render_pixel(
+    profile: &Profile,
+    scene: &Scene,
+    ray: Ray,
+    caustics: Option<&dyn Caustics>,
+    sampler: &mut dyn Sampler,
+    passes: Option<&mut LightPasses>,
+) -> Vector3<f32> {
+    let spectrum = Spectrum::new(profile, sampler);
+    let Some(passes) = passes else {
+        let radiance = trace_path(profile, scene, ray, caustics, sampler, None, spectrum);
+        return spectrum.to_rgb(radiance);
+    };
+    let mut light = LightPasses::default();
+    let radiance = trace_path(
+        profile,
+        scene,
+        ray,
+        caustics,
+        sampler,
+        Some(&mut light),
+        spectrum,
+    );
+    passes.add_converted(&light, |light| spectrum.to_rgb(light));
+    spectrum.to_rgb(radiance)
+}
+
+/// Light carried along a path from the camera, in the colors or wavelengths of the spectrum
+fn trace_path(
+    profile: &Profile,
+    scene: &Scene,
+    mut ray: Ray,
+    caustics: Option<&dyn Caustics>,
+    sampler: &mut dyn Sampler,
+    mut passes: Option<&mut LightPasses>,
+    spectrum: Spectrum,
+) -> Vector3<f32> {
+    let mut rad_info = RadianceInfo {
+        spectrum,
+        ..Default::default()
+    };
+    // Medium the ray is traveling through
+    let mut medium = scene.medium.as_ref();
+    // Whether the ray comes from specular bounces following a diffuse or glossy one
+    let mut diffuse_bounce = false;
+    let mut caustic = false;
+    // Scattering events along the path, the first one going through a diffuse lobe or not
+    let mut scatterings = 0;
+    let mut first_diffuse = false;
+
+    for bounce in 0..(profile.bounces + 1) {
+        // Test intersection
+        let intersections = ray_cast(scene, &ray);
+
+        let mut surface_info = None;
+        // Invisible boundaries of media crossed before reaching the surface
+        let mut medium_boundaries = vec![];
+        for (hit, model) in intersections {
+            let material_sample = hit.get_material_sample(model);
+            let normal = hit.get_normal(model.get_material());
+            let opacity = material_sample.opacity;
+
+            // Alpha transparency
+            let is_opaque = opacity >= 1. || (opacity > 0.001 && sampler.next_1d() < opacity);
+            if !is_opaque && model.get_material().medium.is_some() {
+                medium_boundaries.push((hit, model));
+                continue;
+            }
+
+            surface_info = Some(SurfaceInfo {
+                hit,
+                model,
+                material: material_sample,
+                normal,
+                medium: None,
+            });
+
+            if is_opaque {
+                // Consider the surface as opaque and stop iterating over the intersections
+                break;
+            }
+        }
+
+        // Check if we hit a light sphere in front of the surface
+        let surface_dist = surface_info
+            .as_ref()
+            .map_or(f32::INFINITY, |surface_info| surface_info.hit.get_dist());
+        let visible_light =
+            Renderer::get_visible_light(scene, &ray, surface_dist, rad_info.last_pdf);
+        let end_dist = visible_light.map_or(surface_dist, |(dist, _)| dist);
+
+        // Travel through the media up to the light or the surface
+        let scattering_dist;
+        (scattering_dist, medium) = sample_media(
+            scene,
+            medium,
+            &ray,
+            &medium_boundaries,
+            end_dist,
+            &mut rad_info,
+            sampler,
+        );
+        let compute_indirect = bounce < profile.bounces;
+
+        if let (Some(dist), Some(scattering_medium)) = (scattering_dist, medium) {
+            (rad_info, ray) = compute_medium_scattering(
+                scene,
+                rad_info,
+                scattering_medium,
+                &ray,
+                dist,
+                compute_indirect,
+                sampler,
+            );
+            if let Some(passes) = passes.as_deref_mut() {
+                passes.add_vertex(&rad_info.vertex, scatterings, first_diffuse);
+            }
+            // Scattering in media counts as diffuse
+            first_diffuse |= scatterings == 0;
+            scatterings += 1;
+        } else {
+            if let Some((_, light_radiance)) = visible_light {
+                if caustic && caustics.is_some() {
+                    // Already gathered by the estimator
+                    return rad_info.color;
+                }
+                let light_radiance = rad_info.spectrum.unbounded(light_radiance);
+                let light = rad_info.throughput.mul_element_wise(light_radiance);
+                if let Some(passes) = passes {
+                    passes.add_emitted(light, scatterings, first_diffuse);
+                }
+                return rad_info.color + light;
+            }
+
+            // Check if we hit nothing (background)
+            let Some(mut surface_info) = surface_info else {
+                let background = Renderer::get_background(scene, &ray, rad_info.last_pdf);
+                let background = rad_info.spectrum.unbounded(background);
+                let background = rad_info.throughput.mul_element_wise(background);
+                if let Some(passes) = passes {
+                    passes.add_emitted(background, scatterings, first_diffuse);
+                }
+                return rad_info.color + background;
+            };
+            surface_info.medium = medium;
+            rad_info
+                .spectrum
+                .material(&mut surface_info.material, &mut rad_info.throughput);
+
+            let view_direction = -1. * ray.direction;
+
+            let mut brdf = get_brdf(
+                &surface_info.material,
+                profile.brdf,
+                surface_info.hit.is_backface(),
+                sampler,
+            );
+            if let Some(estimator) = caustics {
+                // Keep the length of the whole path within the bounces
+                let caustics = estimator.gather(
+                    brdf.as_ref(),
+                    surface_info.hit.get_position(),
+                    surface_info.normal,
+                    view_direction,
+                    profile.bounces.saturating_sub(bounce + 1),
+                );
+                let caustics = rad_info.throughput.mul_element_wise(caustics);
+                rad_info.color += caustics;
+                if let Some(passes) = passes.as_deref_mut() {
+                    // Caustics are gathered by the diffuse lobes
+                    passes.add_indirect(caustics, scatterings == 0 || first_diffuse);
+                }
+            }
+            (rad_info, ray) = compute_radiance(
+                scene,
+                rad_info,
+                &surface_info,
+                brdf.as_mut(),
+                view_direction,
+                compute_indirect,
+                sampler,
+            );
+            if let Some(passes) = passes.as_deref_mut() {
+                passes.add_vertex(&rad_info.vertex, scatterings, first_diffuse);
+            }
+            if scatterings == 0 {
+                first_diffuse = brdf.is_diffuse_sample();
+            }
+            scatterings += 1;
+            if brdf.is_delta_sample() {
+                caustic = diffuse_bounce;
+            } else {
+                diffuse_bounce = true;
+                caustic = false;
+            }
+
+            // Light refracted into a subsurface medium walks inside the model before leaving it
+            if let Some(subsurface) = &surface_info.material.subsurface {
+                let is_entering = !surface_info.hit.is_backface()
+                    && ray.direction.dot(surface_info.hit.get_geometric_normal()) < 0.;
+                if compute_indirect && is_entering {
+                    let color = rad_info.color;
+                    let Some(res) = compute_subsurface(
+                        scene,
+                        rad_info,
+                        &surface_info,
+                        subsurface,
+                        &ray,
+                        sampler,
+                    ) else {
+                        return color;
+                    };
+                    (rad_info, ray) = res;
+                    if let Some(passes) = passes.as_deref_mut() {
+                        passes.add_vertex(&rad_info.vertex, scatterings, first_diffuse);
+                    }
+                    scatterings += 1;
+                }
+            }
+
+            // Going through the surface of a model filled with a medium
+            if surface_info.model.get_material().medium.is_some() {
+                medium = Renderer::crossed_medium(
+                    scene,
+                    surface_info.model,
+                    &surface_info.hit,
+                    ray.direction,
+                );
+            }
+        }
+
+        if rad_info.throughput.magnitude2() < 0.00001 {
+            return rad_info.color;
+        }
+
+        if bounce > 3 && russian_roulette(&mut rad_info.throughput, sampler) {
+            return rad_info.color;
+        }
+    }
+    rad_info.color
+}
+
+fn compute_radiance(
+    scene: &Scene,
+    rad_info: RadianceInfo,
+    surface_info: &SurfaceInfo,
+    brdf: &mut dyn Brdf,
+    view_direction: Vector3<f32>,
+    compute_indirect: bool,
+    sampler: &mut dyn Sampler,
+) -> (RadianceInfo, Ray) {
+    let mut color = rad_info.color;
+    let mut throughput = rad_info.throughput;
+    let mut ray = Default::default();
+    let mut last_pdf = None;
+
+    // Emissive (weighted against emissive light sampling if reached by a brdf sample)
+    let emissive_weight = match rad_info.last_pdf {
+        Some(brdf_pdf) if surface_info.material.emissive != Zero::zero() => {
+            let light_pdf = emissive_light_pdf(scene, surface_info, view_direction);
+            power_heuristic(brdf_pdf, light_pdf)
+        }
+        _ => 1.,
+    };
+    let emitted = throughput.mul_element_wise(surface_info.material.emissive) * emissive_weight;
+    color += emitted;
+
+    // Direct light computation
+    let direct_light = compute_direct_light(
+        scene,
+        brdf,
+        &surface_info.hit,
+        surface_info.normal,
+        view_direction,
+        surface_info.medium,
+        &rad_info.spectrum,
+        sampler,
+    );
+    let vertex = VertexLight {
+        emitted,
+        direct: throughput.mul_element_wise(direct_light.color),
+        direct_diffuse: throughput.mul_element_wise(direct_light.diffuse),
+    };
+    color += vertex.direct;
+
+    // Indirect light computation
+    if compute_indirect {
+        let direction = brdf.sample(surface_info.normal, view_direction, sampler);
+        // The ray may go through the surface
+        ray = Ray::new(
+            Renderer::offset_origin(&surface_info.hit, direction),
+            direction,
+        );
+        let sample_radiance =
+            brdf.eval_indirect(surface_info.normal, view_direction, ray.direction);
+        let weighted_sample_radiance = sample_radiance / brdf.pdf();
+        throughput = throughput.mul_element_wise(weighted_sample_radiance);
+        last_pdf = Some(if brdf.is_delta_sample() {
+            f32::INFINITY
+        } else {
+            brdf.sampling_pdf(surface_info.normal, view_direction, ray.direction)
+        });
+    }
+
+    (
+        RadianceInfo {
+            color,
+            throughput,
+            last_pdf,
+            vertex,
+            spectrum: rad_info.spectrum,
+        },
+        ray,
+    )
+}
+
+/// Light received from every light source at a point (MIS weighted)
+#[allow(clippy::too_many_arguments)]
+fn compute_direct_light(
+    scene: &Scene,
+    brdf: &dyn Brdf,
+    hit: &Hit,
+    normal: Vector3<f32>,
+    view_direction: Vector3<f32>,
+    medium: Option<&Medium>,
+    spectrum: &Spectrum,
+    sampler: &mut dyn Sampler,
+) -> DirectLight {
+    let mut direct_light = DirectLight::zero();
+
+    for light in scene.lights.iter() {
+        let (light_radiance, light_direction, light_pdf) =
+            Renderer::get_light_info(light, hit, scene, medium, spectrum, sampler);
+        if light_radiance == Zero::zero() {
+            continue;
+        }
+        let reversed_light_dir = -1. * light_direction;
+
+        // Lights with a surface can also be reached by brdf samples
+        let weight = match light_pdf {
+            Some(light_pdf) => power_heuristic(
+                light_pdf,
+                brdf.sampling_pdf(normal, view_direction, reversed_light_dir),
+            ),
+            None => 1.,
+        };
+
+        let reflected =
+            |brdf_value: Vector3<f32>| brdf_value.mul_element_wise(light_radiance) * weight;
+        direct_light.color +=
+            reflected(brdf.eval_direct(normal, view_direction, reversed_light_dir));
+        direct_light.diffuse +=
+            reflected(brdf.eval_direct_diffuse(normal, view_direction, reversed_light_dir));
+    }
+
+    // Direct light from emissive geometry
+    if let Some(light_sample) = scene.emissive_lights.sample(&scene.models, sampler) {
+        let emissive_light = Renderer::compute_emissive_light(
+            scene,
+            brdf,
+            hit,
+            normal,
+            view_direction,
+            medium,
+            spectrum,
+            light_sample,
+        );
+        direct_light.color += emissive_light.color;
+        direct_light.diffuse += emissive_light.diffuse;
+    }
+
+    // Direct light from the environment
+    let environment_light = Renderer::compute_environment_light(
+        scene,
+        brdf,
+        hit,
+        normal,
+        view_direction,
+        medium,
+        spectrum,
+        sampler,
+    );
+    direct_light.color += environment_light.color;
+    direct_light.diffuse += environment_light.diffuse;
+
+    direct_light
+}
+
+/// Light scattered by a medium at a given distance along the ray
+fn compute_medium_scattering(
+    scene: &Scene,
+    rad_info: RadianceInfo,
+    medium: &Medium,
+    ray: &Ray,
+    dist: f32,
+    compute_indirect: bool,
+    sampler: &mut dyn Sampler,
+) -> (RadianceInfo, Ray) {
+    let position = ray.origin + ray.direction * dist;
+    let view_direction = -1. * ray.direction;
+    let mut phase = HenyeyGreenstein::new(medium.anisotropy);
+
+    // Scattering events have no surface, shadow rays start from the point itself
+    let normal = Vector3::zero();
+    let hit = Hit::Sphere {
+        dist,
+        position,
+        normal,
+        is_backface: false,
+    };
+
+    let direct_light = compute_direct_light(
+        scene,
+        &phase,
+        &hit,
+        normal,
+        view_direction,
+        Some(medium),
+        &rad_info.spectrum,
+        sampler,
+    );
+    let vertex = VertexLight {
+        emitted: Vector3::zero(),
+        direct: rad_info.throughput.mul_element_wise(direct_light.color),
+        direct_diffuse: rad_info.throughput.mul_element_wise(direct_light.diffuse),
+    };
+    let color = rad_info.color + vertex.direct;
+
+    let (ray, last_pdf) = if compute_indirect {
+        let direction = phase.sample(normal, view_direction, sampler);
+        let pdf = phase.sampling_pdf(normal, view_direction, direction);
+        (Ray::new(position, direction), Some(pdf))
+    } else {
+        (Default::default(), None)
+    };
+
+    (
+        RadianceInfo {
+            color,
+            throughput: rad_info.throughput,
+            last_pdf,
+            vertex,
+            spectrum: rad_info.spectrum,
+        },
+        ray,
+    )
+}
+
+/// Sample a scattering event in the media crossed by a ray up to `end_dist`.
+/// Returns the distance of the event (if any) and the medium the ray ends in.
+fn sample_media<'a>(
+    scene: &'a Scene,
+    mut medium: Option<&'a Medium>,
+    ray: &Ray,
+    boundaries: &[(Hit, &'a Model)],
+    end_dist: f32,
+    rad_info: &mut RadianceInfo,
+    sampler: &mut dyn Sampler,
+) -> (Option<f32>, Option<&'a Medium>) {
+    if !scene.has_media {
+        return (None, medium);
+    }
+    let (start, end) = clip_to_bounds(&scene.bounds, ray, end_dist).unwrap_or((0., 0.));
+
+    let mut sample_segment = |medium: Option<&Medium>, from: f32, to: f32| {
+        let (from, to) = (from.max(start), to.min(end));
+        let medium = rad_info.spectrum.medium(medium.filter(|_| to > from)?);
+        let (dist, weight) = medium.sample_distance(to - from, sampler);
+        rad_info.throughput = rad_info.throughput.mul_element_wise(weight);
+        dist.map(|dist| from + dist)
+    };
+
+    let mut segment_start = 0.;
+    for (hit, model) in boundaries.iter() {
+        if hit.get_dist() >= end_dist {
+            break;
+        }
+        if let Some(dist) = sample_segment(medium, segment_start, hit.get_dist()) {
+            return (Some(dist), medium);
+        }
+        medium = Renderer::crossed_medium(scene, model, hit, ray.direction);
+        segment_start = hit.get_dist();
+    }
+    (sample_segment(medium, segment_start, end_dist), medium)
+}
+
+/// Random walk inside a subsurface medium. The light transmitted out of the model is
+/// shaded as a diffuse transmission at the exit point.
+/// Returns None if the path was absorbed.
+fn compute_subsurface(
+    scene: &Scene,
+    rad_info: RadianceInfo,
+    surface_info: &SurfaceInfo,
+    subsurface: &Subsurface,
+    ray: &Ray,
+    sampler: &mut dyn Sampler,
+) -> Option<(RadianceInfo, Ray)> {
+    let (exit_hit, weight) = subsurface::random_walk(
+        surface_info.model,
+        subsurface,
+        surface_info.material.ior,
+        ray,
+        sampler,
+    )?;
+
+    let mut rad_info = RadianceInfo {
+        throughput: rad_info.throughput.mul_element_wise(weight),
+        last_pdf: None,
+        ..rad_info
+    };
+
+    let hit = exit_hit.to_front_face();
+    let normal = hit.get_normal(surface_info.model.get_material());
+    let mut material = hit.get_material_sample(surface_info.model);
+    rad_info
+        .spectrum
+        .material(&mut material, &mut rad_info.throughput);
+    // The emission was already accounted when entering the model
+    material.emissive = Zero::zero();
+    let exit_info = SurfaceInfo {
+        hit,
+        model: surface_info.model,
+        material,
+        normal,
+        medium: surface_info.medium,
+    };
+    let mut brdf = Lambert::new(Vector3::new(1., 1., 1.));
+    Some(compute_radiance(
+        scene, rad_info, &exit_info, &mut brdf, normal, true, sampler,
+    ))
+}
+
+/// Solid angle density of sampling the surface hit by the current ray with emissive light sampling
+fn emissive_light_pdf(
+    scene: &Scene,
+    surface_info: &SurfaceInfo,
+    view_direction: Vector3<f32>,
+) -> f32 {
+    let cos_light = surface_info
+        .hit
+        .get_geometric_normal()
+        .dot(view_direction)
+        .abs();
+    if cos_light < 0.0001 {
+        return 0.;
+    }
+    let dist = surface_info.hit.get_dist();
+    scene.emissive_lights.pdf(surface_info.model) * dist * dist / cos_light
+}
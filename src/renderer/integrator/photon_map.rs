@@ -0,0 +1,310 @@
+use super::bdpt::Emitter;
+use crate::config::Profile;
+use crate::renderer::brdf::{get_brdf, transform_to_world, Brdf};
+use crate::renderer::sampler::{IndependentSampler, Sampler};
+use crate::renderer::utils::*;
+use crate::renderer::{Hit, Ray, Renderer};
+use crate::scene::internal::{Light, Model};
+use crate::Scene;
+use cgmath::*;
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Fraction of the photons kept from one pass to the next in progressive photon mapping
+const ALPHA: f32 = 2. / 3.;
+
+/// Photon stored on a surface after at least one specular bounce
+#[derive(Clone, Copy)]
+struct Photon {
+    position: Vector3<f32>,
+    /// Direction the photon comes from
+    direction: Vector3<f32>,
+    flux: Vector3<f32>,
+    /// Number of specular bounces since the light
+    bounces: usize,
+    /// Splitting axis of the kd-tree node
+    axis: usize,
+}
+
+/// Caustic photons of a pass of progressive photon mapping, stored in a balanced kd-tree
+pub struct PhotonMap {
+    /// Nodes of the kd-tree, the root of each subtree being the median of its slice
+    photons: Vec<Photon>,
+    /// Gather radius of the pass
+    radius: f32,
+}
+
+impl PhotonMap {
+    /// Trace the photons of a pass (starting at 1). The gather radius shrinks from one pass to
+    /// the next so the average of the passes converges (probabilistic progressive photon mapping).
+    pub fn trace(profile: &Profile, scene: &Scene, pass: usize) -> Self {
+        let mut photons: Vec<Photon> = (0..profile.photons)
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let seed = ((pass - 1) * profile.photons + i) as u64;
+                trace_photon(profile, scene, &mut IndependentSampler::new(seed))
+            })
+            .collect();
+        build(&mut photons);
+
+        Self {
+            photons,
+            radius: pass_radius(profile.photon_radius, pass),
+        }
+    }
+
+    /// Radiance reflected towards `view_direction` estimated from the photons around `position`.
+    /// Photons with more than `max_bounces` specular bounces are ignored.
+    pub fn estimate(
+        &self,
+        brdf: &dyn Brdf,
+        position: Vector3<f32>,
+        normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        max_bounces: usize,
+    ) -> Vector3<f32> {
+        let mut radiance = Vector3::zero();
+        if max_bounces == 0 {
+            return radiance;
+        }
+        gather(
+            &self.photons,
+            position,
+            self.radius * self.radius,
+            &mut |photon| {
+                // Remove the cosine included by the brdf
+                let cos = normal.dot(photon.direction).abs();
+                if cos > 0. && photon.bounces <= max_bounces {
+                    radiance += brdf
+                        .eval_direct(normal, view_direction, photon.direction)
+                        .mul_element_wise(photon.flux)
+                        / cos;
+                }
+            },
+        );
+        radiance / (PI * self.radius * self.radius)
+    }
+}
+
+/// Gather radius of a pass (starting at 1), the area shrinking by (i + α) / (i + 1) after pass i
+fn pass_radius(radius: f32, pass: usize) -> f32 {
+    let mut radius2 = radius * radius;
+    for i in 1..pass {
+        radius2 *= (i as f32 + ALPHA) / (i as f32 + 1.);
+    }
+    radius2.sqrt()
+}
+
+/// Sort the photons into a balanced kd-tree, splitting along the largest extent
+fn build(photons: &mut [Photon]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (min, max) = photons.iter().fold(
+        (photons[0].position, photons[0].position),
+        |(min, max), photon| {
+            (
+                min.zip(photon.position, f32::min),
+                max.zip(photon.position, f32::max),
+            )
+        },
+    );
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let median = photons.len() / 2;
+    photons.select_nth_unstable_by(median, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+    photons[median].axis = axis;
+    let (left, right) = photons.split_at_mut(median);
+    build(left);
+    build(&mut right[1..]);
+}
+
+/// Call `f` on every photon closer than the radius to the position
+fn gather<F: FnMut(&Photon)>(photons: &[Photon], position: Vector3<f32>, radius2: f32, f: &mut F) {
+    if photons.is_empty() {
+        return;
+    }
+    let median = photons.len() / 2;
+    let photon = &photons[median];
+    if (photon.position - position).magnitude2() <= radius2 {
+        f(photon);
+    }
+
+    let dist = position[photon.axis] - photon.position[photon.axis];
+    let (near, far) = if dist < 0. {
+        (&photons[..median], &photons[median + 1..])
+    } else {
+        (&photons[median + 1..], &photons[..median])
+    };
+    gather(near, position, radius2, f);
+    if dist * dist <= radius2 {
+        gather(far, position, radius2, f);
+    }
+}
+
+/// Emit a photon from a random light and return the photons it leaves after specular bounces
+fn trace_photon(profile: &Profile, scene: &Scene, sampler: &mut dyn Sampler) -> Vec<Photon> {
+    let mut photons = vec![];
+    if scene.lights.is_empty() {
+        return photons;
+    }
+    let index =
+        ((sampler.next_1d() * scene.lights.len() as f32) as usize).min(scene.lights.len() - 1);
+    let Some((mut ray, flux)) = emit(scene, &scene.lights[index], sampler) else {
+        return photons;
+    };
+    let mut flux = flux * (scene.lights.len() as f32 / profile.photons as f32);
+
+    // The camera path needs at least one bounce to reach the photons
+    for bounce in 0..profile.bounces {
+        let Some((hit, model)) = closest_surface(scene, &ray, sampler) else {
+            break;
+        };
+        if bounce > 0 {
+            photons.push(Photon {
+                position: hit.get_position(),
+                direction: -1. * ray.direction,
+                flux,
+                bounces: bounce,
+                axis: 0,
+            });
+        }
+
+        // Only specular bounces are followed, other paths are handled by the path tracer
+        let material_sample = hit.get_material_sample(model);
+        let normal = hit.get_normal(model.get_material());
+        let view_direction = -1. * ray.direction;
+        let mut brdf = get_brdf(&material_sample, profile.brdf, hit.is_backface(), sampler);
+        let direction = brdf.sample(normal, view_direction, sampler);
+        if !brdf.is_delta_sample() {
+            break;
+        }
+        flux = flux.mul_element_wise(brdf.eval_indirect(normal, view_direction, direction))
+            / brdf.pdf();
+        if flux == Zero::zero() {
+            break;
+        }
+        ray = Ray::new(Renderer::offset_origin(&hit, direction), direction);
+    }
+    photons
+}
+
+/// Sample the ray of a photon leaving a light and its flux
+fn emit(scene: &Scene, light: &Light, sampler: &mut dyn Sampler) -> Option<(Ray, Vector3<f32>)> {
+    let (emitter, position, normal, position_pdf) = match light {
+        Light::Directional { direction, color } => {
+            // Photons cross a disk covering the scene
+            let direction = direction.normalize();
+            let center = (scene.bounds.min + scene.bounds.max) / 2.;
+            let radius = (scene.bounds.max - scene.bounds.min).magnitude() / 2.;
+            let (r1, r2) = sampler.next_2d();
+            let r = radius * r1.sqrt();
+            let phi = 2. * PI * r2;
+            let offset =
+                transform_to_world(Vector3::new(r * phi.cos(), 0., r * phi.sin()), direction);
+            let origin = center + offset - direction * radius;
+            return Some((Ray::new(origin, direction), color * (PI * radius * radius)));
+        }
+        Light::Point { position, size, .. } if *size > 0. => {
+            let normal = uniform_sphere(sampler);
+            let pdf = 1. / (4. * PI * size * size);
+            (
+                Emitter::Sphere(light),
+                position + normal * *size,
+                normal,
+                pdf,
+            )
+        }
+        Light::Point { position, .. } | Light::Spot { position, .. } => {
+            (Emitter::Point(light), *position, Zero::zero(), 1.)
+        }
+    };
+
+    let direction = emitter.sample_direction(normal, sampler);
+    let pdf = emitter.direction_pdf(normal, direction) * position_pdf;
+    if pdf <= 0. {
+        return None;
+    }
+    let flux = emitter.emission(normal, direction) / pdf;
+    let origin = position + normal * Renderer::NORMAL_BIAS;
+    Some((Ray::new(origin, direction), flux))
+}
+
+/// First surface hit by a ray, alpha transparent surfaces being randomly skipped
+fn closest_surface<'a>(
+    scene: &'a Scene,
+    ray: &Ray,
+    sampler: &mut dyn Sampler,
+) -> Option<(Hit, &'a Model)> {
+    ray_cast(scene, ray).into_iter().find(|(hit, model)| {
+        let opacity = hit.get_material_sample(model).opacity;
+        opacity >= 1. || (opacity > 0.001 && sampler.next_1d() < opacity)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::sampler::IndependentSampler;
+
+    #[test]
+    fn gather_matches_brute_force() {
+        let mut sampler = IndependentSampler::new(0);
+        let mut photons: Vec<Photon> = (0..2000)
+            .map(|_| {
+                let (x, y) = sampler.next_2d();
+                Photon {
+                    // Flattened along z, so the splitting axis varies
+                    position: Vector3::new(x * 4., y, sampler.next_1d() * 0.1),
+                    direction: Vector3::unit_y(),
+                    flux: Vector3::zero(),
+                    bounces: 1,
+                    axis: 0,
+                }
+            })
+            .collect();
+        let all = photons.clone();
+        build(&mut photons);
+
+        for _ in 0..100 {
+            let (x, y) = sampler.next_2d();
+            let position = Vector3::new(x * 4., y, 0.05);
+            let radius2 = sampler.next_1d() * 0.1;
+            let mut found = vec![];
+            gather(&photons, position, radius2, &mut |photon| {
+                found.push(photon.position)
+            });
+            let mut expected: Vec<_> = all
+                .iter()
+                .filter(|photon| (photon.position - position).magnitude2() <= radius2)
+                .map(|photon| photon.position)
+                .collect();
+            let key = |p: &Vector3<f32>| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+            found.sort_by_key(key);
+            expected.sort_by_key(key);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn radius_reduction() {
+        assert_eq!(pass_radius(0.5, 1), 0.5);
+        for pass in 1..100 {
+            let (radius, next) = (pass_radius(0.5, pass), pass_radius(0.5, pass + 1));
+            let ratio = (next * next) / (radius * radius);
+            let expected = (pass as f32 + ALPHA) / (pass as f32 + 1.);
+            assert!((ratio - expected).abs() < 1e-5);
+        }
+        // The radius goes to zero, slowly enough for the photons gathered to keep growing
+        let radius = pass_radius(0.5, 10_000);
+        assert!(radius < 0.25);
+        assert!(radius * radius * 10_000. > 100. * 0.25);
+    }
+}
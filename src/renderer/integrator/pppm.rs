@@ -0,0 +1,72 @@
+use super::path::{render_pixel, Caustics};
+use super::photon_map::PhotonMap;
+use super::{supports_surfaces_only, Integrator};
+use crate::config::Profile;
+use crate::renderer::brdf::Brdf;
+use crate::renderer::passes::LightPasses;
+use crate::renderer::sampler::Sampler;
+use crate::renderer::Ray;
+use crate::Scene;
+use cgmath::*;
+
+/// Path tracing with caustics estimated from a photon map traced at each pass
+#[derive(Default)]
+pub struct Pppm {
+    photon_map: Option<PhotonMap>,
+}
+
+impl Integrator for Pppm {
+    fn supports(&self, scene: &Scene) -> bool {
+        supports_surfaces_only(scene)
+    }
+
+    fn start_pass(&mut self, profile: &Profile, scene: &Scene, pass: usize) {
+        self.photon_map = Some(PhotonMap::trace(profile, scene, pass));
+    }
+
+    fn independent_passes(&self) -> bool {
+        false
+    }
+
+    fn radiance(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        render_pixel(profile, scene, ray, Some(self), sampler, None)
+    }
+
+    fn light_passes(&self) -> bool {
+        true
+    }
+
+    fn radiance_passes(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+        passes: &mut LightPasses,
+    ) -> Vector3<f32> {
+        render_pixel(profile, scene, ray, Some(self), sampler, Some(passes))
+    }
+}
+
+impl Caustics for Pppm {
+    fn gather(
+        &self,
+        brdf: &dyn Brdf,
+        position: Vector3<f32>,
+        normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        bounces: usize,
+    ) -> Vector3<f32> {
+        let photon_map = self
+            .photon_map
+            .as_ref()
+            .expect("the photon map is traced at the start of the pass");
+        photon_map.estimate(brdf, position, normal, view_direction, bounces)
+    }
+}
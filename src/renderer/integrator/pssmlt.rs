@@ -0,0 +1,317 @@
+use super::Integrator;
+use crate::config::Profile;
+use crate::renderer::distribution::Distribution1D;
+use crate::renderer::sampler::Sampler;
+use crate::renderer::utils::luminance;
+use crate::renderer::{Ray, Renderer};
+use crate::Scene;
+use cgmath::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Number of independent paths used to estimate the image brightness and seed the chains
+const BOOTSTRAP_SAMPLES: usize = 100_000;
+/// Number of Markov chains run in parallel
+const CHAINS: usize = 1000;
+/// Probability of replacing every primary sample instead of perturbing them
+const LARGE_STEP_PROBABILITY: f32 = 0.3;
+/// Standard deviation of small step perturbations
+const SIGMA: f32 = 0.01;
+
+/// Primary sample space Metropolis light transport, mutating the paths of a kernel integrator.
+/// The whole frame is rendered by rounds of mutations instead of sampling the pixels by passes.
+pub struct Pssmlt {
+    kernel: Box<dyn Integrator>,
+}
+
+impl Pssmlt {
+    pub fn new(kernel: Box<dyn Integrator>) -> Self {
+        Self { kernel }
+    }
+}
+
+impl Integrator for Pssmlt {
+    fn supports(&self, scene: &Scene) -> bool {
+        self.kernel.supports(scene)
+    }
+
+    fn spectral(&self) -> bool {
+        self.kernel.spectral()
+    }
+
+    fn independent_passes(&self) -> bool {
+        false
+    }
+
+    fn radiance(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        ray: Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Vector3<f32> {
+        self.kernel.radiance(profile, scene, ray, sampler)
+    }
+
+    fn frame_rounds(&self, profile: &Profile) -> Option<usize> {
+        Some(profile.mutations)
+    }
+
+    fn render_frame(
+        &self,
+        profile: &Profile,
+        scene: &Scene,
+        progress: &mut dyn FnMut(&[Vector3<f32>]),
+    ) -> Vec<Vector3<f32>> {
+        render(profile, scene, self.kernel.as_ref(), progress)
+    }
+}
+
+/// Value of a primary sample with the state needed to undo a rejected mutation
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f32,
+    /// Iteration of the last modification
+    last_modification: usize,
+    backup_value: f32,
+    backup_modification: usize,
+}
+
+/// Sampler replaying a vector of primary samples in [0, 1).
+/// Each iteration mutates the vector, samples being lazily updated when the path tracer reads them.
+struct PrimarySampler {
+    samples: Vec<PrimarySample>,
+    /// Random generator driving the mutations
+    rand_gen: StdRng,
+    iteration: usize,
+    last_large_step: usize,
+    large_step: bool,
+    /// Index of the next primary sample to read
+    index: usize,
+}
+
+impl PrimarySampler {
+    fn new(seed: u64) -> Self {
+        Self {
+            samples: vec![],
+            rand_gen: StdRng::seed_from_u64(seed),
+            iteration: 0,
+            last_large_step: 0,
+            // The first path uses independent samples
+            large_step: true,
+            index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rand_gen.gen::<f32>() < LARGE_STEP_PROBABILITY;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modification == self.iteration {
+                sample.value = sample.backup_value;
+                sample.last_modification = sample.backup_modification;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// Next primary sample, brought up to date with the mutations it missed
+    fn next_sample(&mut self) -> f32 {
+        let index = self.index;
+        self.index += 1;
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let sample = &mut self.samples[index];
+
+        // Replay the last large step if the sample wasn't used since
+        if sample.last_modification < self.last_large_step {
+            sample.value = self.rand_gen.gen();
+            sample.last_modification = self.last_large_step;
+        }
+
+        sample.backup_value = sample.value;
+        sample.backup_modification = sample.last_modification;
+        if self.large_step {
+            sample.value = self.rand_gen.gen();
+        } else {
+            // Missed small steps add up to a single perturbation
+            let small_steps = (self.iteration - sample.last_modification) as f32;
+            let sigma = SIGMA * small_steps.sqrt();
+            sample.value += normal_sample(&mut self.rand_gen) * sigma;
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modification = self.iteration;
+        sample.value
+    }
+}
+
+impl Sampler for PrimarySampler {
+    fn next_1d(&mut self) -> f32 {
+        // Quantized to 24 bits like the uniform floats of a random generator, which keeps the
+        // paths those of the path tracer and the perturbed values below 1
+        let bits = (self.next_sample() as f64 * 4294967296.) as u32 >> 8;
+        bits as f32 / (1 << 24) as f32
+    }
+}
+
+/// Standard normal random variable (Box-Muller transform)
+fn normal_sample(rand_gen: &mut StdRng) -> f32 {
+    let r1: f32 = rand_gen.gen();
+    let r2: f32 = rand_gen.gen();
+    (-2. * (1. - r1).ln()).sqrt() * (2. * PI * r2).cos()
+}
+
+/// Path traced from the primary samples
+#[derive(Clone, Copy)]
+struct PathSample {
+    /// Index of the pixel the path goes through
+    pixel: usize,
+    color: Vector3<f32>,
+    /// Scalar contribution the chains are distributed according to
+    luminance: f32,
+}
+
+struct Chain {
+    sampler: PrimarySampler,
+    current: PathSample,
+}
+
+/// Trace a path through the film using the primary samples (the first two pick the film position).
+/// The path only adds to the pixel it goes through, the reconstruction filter isn't applied.
+fn evaluate(
+    profile: &Profile,
+    scene: &Scene,
+    kernel: &dyn Integrator,
+    sampler: &mut PrimarySampler,
+) -> PathSample {
+    let width = profile.resolution.width;
+    let height = profile.resolution.height;
+    let (u, v) = sampler.next_2d();
+    let film_x = u * width as f32;
+    let film_y = v * height as f32;
+    let x = (film_x as u32).min(width - 1);
+    let y = (film_y as u32).min(height - 1);
+
+    let ray = Renderer::camera_ray(scene, profile.resolution, film_x, film_y);
+    let color = kernel.radiance(profile, scene, ray, sampler);
+    let mut luminance = luminance(&color, profile.working_space);
+    if !luminance.is_finite() || luminance < 0. {
+        luminance = 0.;
+    }
+    PathSample {
+        pixel: (x + y * width) as usize,
+        color,
+        luminance,
+    }
+}
+
+/// Render the image with primary sample space Metropolis light transport.
+/// The paths of the kernel integrator are mutated, chains are started from a bootstrap of
+/// independent paths which also gives the normalization constant. `progress` is called after each
+/// round of mutations (one per pixel on average) with the current estimate of the image.
+fn render(
+    profile: &Profile,
+    scene: &Scene,
+    kernel: &dyn Integrator,
+    progress: &mut dyn FnMut(&[Vector3<f32>]),
+) -> Vec<Vector3<f32>> {
+    let pixels = (profile.resolution.width * profile.resolution.height) as usize;
+    let mut buffer = vec![Vector3::zero(); pixels];
+
+    // Bootstrap
+    let luminances: Vec<f32> = (0..BOOTSTRAP_SAMPLES)
+        .into_par_iter()
+        .map(|i| evaluate(profile, scene, kernel, &mut PrimarySampler::new(i as u64)).luminance)
+        .collect();
+    let bootstrap = Distribution1D::new(&luminances);
+    let brightness = bootstrap.integral();
+    if brightness <= 0. || profile.mutations == 0 {
+        return buffer;
+    }
+
+    let mut chains: Vec<Chain> = (0..CHAINS)
+        .into_par_iter()
+        .map(|i| {
+            let seed = (BOOTSTRAP_SAMPLES + i) as u64;
+            let mut rand_gen = StdRng::seed_from_u64(seed);
+            let (index, _) = bootstrap.sample_discrete(rand_gen.gen());
+
+            // Replay the bootstrap path then mutate it with a generator of its own
+            let mut sampler = PrimarySampler::new(index as u64);
+            let current = evaluate(profile, scene, kernel, &mut sampler);
+            sampler.rand_gen = rand_gen;
+            Chain { sampler, current }
+        })
+        .collect();
+
+    let mut splats = vec![Vector3::zero(); pixels];
+    for round in 1..(profile.mutations + 1) {
+        let chain_splats: Vec<Vec<(usize, Vector3<f32>)>> = chains
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, chain)| {
+                // Spread the mutations of the round over the chains
+                let mutations = pixels / CHAINS + usize::from(i < pixels % CHAINS);
+                let mut splats = Vec::with_capacity(2 * mutations);
+                for _ in 0..mutations {
+                    chain.sampler.start_iteration();
+                    let proposed = evaluate(profile, scene, kernel, &mut chain.sampler);
+                    let current = chain.current;
+                    let accept = if current.luminance > 0. {
+                        (proposed.luminance / current.luminance).min(1.)
+                    } else {
+                        1.
+                    };
+
+                    // Both samples contribute, weighted by their probability of being the next state
+                    if proposed.luminance > 0. {
+                        splats.push((
+                            proposed.pixel,
+                            proposed.color * (accept / proposed.luminance),
+                        ));
+                    }
+                    if current.luminance > 0. {
+                        splats.push((
+                            current.pixel,
+                            current.color * ((1. - accept) / current.luminance),
+                        ));
+                    }
+
+                    if chain.sampler.rand_gen.gen::<f32>() < accept {
+                        chain.current = proposed;
+                        chain.sampler.accept();
+                    } else {
+                        chain.sampler.reject();
+                    }
+                }
+                splats
+            })
+            .collect();
+
+        // Accumulate in chain order to keep the result deterministic
+        for (pixel, color) in chain_splats.into_iter().flatten() {
+            splats[pixel] += color;
+        }
+
+        let scale = brightness / round as f32;
+        buffer
+            .par_iter_mut()
+            .zip(splats.par_iter())
+            .for_each(|(pixel, splat)| *pixel = splat * scale);
+        progress(&buffer);
+    }
+    buffer
+}
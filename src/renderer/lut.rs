@@ -0,0 +1,203 @@
+use cgmath::*;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// 3D color lookup table, as saved in Adobe / Resolve `.cube` files
+pub struct Lut {
+    /// Number of entries along each axis
+    size: usize,
+    /// Input colors mapped to the first and last entries
+    domain_min: Vector3<f32>,
+    domain_max: Vector3<f32>,
+    /// Output colors, red changing the fastest
+    table: Vec<Vector3<f32>>,
+}
+
+impl Lut {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path = path.as_ref();
+        read_to_string(path)
+            .map_err(Into::into)
+            .and_then(|text| Self::parse(&text))
+            .map_err(|e| format!("Couldn't load the LUT {}: {e}", path.display()).into())
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut size = None;
+        let mut domain_min = Vector3::zero();
+        let mut domain_max = Vector3::new(1., 1., 1.);
+        let mut table = vec![];
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let arguments: Vec<_> = words.collect();
+            let values = || {
+                arguments
+                    .iter()
+                    .map(|word| word.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => size = Some(arguments.first().unwrap_or(&"").parse::<usize>()?),
+                "LUT_1D_SIZE" => return Err("only 3D LUTs are supported".into()),
+                "DOMAIN_MIN" => domain_min = vector(&values()?)?,
+                "DOMAIN_MAX" => domain_max = vector(&values()?)?,
+                // Resolve's spelling of the domain, the same on all the channels
+                "LUT_3D_INPUT_RANGE" => match values()?[..] {
+                    [min, max] => {
+                        domain_min = Vector3::new(min, min, min);
+                        domain_max = Vector3::new(max, max, max);
+                    }
+                    _ => return Err(format!("invalid input range: {line}").into()),
+                },
+                _ => {
+                    let values: Vec<f32> = line
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| format!("invalid line: {line}"))?;
+                    table.push(vector(&values)?);
+                }
+            }
+        }
+
+        let size = size.ok_or("missing LUT_3D_SIZE")?;
+        if size < 2 {
+            return Err(format!("invalid size {size}").into());
+        }
+        if table.len() != size * size * size {
+            return Err(
+                format!("{} entries instead of {}", table.len(), size * size * size).into(),
+            );
+        }
+        Ok(Lut {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Color looked up with trilinear interpolation, clamped to the domain of the table
+    pub fn apply(&self, color: Vector3<f32>) -> Vector3<f32> {
+        let last = (self.size - 1) as f32;
+        let position = (color - self.domain_min)
+            .div_element_wise(self.domain_max - self.domain_min)
+            .map(|x| (x * last).clamp(0., last));
+        let lower = position.map(|x| (x.floor() as usize).min(self.size - 2));
+        let fraction = position - lower.cast::<f32>().unwrap();
+        let entry = |r: usize, g: usize, b: usize| {
+            self.table[(lower.x + r) + ((lower.y + g) + (lower.z + b) * self.size) * self.size]
+        };
+        let lerp = |a: Vector3<f32>, b: Vector3<f32>, t: f32| a + (b - a) * t;
+        let bottom = lerp(
+            lerp(entry(0, 0, 0), entry(1, 0, 0), fraction.x),
+            lerp(entry(0, 1, 0), entry(1, 1, 0), fraction.x),
+            fraction.y,
+        );
+        let top = lerp(
+            lerp(entry(0, 0, 1), entry(1, 0, 1), fraction.x),
+            lerp(entry(0, 1, 1), entry(1, 1, 1), fraction.x),
+            fraction.y,
+        );
+        lerp(bottom, top, fraction.z)
+    }
+}
+
+fn vector(values: &[f32]) -> Result<Vector3<f32>, Box<dyn Error + Send + Sync>> {
+    match values {
+        [r, g, b] => Ok(Vector3::new(*r, *g, *b)),
+        _ => Err(format!("expected 3 values, got {}", values.len()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries of a 2x2x2 identity table, red changing the fastest
+    const IDENTITY: &str = "
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    fn parse_error(text: &str) -> String {
+        match Lut::parse(text) {
+            Ok(_) => panic!("parsed an invalid LUT:{text}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-6, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn identity() {
+        let text =
+            format!("# Identity\nTITLE \"identity\"\n\n  LUT_3D_SIZE 2\n# Entries\n{IDENTITY}");
+        let lut = Lut::parse(&text).unwrap();
+        assert_eq!(lut.size, 2);
+        for color in [
+            Vector3::new(0., 0., 0.),
+            Vector3::new(0.25, 0.5, 0.75),
+            Vector3::new(1., 0.1, 0.9),
+        ] {
+            assert_close(lut.apply(color), color);
+        }
+        // Colors out of the domain are clamped
+        assert_close(
+            lut.apply(Vector3::new(-1., 2., 0.5)),
+            Vector3::new(0., 1., 0.5),
+        );
+    }
+
+    #[test]
+    fn domain() {
+        let text = format!("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 -1\nDOMAIN_MAX 2 4 1\n{IDENTITY}");
+        let lut = Lut::parse(&text).unwrap();
+        assert_close(lut.domain_min, Vector3::new(0., 0., -1.));
+        assert_close(lut.domain_max, Vector3::new(2., 4., 1.));
+        assert_close(
+            lut.apply(Vector3::new(1., 1., 0.)),
+            Vector3::new(0.5, 0.25, 0.5),
+        );
+
+        let text = format!("LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0 2\n{IDENTITY}");
+        let lut = Lut::parse(&text).unwrap();
+        assert_close(
+            lut.apply(Vector3::new(1., 0.5, 2.)),
+            Vector3::new(0.5, 0.25, 1.),
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse_error(IDENTITY).contains("missing LUT_3D_SIZE"));
+        assert!(parse_error("LUT_3D_SIZE 1\n0 0 0").contains("invalid size"));
+        assert!(parse_error("LUT_3D_SIZE two").contains("invalid digit"));
+        assert!(parse_error("LUT_1D_SIZE 2").contains("only 3D LUTs"));
+        let missing_entry = format!("LUT_3D_SIZE 2\n{}", &IDENTITY[..IDENTITY.len() - 6]);
+        assert!(parse_error(&missing_entry).contains("7 entries instead of 8"));
+        let short_row = format!("LUT_3D_SIZE 2\n{IDENTITY}1 1");
+        assert!(parse_error(&short_row).contains("expected 3 values, got 2"));
+        let text_row = format!("LUT_3D_SIZE 2\n{IDENTITY}1 one 1");
+        assert!(parse_error(&text_row).contains("invalid line: 1 one 1"));
+        let domain = format!("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0\n{IDENTITY}");
+        assert!(parse_error(&domain).contains("expected 3 values"));
+        let range = format!("LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0 1 2\n{IDENTITY}");
+        assert!(parse_error(&range).contains("invalid input range"));
+    }
+}
@@ -1,6 +1,7 @@
 use cgmath::*;
 
-use crate::scene::internal::Material;
+use crate::renderer::color::WorkingSpace;
+use crate::scene::internal::{Dispersion, Material, Subsurface};
 use derivative::Derivative;
 
 #[derive(Derivative)]
@@ -13,7 +14,12 @@ pub struct MaterialSample {
     pub opacity: f32,
     #[derivative(Default(value = "Zero::zero()"))]
     pub emissive: Vector3<f32>,
+    pub transmission: f32,
+    pub subsurface: Option<Subsurface>,
     pub ior: f32,
+    pub dispersion: Option<Dispersion>,
+    /// Space the colors are given in
+    pub working_space: WorkingSpace,
 }
 
 impl MaterialSample {
@@ -24,7 +30,11 @@ impl MaterialSample {
             albedo: material.get_albedo(tex_coords),
             opacity: material.get_opacity(tex_coords),
             emissive: material.get_emissive(tex_coords),
+            transmission: material.get_transmission(tex_coords),
+            subsurface: material.subsurface,
             ior: material.ior,
+            dispersion: material.dispersion,
+            working_space: material.working_space(),
         }
     }
 
@@ -35,7 +45,11 @@ impl MaterialSample {
             albedo: material.get_simple_albedo(),
             opacity: material.get_simple_opacity(),
             emissive: material.get_simple_emissive(),
+            transmission: material.get_simple_transmission(),
+            subsurface: material.subsurface,
             ior: material.ior,
+            dispersion: material.dispersion,
+            working_space: material.working_space(),
         }
     }
 }
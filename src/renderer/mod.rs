@@ -1,33 +1,62 @@
+mod accumulator;
+mod bloom;
 pub mod brdf;
+mod checkpoint;
+pub mod color;
 pub mod debug_renderer;
+mod denoiser;
+pub mod distribution;
+mod exposure;
+pub mod filter;
+pub mod hdr;
 mod hit;
+pub mod integrator;
+mod lut;
 mod material_sample;
+pub mod passes;
 mod ray;
+pub mod sampler;
+mod spectrum;
+mod subsurface;
+pub mod tiles;
 pub mod tonemap;
 pub mod utils;
 mod viewer;
 
 use crate::config::*;
-use crate::scene::internal::Light;
+use crate::scene::internal::{EmissiveSample, Light, Medium, Model};
 use crate::Scene;
+use accumulator::{FilmPixel, FilmSample, PixelAccumulator};
+use bloom::bloom;
 use brdf::*;
 use cgmath::*;
-use derivative::Derivative;
-use image::{Rgb, RgbImage};
+use checkpoint::{Checkpoint, Hash};
+use debug_renderer::render_features;
+use denoiser::denoise;
+use filter::Filter;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use integrator::{get_integrator, Integrator, IntegratorType};
+use lut::Lut;
 use material_sample::MaterialSample;
+use passes::{sample_passes, LightPasses};
 use pbr::ProgressBar;
-use rand::rngs::StdRng;
-use rand::Rng;
-use rand::SeedableRng;
-use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rayon::current_num_threads;
+use rayon::prelude::*;
+use sampler::{get_sampler, PixelSample, Sampler};
+use spectrum::Spectrum;
+use std::error::Error;
 use std::f32::consts::PI;
-use std::sync::atomic::AtomicBool;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use tiles::{tiles, Tile};
 use tonemap::*;
 use utils::*;
-use viewer::Viewer;
+use viewer::{SenderPixel, Viewer};
 
 pub use hit::Hit;
 pub use ray::{Intersectable, Ray};
@@ -36,313 +65,1275 @@ pub struct Renderer {
     profile: Profile,
     quiet: bool,
     viewer: Option<Viewer>,
+    /// Where to save the number of samples of each pixel
+    sample_map: Option<PathBuf>,
+    /// Scene description file, identifying the scene in checkpoints
+    input: PathBuf,
+    /// Where to periodically save the state of the render
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: Duration,
+    /// State of an interrupted render to start from
+    resume: Option<Checkpoint>,
+    /// Where to save the image as tiles finish
+    tile_output: Option<PathBuf>,
+    /// Where to save the image before denoising
+    raw_output: Option<PathBuf>,
+    /// Where to save the render passes
+    passes_output: PathBuf,
+    /// Where to save the linear image
+    hdr_outputs: Vec<PathBuf>,
+    /// Save OpenEXR images with half floats
+    half: bool,
+    /// Look applied to the tone mapped colors
+    lut: Option<Lut>,
 }
 
-#[derive(Derivative)]
-#[derivative(Default)]
-struct RadianceInfo {
-    #[derivative(Default(value = "Vector3::zero()"))]
+/// Light sampled from the light sources at a scattering event
+struct DirectLight {
     color: Vector3<f32>,
-    #[derivative(Default(value = "Vector3::new(1., 1., 1.)"))]
-    throughput: Vector3<f32>,
+    /// Part of the light reflected by diffuse lobes, for the render passes
+    diffuse: Vector3<f32>,
 }
 
-struct SurfaceInfo {
-    hit: Hit,
-    material: MaterialSample,
-    normal: Vector3<f32>,
+impl DirectLight {
+    fn zero() -> Self {
+        Self {
+            color: Vector3::zero(),
+            diffuse: Vector3::zero(),
+        }
+    }
+}
+
+/// Splats of the samples of a tile on the pixels of its region, and on their render passes
+type TileFilm = (Vec<FilmPixel>, Vec<Vec<FilmPixel>>);
+
+/// How the samples of a render are taken, settled before it starts
+struct Sampling<'a> {
+    /// Rounds of an integrator rendering the whole frame at once
+    rounds: Option<usize>,
+    /// Tiles rendered to completion, in the order they start
+    tiles: Option<Vec<Tile>>,
+    time_limit: Option<Duration>,
+    target_noise: Option<f32>,
+    save_passes: bool,
+    /// Where to save the checkpoints with the hash of the scene, None if they are disabled
+    checkpoint: Option<(&'a Path, Hash)>,
 }
 
 impl Renderer {
     // To avoid self-intersection
     const NORMAL_BIAS: f32 = 0.00001;
+    // Minimum time between two saves of the finished tiles
+    const TILE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+    // Minimum time between two previews of the finished tiles
+    const TILE_PREVIEW_INTERVAL: Duration = Duration::from_millis(100);
 
     /// Create new raytracer given resolution
-    pub fn new(config: &RenderConfig, profile: Profile) -> Self {
+    pub fn new(
+        config: &RenderConfig,
+        profile: Profile,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let lut = profile.lut.as_ref().map(Lut::load).transpose()?;
         let viewer = if config.viewer {
             Some(Viewer::create(profile.resolution))
         } else {
             None
         };
 
-        Renderer {
+        Ok(Renderer {
             profile,
             quiet: config.quiet,
             viewer,
+            sample_map: config.sample_map.clone(),
+            input: config.input.clone(),
+            checkpoint: config.checkpoint.clone(),
+            checkpoint_interval: Duration::from_secs(config.checkpoint_interval),
+            resume: None,
+            tile_output: config.save_tiles.then(|| config.output.clone()),
+            raw_output: config.raw_output.clone(),
+            passes_output: config
+                .passes
+                .clone()
+                .unwrap_or_else(|| config.output.with_extension("passes.exr")),
+            hdr_outputs: config
+                .hdr_output
+                .iter()
+                .chain(Some(&config.output).filter(|output| hdr::is_hdr(output)))
+                .cloned()
+                .collect(),
+            half: config.half,
+            lut,
+        })
+    }
+
+    /// Start the render from a checkpoint, which must come from the same scene and profile
+    pub fn resume<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let integrator = get_integrator(self.profile.integrator);
+        if integrator.frame_rounds(&self.profile).is_some() {
+            return Err(format!(
+                "The {:?} integrator renders the whole frame at once, its renders can't be resumed",
+                self.profile.integrator
+            )
+            .into());
         }
+        let scene_hash = Checkpoint::hash_scene(&self.input)?;
+        self.resume = Some(Checkpoint::load(path, &self.profile, &scene_hash)?);
+        Ok(())
     }
 
-    /// Render a scene
-    pub fn render(&self, scene: &Scene) -> RgbImage {
+    /// Render a scene. Fails if one of the additional outputs (HDR images, passes, raw image or
+    /// sample map) can't be saved.
+    pub fn render(&self, scene: &Scene) -> Result<RgbImage, Box<dyn Error + Send + Sync>> {
+        let (buffer, save_passes) = self.render_pixels(scene);
+        self.write_outputs(scene, &buffer, save_passes)
+    }
+
+    /// Sample the pixels of the image, from the checkpoint to resume if any. Returns their state
+    /// and whether the render passes can be saved.
+    fn render_pixels(&self, scene: &Scene) -> (Vec<PixelAccumulator>, bool) {
         let width = self.profile.resolution.width;
         let height = self.profile.resolution.height;
 
-        // Buffer containing the rendered image
-        let mut buffer = vec![Vector3::<f32>::zero(); (width * height) as usize];
+        // Buffer containing the rendered image and the number of passes it holds
+        let (mut buffer, mut pass) = match &self.resume {
+            Some(checkpoint) => (checkpoint.pixels.clone(), checkpoint.pass),
+            None => (
+                vec![
+                    PixelAccumulator::with_passes(self.profile.passes.len());
+                    (width * height) as usize
+                ],
+                0,
+            ),
+        };
+
+        let mut integrator = self.choose_integrator(scene);
+        let sampling = self.sampling(integrator.as_ref());
+        let mut pb = self.progress_bar(&sampling, pass);
+        let now = Instant::now();
 
-        // Create progress bar (if quiet isn't activated)
-        let mut pb = if self.quiet {
-            None
+        match (&sampling.tiles, sampling.rounds) {
+            (_, Some(_)) => self.render_rounds(scene, integrator.as_ref(), &mut buffer, &mut pb),
+            (Some(tiles), None) => self.render_tiles(
+                scene,
+                integrator.as_ref(),
+                tiles,
+                &mut buffer,
+                &mut pb,
+                |buffer| self.save_checkpoint(&sampling, buffer, pass),
+            ),
+            (None, None) => {
+                pass = self.render_passes(
+                    scene,
+                    integrator.as_mut(),
+                    &sampling,
+                    &mut buffer,
+                    pass,
+                    &mut pb,
+                )
+            }
+        }
+        self.save_checkpoint(&sampling, &buffer, pass);
+
+        if let Some(ref mut pb) = pb {
+            pb.finish_print(format!("Done: {}s", now.elapsed().as_secs()).as_str());
+        }
+        (buffer, sampling.save_passes)
+    }
+
+    /// Integrator of the profile, or the path tracer if it can't render the scene
+    fn choose_integrator(&self, scene: &Scene) -> Box<dyn Integrator> {
+        let profile = &self.profile;
+        let integrator = get_integrator(profile.integrator);
+        if !integrator.supports(scene) {
+            if !self.quiet {
+                eprintln!("Warning: the {:?} integrator doesn't handle media and subsurface scattering, falling back to path tracing", profile.integrator);
+            }
+            get_integrator(IntegratorType::Path)
+        } else if profile.spectral && !integrator.spectral() {
+            if !self.quiet {
+                eprintln!("Warning: the {:?} integrator doesn't handle spectral rendering, falling back to path tracing", profile.integrator);
+            }
+            get_integrator(IntegratorType::Path)
         } else {
-            let mut pb = ProgressBar::new(self.profile.samples as u64);
-            pb.message("Rendering: ");
-            pb.set(0);
-            Some(pb)
+            integrator
+        }
+    }
+
+    /// How the integrator samples the image, warning about the settings of the profile it ignores
+    fn sampling(&self, integrator: &dyn Integrator) -> Sampling<'_> {
+        let profile = &self.profile;
+        // Integrators rendering the whole frame at once progress by rounds instead of samples
+        let rounds = integrator.frame_rounds(profile);
+        if rounds.is_some() && !Filter::new(profile).is_pixel_box() && !self.quiet {
+            eprintln!(
+                "Warning: the {:?} integrator adds the paths to the pixel they go through, ignoring the filter",
+                profile.integrator
+            );
+        }
+        let save_passes = match rounds {
+            _ if profile.passes.is_empty() => false,
+            Some(_) => {
+                if !self.quiet {
+                    eprintln!(
+                        "Warning: the {:?} integrator renders the whole frame at once, not saving the passes",
+                        profile.integrator
+                    );
+                }
+                false
+            }
+            _ => {
+                if profile.passes.has_light() && !integrator.light_passes() && !self.quiet {
+                    eprintln!(
+                        "Warning: the {:?} integrator doesn't split the light, the light passes will be black",
+                        profile.integrator
+                    );
+                }
+                true
+            }
         };
 
-        // Save f32 cast of resolution
-        let width_f = width as f32;
-        let height_f = height as f32;
+        // Tiles are rendered to completion, which doesn't fit integrators working by passes
+        let tiles = match profile.tile_size {
+            Some(_) if !integrator.independent_passes() => {
+                if !self.quiet {
+                    eprintln!(
+                        "Warning: the {:?} integrator renders the whole frame, ignoring the tiles",
+                        profile.integrator
+                    );
+                }
+                None
+            }
+            Some(size) => Some(tiles(profile.resolution, size, profile.tile_order)),
+            None => None,
+        };
 
-        let image_ratio = width_f / height_f;
+        // Renders of a fixed length can't stop on time or noise
+        let fixed_length = match (&tiles, rounds) {
+            (Some(_), _) => Some("tiles are rendered to completion".to_string()),
+            (None, Some(_)) => Some(format!(
+                "the {:?} integrator renders a fixed number of rounds",
+                profile.integrator
+            )),
+            _ => None,
+        };
+        let (time_limit, target_noise) = match fixed_length {
+            Some(reason) => {
+                if (profile.time_limit.is_some() || profile.target_noise.is_some()) && !self.quiet {
+                    eprintln!("Warning: {reason}, ignoring the time limit and target noise");
+                }
+                (None, None)
+            }
+            None => (
+                profile.time_limit.map(Duration::from_secs_f32),
+                profile.target_noise,
+            ),
+        };
+
+        let checkpoint = match &self.checkpoint {
+            Some(_) if rounds.is_some() => {
+                if !self.quiet {
+                    eprintln!(
+                        "Warning: the {:?} integrator renders the whole frame at once, checkpoints disabled",
+                        profile.integrator
+                    );
+                }
+                None
+            }
+            Some(path) => match Checkpoint::hash_scene(&self.input) {
+                Ok(scene_hash) => Some((path.as_path(), scene_hash)),
+                Err(e) => {
+                    if !self.quiet {
+                        eprintln!("Warning: checkpoints disabled, couldn't read the scene: {e}");
+                    }
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Sampling {
+            rounds,
+            tiles,
+            time_limit,
+            target_noise,
+            save_passes,
+            checkpoint,
+        }
+    }
 
-        let profile = self.profile;
-        let viewer_enabled = AtomicBool::new(self.viewer.is_some());
-        let sender = Arc::new(Mutex::new(self.viewer.as_ref().map(|v| v.sender.clone())));
+    /// Progress bar counting the passes, rounds, tiles or seconds of the render, None if quiet
+    fn progress_bar(&self, sampling: &Sampling, pass: usize) -> Option<ProgressBar<Stdout>> {
+        if self.quiet {
+            return None;
+        }
+        let profile = &self.profile;
+        let passes = match (&sampling.tiles, sampling.rounds) {
+            (Some(tiles), _) => tiles.len(),
+            (None, Some(rounds)) => rounds,
+            _ => profile
+                .adaptive
+                .map_or(profile.samples, |adaptive| adaptive.max_samples),
+        };
+        let mut pb = match sampling.time_limit {
+            // Count the elapsed seconds instead of the passes
+            Some(time_limit) => {
+                let mut pb = ProgressBar::new(time_limit.as_secs().max(1));
+                pb.show_speed = false;
+                pb
+            }
+            _ => ProgressBar::new(passes as u64),
+        };
+        pb.message("Rendering: ");
+        pb.set(
+            if sampling.time_limit.is_some() || sampling.tiles.is_some() {
+                0
+            } else {
+                pass as u64
+            },
+        );
+        Some(pb)
+    }
 
+    /// Save the state of the render, if checkpoints are enabled
+    fn save_checkpoint(&self, sampling: &Sampling, buffer: &[PixelAccumulator], pass: usize) {
+        if let Some((path, scene_hash)) = sampling.checkpoint {
+            let checkpoint = Checkpoint {
+                width: self.profile.resolution.width,
+                height: self.profile.resolution.height,
+                pass,
+                profile_hash: Checkpoint::hash_profile(&self.profile),
+                scene_hash,
+                pixels: buffer.to_vec(),
+            };
+            if let Err(e) = checkpoint.save(path) {
+                if !self.quiet {
+                    eprintln!("Warning: couldn't save the checkpoint: {e}");
+                }
+            }
+        }
+    }
+
+    /// Render the whole frame with an integrator working by rounds
+    fn render_rounds(
+        &self,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        buffer: &mut [PixelAccumulator],
+        pb: &mut Option<ProgressBar<Stdout>>,
+    ) {
+        let profile = &self.profile;
+        let mut viewer = self.viewer.as_ref().map(|viewer| &viewer.sender);
+        let estimate = integrator.render_frame(profile, scene, &mut |estimate| {
+            if let Some(sender) = viewer {
+                if !self.send_preview(sender, estimate) {
+                    viewer = None;
+                }
+            }
+            if let Some(ref mut pb) = pb {
+                pb.inc();
+            }
+        });
+        // The paths are splatted on the pixel they go through, the estimate being normalized
+        for (pixel, color) in buffer.iter_mut().zip(estimate) {
+            pixel.add(luminance(&color, profile.working_space));
+            pixel.film_mut().splat(color, 1.);
+        }
+    }
+
+    /// Sample the whole image by passes, from the one after `pass`, until the sample count, the
+    /// time limit or the target noise is reached. Returns the number of passes done.
+    fn render_passes(
+        &self,
+        scene: &Scene,
+        integrator: &mut dyn Integrator,
+        sampling: &Sampling,
+        buffer: &mut [PixelAccumulator],
+        mut pass: usize,
+        pb: &mut Option<ProgressBar<Stdout>>,
+    ) -> usize {
+        let profile = &self.profile;
+        let width = profile.resolution.width;
+        let adaptive = profile.adaptive;
+        let time_limit = sampling.time_limit;
+        let samples = match time_limit {
+            // Passes go on until the time is up
+            Some(_) => usize::MAX,
+            None => adaptive.map_or(profile.samples, |adaptive| adaptive.max_samples),
+        };
+
+        let mut viewer = self.viewer.as_ref().map(|viewer| &viewer.sender);
         let now = Instant::now();
+        let filter = Filter::new(profile);
+        let frame = Tile {
+            x: 0,
+            y: 0,
+            width,
+            height: profile.resolution.height,
+        };
+        // Sample taken in each pixel during a pass
+        let mut film_samples = vec![None; buffer.len()];
+        let mut last_checkpoint = Instant::now();
+        // Number of samples that can be spent over the image
+        let budget = profile.samples * buffer.len();
+        for current_sample in (pass + 1)..=samples {
+            let pass_start = Instant::now();
+            integrator.start_pass(profile, scene, current_sample);
+            let integrator = &*integrator;
 
-        for current_sample in 1..(profile.samples + 1) {
+            buffer
+                .par_iter_mut()
+                .zip(film_samples.par_iter_mut())
+                .enumerate()
+                .for_each(|(i, (pixel, sample))| {
+                    // Past the sample count, the time limit keeps adding samples
+                    *sample = if Self::needs_samples(profile, pixel)
+                        || (adaptive.is_none() && time_limit.is_some())
+                    {
+                        let x = i as u32 % width;
+                        let y = i as u32 / width;
+                        Some(Self::sample_pixel(profile, scene, integrator, x, y, pixel))
+                    } else {
+                        None
+                    };
+                });
+
+            // Splat the samples on the pixels around them
             buffer.par_iter_mut().enumerate().for_each(|(i, pixel)| {
                 let x = i as u32 % width;
                 let y = i as u32 / width;
+                let splats = filter.gather(&film_samples, &frame, x, y);
+                if splats.is_empty() {
+                    return;
+                }
+                *pixel.film_mut() += splats;
+                for (sample, weight) in filter.splats(&film_samples, &frame, x, y) {
+                    passes::splat(profile.passes, pixel.passes_mut(), &sample.passes, weight);
+                }
+            });
+            pass = current_sample;
 
-                let mut rand_gen = StdRng::seed_from_u64(
-                    current_sample as u64 + i as u64 * profile.samples as u64,
-                );
+            // Send it to viewer
+            if let Some(sender) = viewer {
+                let colors: Vec<_> = buffer.par_iter().map(PixelAccumulator::color).collect();
+                if !self.send_preview(sender, &colors) {
+                    viewer = None;
+                }
+            }
+            if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                self.save_checkpoint(sampling, buffer, pass);
+                last_checkpoint = Instant::now();
+            }
 
-                let mut screen_x = x as f32 + rand_gen.gen::<f32>();
-                screen_x = screen_x / width_f * 2. - 1.;
-                screen_x *= Rad::tan(scene.camera.fov / 2.) * image_ratio;
+            // Update progressbar
+            if let Some(ref mut pb) = pb {
+                match time_limit {
+                    Some(time_limit) => {
+                        pb.set(now.elapsed().as_secs().min(time_limit.as_secs()));
+                    }
+                    None => {
+                        pb.inc();
+                    }
+                }
+            }
 
-                let mut screen_y = y as f32 + rand_gen.gen::<f32>();
-                screen_y = 1. - screen_y / height_f * 2.;
-                screen_y *= Rad::tan(scene.camera.fov / 2.);
+            // Stop before the next pass would exceed the time limit
+            if let Some(time_limit) = time_limit {
+                if now.elapsed() + pass_start.elapsed() > time_limit {
+                    break;
+                }
+            }
 
-                let ray_dir = Vector3::new(screen_x, screen_y, -1.).normalize();
-                let ray_dir = scene.camera.apply_transform_vector(&ray_dir);
-                let ray = Ray::new(scene.camera.position(), ray_dir);
+            if let Some(target_noise) = sampling.target_noise {
+                let noise = buffer
+                    .par_iter()
+                    .map(PixelAccumulator::relative_error)
+                    .sum::<f32>()
+                    / buffer.len() as f32;
+                if noise <= target_noise {
+                    break;
+                }
+            }
 
-                // Compute pixel color
-                let color = Self::render_pixel(&profile, scene, ray, &mut rand_gen);
+            // Stop once every pixel converged or the budget is spent
+            if let Some(adaptive) = &adaptive {
+                let spent: usize = buffer.par_iter().map(PixelAccumulator::count).sum();
+                let exhausted = time_limit.is_none() && spent >= budget;
+                if exhausted || !buffer.par_iter().any(|pixel| pixel.needs_samples(adaptive)) {
+                    break;
+                }
+            }
+        }
+        pass
+    }
 
-                // Update my pixel
-                *pixel += color;
+    /// Save the render passes, the raw image, the HDR images and the sample map, and return the
+    /// tone mapped image, denoised if the profile asks for it
+    fn write_outputs(
+        &self,
+        scene: &Scene,
+        buffer: &[PixelAccumulator],
+        save_passes: bool,
+    ) -> Result<RgbImage, Box<dyn Error + Send + Sync>> {
+        let profile = &self.profile;
+        if save_passes {
+            passes::save(
+                profile.passes,
+                profile.resolution,
+                buffer,
+                &self.passes_output,
+            )
+            .map_err(|e| format!("Couldn't save the render passes: {e}"))?;
+        }
 
-                // Send it to viewer
-                if viewer_enabled.load(Ordering::Relaxed) {
-                    let sender_guard = sender.lock().unwrap();
-                    let sender = sender_guard.as_ref().unwrap();
-                    let color = *pixel / current_sample as f32;
-                    let color = Self::post_processing(&profile, color);
-                    if Viewer::send_pixel_update(sender, x, y, color.0).is_err() {
-                        viewer_enabled.store(false, Ordering::Relaxed);
-                    }
+        // Final pass
+        let colors: Vec<_> = buffer.iter().map(PixelAccumulator::color).collect();
+        let colors = match &profile.denoiser {
+            Some(settings) => {
+                if let Some(path) = &self.raw_output {
+                    self.save_image(path, &colors)
+                        .map_err(|e| format!("Couldn't save the raw image: {e}"))?;
                 }
+                // Without an estimate of the noise, the pixel is assumed as noisy as it is bright
+                let variances: Vec<_> = buffer
+                    .iter()
+                    .zip(&colors)
+                    .map(|(pixel, color)| {
+                        pixel
+                            .variance()
+                            .unwrap_or(luminance(color, profile.working_space).powi(2))
+                    })
+                    .collect();
+                let features = render_features(scene, profile.resolution);
+                denoise(
+                    settings,
+                    profile.resolution,
+                    profile.working_space,
+                    &colors,
+                    &variances,
+                    &features,
+                )
+            }
+            None => {
+                if self.raw_output.is_some() && !self.quiet {
+                    eprintln!("Warning: the profile has no denoiser, not saving the raw image");
+                }
+                colors
+            }
+        };
+        for path in &self.hdr_outputs {
+            hdr::save(path, profile.resolution, &colors, self.half)
+                .map_err(|e| format!("Couldn't save the HDR image: {e}"))?;
+        }
+
+        if let Some(path) = &self.sample_map {
+            let resolution = profile.resolution;
+            Self::sample_map(buffer, resolution.width, resolution.height)
+                .save(path)
+                .map_err(|e| format!("Couldn't save the sample map: {e}"))?;
+        }
+
+        Ok(self.tonemapped(&colors))
+    }
+
+    /// Render the tiles to completion in parallel, starting them in order. Finished tiles are
+    /// gathered on this thread, which sends them to the viewer and saves the image.
+    fn render_tiles<F>(
+        &self,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        tiles: &[Tile],
+        buffer: &mut [PixelAccumulator],
+        pb: &mut Option<ProgressBar<Stdout>>,
+        save_checkpoint: F,
+    ) where
+        F: Fn(&[PixelAccumulator]),
+    {
+        let profile = &self.profile;
+        let width = profile.resolution.width;
+        let filter = Filter::new(profile);
+        let extent = filter.extent();
+        let tile_size = profile.tile_size.unwrap_or(1).max(1);
+
+        // Pixels of the tiles before rendering (resumed renders start from a checkpoint)
+        let jobs: Vec<Vec<PixelAccumulator>> = tiles
+            .iter()
+            .map(|tile| {
+                (0..tile.len())
+                    .map(|j| {
+                        let (x, y) = tile.pixel(j);
+                        buffer[(x + y * width) as usize].clone()
+                    })
+                    .collect()
+            })
+            .collect();
+        // Pixels reached by the samples of each tile
+        let regions: Vec<Tile> = tiles
+            .iter()
+            .map(|tile| tile.expand(extent, profile.resolution))
+            .collect();
+        // Index of the tile at each position of the grid
+        let columns = width.div_ceil(tile_size);
+        let rows = profile.resolution.height.div_ceil(tile_size);
+        let mut grid = vec![0; tiles.len()];
+        for (index, tile) in tiles.iter().enumerate() {
+            grid[(tile.x / tile_size + tile.y / tile_size * columns) as usize] = index;
+        }
+        let base: Vec<FilmPixel> = buffer.iter().map(PixelAccumulator::film).collect();
+        let base_passes: Vec<Vec<FilmPixel>> =
+            buffer.iter().map(|pixel| pixel.passes().to_vec()).collect();
+        let mut films: Vec<Option<TileFilm>> = vec![None; tiles.len()];
+
+        let next_tile = AtomicUsize::new(0);
+        let (sender, receiver) = channel();
+
+        thread::scope(|s| {
+            let (jobs, regions, filter) = (&jobs, &regions, &filter);
+            let (next_tile, grid) = (&next_tile, &grid);
+            s.spawn(move || {
+                (0..current_num_threads())
+                    .into_par_iter()
+                    .for_each(|_| loop {
+                        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                        let Some(pixels) = jobs.get(index) else {
+                            break;
+                        };
+                        let mut pixels = pixels.clone();
+                        let film = Self::render_tile(
+                            profile,
+                            scene,
+                            integrator,
+                            filter,
+                            (&tiles[index], &regions[index]),
+                            &mut pixels,
+                        );
+                        if sender.send((index, pixels, film)).is_err() {
+                            break;
+                        }
+                    });
             });
-            // Update progressbar
-            if let Some(ref mut pb) = pb {
-                pb.inc();
+
+            let mut viewer = self.viewer.as_ref().map(|viewer| &viewer.sender);
+            let mut last_checkpoint = Instant::now();
+            let mut last_save = Instant::now();
+            let mut last_preview = Instant::now();
+            for (index, pixels, film) in receiver {
+                let tile = &tiles[index];
+                for (j, pixel) in pixels.into_iter().enumerate() {
+                    let (x, y) = tile.pixel(j);
+                    buffer[(x + y * width) as usize] = pixel;
+                }
+                films[index] = Some(film);
+
+                // Sum the splats of the finished tiles in their order, whatever the order they
+                // finish in, so the image stays deterministic
+                let region = &regions[index];
+                for j in 0..region.len() {
+                    let (x, y) = region.pixel(j);
+                    let columns_range = (x.saturating_sub(extent) / tile_size)
+                        ..=((x + extent) / tile_size).min(columns - 1);
+                    let rows_range = (y.saturating_sub(extent) / tile_size)
+                        ..=((y + extent) / tile_size).min(rows - 1);
+                    let mut contributors: Vec<usize> = rows_range
+                        .flat_map(|row| {
+                            columns_range
+                                .clone()
+                                .map(move |column| grid[(column + row * columns) as usize])
+                        })
+                        .filter(|&other| films[other].is_some())
+                        .collect();
+                    contributors.sort_unstable();
+
+                    let i = (x + y * width) as usize;
+                    let mut pixel_film = base[i];
+                    let mut pixel_passes = base_passes[i].clone();
+                    for other in contributors {
+                        if let Some((film, passes)) = &films[other] {
+                            let j = regions[other].index(x, y);
+                            pixel_film += film[j];
+                            passes::merge(profile.passes, &mut pixel_passes, &passes[j]);
+                        }
+                    }
+                    *buffer[i].film_mut() = pixel_film;
+                    buffer[i].passes_mut().clone_from_slice(&pixel_passes);
+                }
+
+                if let Some(sender) = viewer {
+                    if last_preview.elapsed() >= Self::TILE_PREVIEW_INTERVAL {
+                        let colors: Vec<_> =
+                            buffer.par_iter().map(PixelAccumulator::color).collect();
+                        if !self.send_preview(sender, &colors) {
+                            viewer = None;
+                        }
+                        last_preview = Instant::now();
+                    }
+                }
+
+                if let Some(ref mut pb) = pb {
+                    pb.inc();
+                }
+                if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                    save_checkpoint(buffer);
+                    last_checkpoint = Instant::now();
+                }
+                if let Some(path) = &self.tile_output {
+                    if last_save.elapsed() >= Self::TILE_SAVE_INTERVAL {
+                        let colors: Vec<_> = buffer.iter().map(PixelAccumulator::color).collect();
+                        if let Err(e) = self.save_image(path, &colors) {
+                            if !self.quiet {
+                                eprintln!("Warning: couldn't save the finished tiles: {e}");
+                            }
+                        }
+                        last_save = Instant::now();
+                    }
+                }
+            }
+        });
+    }
+
+    /// Sample the pixels of a tile until they are done. Returns the splats on the region around
+    /// the tile its samples reach (row by row).
+    fn render_tile(
+        profile: &Profile,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        filter: &Filter,
+        (tile, region): (&Tile, &Tile),
+        pixels: &mut [PixelAccumulator],
+    ) -> TileFilm {
+        let mut film = vec![FilmPixel::default(); region.len()];
+        let mut pass_films = vec![vec![FilmPixel::default(); profile.passes.len()]; region.len()];
+        let mut samples = vec![None; tile.len()];
+        let passes = profile
+            .adaptive
+            .map_or(profile.samples, |adaptive| adaptive.max_samples);
+        // Adaptive sampling spends the samples of the tile on its noisiest pixels
+        let budget = profile.samples * pixels.len();
+        for _ in 0..passes {
+            let mut done = true;
+            for (j, (pixel, sample)) in pixels.iter_mut().zip(samples.iter_mut()).enumerate() {
+                *sample = if Self::needs_samples(profile, pixel) {
+                    let (x, y) = tile.pixel(j);
+                    done = false;
+                    Some(Self::sample_pixel(profile, scene, integrator, x, y, pixel))
+                } else {
+                    None
+                };
+            }
+            if done {
+                break;
+            }
+
+            for (j, (pixel_film, pixel_passes)) in film.iter_mut().zip(&mut pass_films).enumerate()
+            {
+                let (x, y) = region.pixel(j);
+                let splats = filter.gather(&samples, tile, x, y);
+                if !splats.is_empty() {
+                    *pixel_film += splats;
+                }
+                for (sample, weight) in filter.splats(&samples, tile, x, y) {
+                    passes::splat(profile.passes, pixel_passes, &sample.passes, weight);
+                }
+            }
+
+            let spent: usize = pixels.iter().map(PixelAccumulator::count).sum();
+            if profile.adaptive.is_some() && spent >= budget {
+                break;
             }
         }
+        (film, pass_films)
+    }
 
-        // Final pass
+    /// Whether a pixel needs more samples to reach the sample count or converge
+    fn needs_samples(profile: &Profile, pixel: &PixelAccumulator) -> bool {
+        match &profile.adaptive {
+            Some(adaptive) => pixel.needs_samples(adaptive),
+            None => pixel.count() < profile.samples,
+        }
+    }
+
+    /// Take a sample in a pixel, to be splatted on the film. The sampler only depends on the pixel
+    /// and its number of samples, so the image doesn't depend on the order the pixels are rendered
+    /// in.
+    fn sample_pixel(
+        profile: &Profile,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        x: u32,
+        y: u32,
+        pixel: &mut PixelAccumulator,
+    ) -> FilmSample {
+        let index = pixel.count();
+        let mut sampler = get_sampler(profile, PixelSample { x, y, index });
+
+        let (jitter_x, jitter_y) = sampler.next_2d();
+        let film_x = x as f32 + jitter_x;
+        let film_y = y as f32 + jitter_y;
+        let ray = Self::camera_ray(scene, profile.resolution, film_x, film_y);
+
+        // Compute pixel color
+        let (color, passes) = if profile.passes.is_empty() {
+            let color = integrator.radiance(profile, scene, ray, sampler.as_mut());
+            (color, vec![])
+        } else {
+            let mut light = LightPasses::default();
+            let color = integrator.radiance_passes(
+                profile,
+                scene,
+                ray.clone(),
+                sampler.as_mut(),
+                &mut light,
+            );
+            (color, sample_passes(profile.passes, scene, &ray, &light))
+        };
+        pixel.add(luminance(&color, profile.working_space));
+        FilmSample {
+            film_x,
+            film_y,
+            color,
+            passes,
+        }
+    }
+
+    /// Save linear colors as is to HDR files, or post processed to other files
+    fn save_image(
+        &self,
+        path: &Path,
+        colors: &[Vector3<f32>],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if hdr::is_hdr(path) {
+            hdr::save(path, self.profile.resolution, colors, self.half)
+        } else {
+            Ok(self.tonemapped(colors).save(path)?)
+        }
+    }
+
+    /// Post processed image of linear colors
+    fn tonemapped(&self, colors: &[Vector3<f32>]) -> RgbImage {
+        let profile = &self.profile;
+        let width = profile.resolution.width;
+        let height = profile.resolution.height;
+        // Exposure, white balance and bloom, in linear space
+        let adjustment = exposure::adjustment(profile, colors);
+        let mut colors: Vec<_> = colors.par_iter().map(|color| adjustment * color).collect();
+        if let Some(settings) = &profile.bloom {
+            colors = bloom(settings, profile.resolution, profile.working_space, &colors);
+        }
+        let to_display = profile.display.conversion_from(profile.working_space);
         let mut image = RgbImage::new(width, height);
         for x in 0..width {
             for y in 0..height {
                 // Post process
-                let color = Self::post_processing(
-                    &self.profile,
-                    buffer[(x + y * width) as usize] / profile.samples as f32,
-                );
+                let color = self.post_processing(&to_display, colors[(x + y * width) as usize]);
 
                 // Set pixel color into image
                 image[(x, y)] = color;
             }
         }
+        image
+    }
 
-        if let Some(ref mut pb) = pb {
-            pb.finish_print(format!("Done: {}s", now.elapsed().as_secs()).as_str());
-        }
+    /// Send the post processed image of linear colors to the viewer. Returns whether the viewer
+    /// is still open.
+    fn send_preview(&self, sender: &SenderPixel, colors: &[Vector3<f32>]) -> bool {
+        self.tonemapped(colors)
+            .enumerate_pixels()
+            .all(|(x, y, color)| Viewer::send_pixel_update(sender, x, y, color.0).is_ok())
+    }
 
-        image
+    /// Grayscale image of the number of samples of each pixel, white being the most sampled pixels
+    fn sample_map(buffer: &[PixelAccumulator], width: u32, height: u32) -> GrayImage {
+        let max_count = buffer
+            .iter()
+            .map(PixelAccumulator::count)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        GrayImage::from_fn(width, height, |x, y| {
+            let count = buffer[(x + y * width) as usize].count();
+            Luma([(count * 255 / max_count) as u8])
+        })
     }
 
-    /// Render the color of a pixel given a ray and the scene
-    fn render_pixel(
-        profile: &Profile,
-        scene: &Scene,
-        mut ray: Ray,
-        rand_gen: &mut StdRng,
-    ) -> Vector3<f32> {
-        let mut rad_info = RadianceInfo::default();
-
-        for bounce in 0..(profile.bounces + 1) {
-            // Test intersection
-            let intersections = ray_cast(scene, &ray);
-            // Check if we hit nothing (background)
-            if intersections.is_empty() {
-                return rad_info.color + rad_info.throughput.mul_element_wise(scene.background);
-            }
-
-            let mut surface_info = None;
-            for (hit, model) in intersections {
-                let material_sample = hit.get_material_sample(model);
-                let normal = hit.get_normal(model.get_material());
-                let opacity = material_sample.opacity;
-
-                surface_info = Some(SurfaceInfo {
-                    hit,
-                    material: material_sample,
-                    normal,
-                });
+    /// Ray leaving the camera through a position of the film (in pixels)
+    fn camera_ray(scene: &Scene, resolution: Resolution, film_x: f32, film_y: f32) -> Ray {
+        let width_f = resolution.width as f32;
+        let height_f = resolution.height as f32;
+        let image_ratio = width_f / height_f;
 
-                // Alpha transparency
-                if opacity >= 1. || (opacity > 0.001 && rand_gen.gen::<f32>() < opacity) {
-                    // Consider the surface as opaque and stop iterating over the intersections
-                    break;
-                }
-            }
+        let mut screen_x = film_x / width_f * 2. - 1.;
+        screen_x *= Rad::tan(scene.camera.fov / 2.) * image_ratio;
 
-            let view_direction = -1. * ray.direction;
+        let mut screen_y = 1. - film_y / height_f * 2.;
+        screen_y *= Rad::tan(scene.camera.fov / 2.);
 
-            (rad_info, ray) = Self::compute_radiance(
-                profile,
-                scene,
-                rad_info,
-                &surface_info.unwrap(),
-                view_direction,
-                bounce < profile.bounces,
-                rand_gen,
-            );
+        let ray_dir = Vector3::new(screen_x, screen_y, -1.).normalize();
+        let ray_dir = scene.camera.apply_transform_vector(&ray_dir);
+        Ray::new(scene.camera.position(), ray_dir)
+    }
 
-            if rad_info.throughput.magnitude2() < 0.00001 {
-                return rad_info.color;
+    /// Attenuation of a shadow ray by the media crossed before `max_dist`
+    fn medium_transmittance<'a>(
+        scene: &'a Scene,
+        mut medium: Option<&'a Medium>,
+        spectrum: &Spectrum,
+        ray: &Ray,
+        max_dist: f32,
+    ) -> Vector3<f32> {
+        let mut transmittance = Vector3::new(1., 1., 1.);
+        if !scene.has_media {
+            return transmittance;
+        }
+        let Some((start, end)) = clip_to_bounds(&scene.bounds, ray, max_dist) else {
+            return transmittance;
+        };
+        let segment_transmittance = |medium: Option<&Medium>, from: f32, to: f32| {
+            let length = to.min(end) - from.max(start);
+            match medium {
+                Some(medium) if length > 0. => spectrum.medium(medium).transmittance(length),
+                _ => Vector3::new(1., 1., 1.),
             }
+        };
 
-            if bounce > 3 && russian_roulette(&mut rad_info.throughput, rand_gen) {
-                return rad_info.color;
+        let mut segment_start = 0.;
+        for (hit, model) in ray_cast(scene, ray) {
+            if hit.get_dist() >= max_dist {
+                break;
+            }
+            if model.get_material().medium.is_none() {
+                continue;
             }
+            transmittance = transmittance.mul_element_wise(segment_transmittance(
+                medium,
+                segment_start,
+                hit.get_dist(),
+            ));
+            medium = Self::crossed_medium(scene, model, &hit, ray.direction);
+            segment_start = hit.get_dist();
         }
-        rad_info.color
+        transmittance.mul_element_wise(segment_transmittance(medium, segment_start, max_dist))
     }
 
-    fn compute_radiance(
-        profile: &Profile,
+    /// Medium on the other side of the surface of a model, given the direction of the ray
+    fn crossed_medium<'a>(
+        scene: &'a Scene,
+        model: &'a Model,
+        hit: &Hit,
+        direction: Vector3<f32>,
+    ) -> Option<&'a Medium> {
+        let outward_normal = hit.to_front_face().get_geometric_normal();
+        if direction.dot(outward_normal) < 0. {
+            model.get_material().medium.as_ref()
+        } else {
+            scene.medium.as_ref()
+        }
+    }
+
+    /// Radiance of an escaped ray (MIS weighted against environment sampling)
+    fn get_background(scene: &Scene, ray: &Ray, last_pdf: Option<f32>) -> Vector3<f32> {
+        match &scene.environment {
+            Some(environment) => {
+                let weight = match last_pdf {
+                    Some(brdf_pdf) => power_heuristic(brdf_pdf, environment.pdf(ray.direction)),
+                    None => 1.,
+                };
+                environment.radiance(ray.direction) * weight
+            }
+            None => scene.background,
+        }
+    }
+
+    /// Compute the radiance received from a direction sampled on the environment (MIS weighted)
+    #[allow(clippy::too_many_arguments)]
+    fn compute_environment_light(
         scene: &Scene,
-        rad_info: RadianceInfo,
-        surface_info: &SurfaceInfo,
+        brdf: &dyn Brdf,
+        hit: &Hit,
+        normal: Vector3<f32>,
         view_direction: Vector3<f32>,
-        compute_indirect: bool,
-        rand_gen: &mut StdRng,
-    ) -> (RadianceInfo, Ray) {
-        let mut brdf = get_brdf(&surface_info.material, profile.brdf);
-        let mut color = rad_info.color;
-        let mut throughput = rad_info.throughput;
-        let mut ray = Default::default();
-
-        // Emissive
-        color += throughput.mul_element_wise(surface_info.material.emissive);
-
-        // Direct Light computation
-        for light in scene.lights.iter() {
-            let (light_radiance, light_direction) =
-                Self::get_light_info(light, &surface_info.hit, scene);
-            if light_radiance == Zero::zero() {
-                continue;
-            }
-            let reversed_light_dir = -1. * light_direction;
-            color += throughput
-                .mul_element_wise(brdf.eval_direct(
-                    surface_info.normal,
-                    view_direction,
-                    reversed_light_dir,
-                ))
-                .mul_element_wise(light_radiance);
-        }
-
-        // Indirect light computation
-        if compute_indirect {
-            ray = Ray::new(
-                surface_info.hit.get_position()
-                    + surface_info.hit.get_geometric_normal() * Self::NORMAL_BIAS,
-                brdf.sample(surface_info.normal, view_direction, rand_gen),
-            );
-            let sample_radiance =
-                brdf.eval_indirect(surface_info.normal, view_direction, ray.direction);
-            let weighted_sample_radiance = sample_radiance / brdf.pdf();
-            throughput = throughput.mul_element_wise(weighted_sample_radiance);
+        medium: Option<&Medium>,
+        spectrum: &Spectrum,
+        sampler: &mut dyn Sampler,
+    ) -> DirectLight {
+        let Some(environment) = &scene.environment else {
+            return DirectLight::zero();
+        };
+        let (light_direction, radiance, light_pdf) = environment.sample(sampler);
+        let radiance = spectrum.unbounded(radiance);
+        let brdf_value = brdf.eval_direct(normal, view_direction, light_direction);
+        if light_pdf <= 0. || brdf_value == Zero::zero() {
+            return DirectLight::zero();
+        }
+
+        let shadow_ray = Ray::new(Self::offset_origin(hit, light_direction), light_direction);
+        let transmittance = Self::shadow_transmittance(scene, &shadow_ray, f32::INFINITY);
+        if transmittance == 0. {
+            return DirectLight::zero();
+        }
+        let medium_transmittance =
+            Self::medium_transmittance(scene, medium, spectrum, &shadow_ray, f32::INFINITY);
+
+        let brdf_pdf = brdf.sampling_pdf(normal, view_direction, light_direction);
+        let weight = power_heuristic(light_pdf, brdf_pdf);
+
+        let reflected = |brdf_value: Vector3<f32>| {
+            brdf_value
+                .mul_element_wise(radiance)
+                .mul_element_wise(medium_transmittance)
+                * (transmittance * weight / light_pdf)
+        };
+        DirectLight {
+            color: reflected(brdf_value),
+            diffuse: reflected(brdf.eval_direct_diffuse(normal, view_direction, light_direction)),
         }
+    }
+
+    /// Distance and radiance of the closest light sphere hit by a ray before `max_dist` (MIS weighted)
+    fn get_visible_light(
+        scene: &Scene,
+        ray: &Ray,
+        max_dist: f32,
+        last_pdf: Option<f32>,
+    ) -> Option<(f32, Vector3<f32>)> {
+        let (light, dist) = scene
+            .lights
+            .iter()
+            .filter_map(|light| light.intersect(ray).map(|dist| (light, dist)))
+            .filter(|(_, dist)| *dist < max_dist)
+            .min_by(|(_, dist1), (_, dist2)| dist1.partial_cmp(dist2).unwrap())?;
 
-        (RadianceInfo { color, throughput }, ray)
+        let weight = match last_pdf {
+            Some(brdf_pdf) => power_heuristic(brdf_pdf, light.pdf_from(ray.origin)),
+            None => 1.,
+        };
+        Some((dist, light.surface_radiance() * weight))
+    }
+
+    /// Compute the radiance received from a point sampled on emissive geometry (MIS weighted)
+    #[allow(clippy::too_many_arguments)]
+    fn compute_emissive_light(
+        scene: &Scene,
+        brdf: &dyn Brdf,
+        hit: &Hit,
+        normal: Vector3<f32>,
+        view_direction: Vector3<f32>,
+        medium: Option<&Medium>,
+        spectrum: &Spectrum,
+        light_sample: EmissiveSample,
+    ) -> DirectLight {
+        let to_light = light_sample.position - hit.get_position();
+        let dist = to_light.magnitude();
+        let light_direction = to_light / dist;
+        let origin = Self::offset_origin(hit, light_direction);
+
+        // Convert the area density into a solid angle density
+        let cos_light = light_sample.normal.dot(light_direction).abs();
+        if cos_light < 0.0001 || light_sample.pdf <= 0. {
+            return DirectLight::zero();
+        }
+        let light_pdf = light_sample.pdf * dist * dist / cos_light;
+
+        let shadow_ray = Ray::new(origin, light_direction);
+        let transmittance = Self::shadow_transmittance(scene, &shadow_ray, dist * 0.999);
+        if transmittance == 0. {
+            return DirectLight::zero();
+        }
+        let medium_transmittance =
+            Self::medium_transmittance(scene, medium, spectrum, &shadow_ray, dist);
+        let radiance = spectrum.unbounded(light_sample.radiance);
+
+        let brdf_pdf = brdf.sampling_pdf(normal, view_direction, light_direction);
+        let weight = power_heuristic(light_pdf, brdf_pdf);
+
+        let reflected = |brdf_value: Vector3<f32>| {
+            brdf_value
+                .mul_element_wise(radiance)
+                .mul_element_wise(medium_transmittance)
+                * (transmittance * weight / light_pdf)
+        };
+        DirectLight {
+            color: reflected(brdf.eval_direct(normal, view_direction, light_direction)),
+            diffuse: reflected(brdf.eval_direct_diffuse(normal, view_direction, light_direction)),
+        }
+    }
+
+    /// Origin of a ray leaving a hit point, offset on the side of the surface the ray goes to
+    fn offset_origin(hit: &Hit, direction: Vector3<f32>) -> Vector3<f32> {
+        let mut geometric_normal = hit.get_geometric_normal();
+        if geometric_normal.dot(direction) < 0. {
+            geometric_normal = -geometric_normal;
+        }
+        hit.get_position() + geometric_normal * Self::NORMAL_BIAS
+    }
+
+    /// Attenuation of a shadow ray by the opacity of the occluders closer than `max_dist`
+    fn shadow_transmittance(scene: &Scene, ray: &Ray, max_dist: f32) -> f32 {
+        let mut transmittance = 1.;
+        for (shadow_hit, shadow_model) in ray_cast(scene, ray) {
+            if shadow_hit.get_dist() >= max_dist {
+                break;
+            }
+            let material_sample = shadow_hit.get_material_sample(shadow_model);
+            transmittance *= 1. - material_sample.opacity;
+            if transmittance == 0. {
+                break;
+            }
+        }
+        transmittance
     }
 
-    /// Get the light radiance and direction
-    fn get_light_info(light: &Light, hit: &Hit, scene: &Scene) -> (Vector3<f32>, Vector3<f32>) {
+    /// Get the light radiance, direction and solid angle pdf (None for delta lights)
+    fn get_light_info(
+        light: &Light,
+        hit: &Hit,
+        scene: &Scene,
+        medium: Option<&Medium>,
+        spectrum: &Spectrum,
+        sampler: &mut dyn Sampler,
+    ) -> (Vector3<f32>, Vector3<f32>, Option<f32>) {
         match light {
             Light::Directional { direction, color } => {
-                let shadow_ray_ori =
-                    hit.get_position() + hit.get_geometric_normal() * Self::NORMAL_BIAS;
                 let shadow_ray_dir = -1. * direction;
-                let shadow_ray = Ray::new(shadow_ray_ori, shadow_ray_dir);
-
-                // Atenuate the light by the opacity of occluders objects
-                let mut color = *color;
-                for (shadow_hit, shadow_model) in ray_cast(scene, &shadow_ray) {
-                    let material_sample = shadow_hit.get_material_sample(shadow_model);
-                    color *= 1. - material_sample.opacity;
-                    if color.sum() == 0. {
-                        break;
-                    }
-                }
-                (color, *direction)
+                let shadow_ray = Ray::new(Self::offset_origin(hit, shadow_ray_dir), shadow_ray_dir);
+
+                let transmittance = Self::shadow_transmittance(scene, &shadow_ray, f32::INFINITY);
+                let medium_transmittance =
+                    Self::medium_transmittance(scene, medium, spectrum, &shadow_ray, f32::INFINITY);
+                (
+                    spectrum
+                        .unbounded(*color)
+                        .mul_element_wise(medium_transmittance)
+                        * transmittance,
+                    *direction,
+                    None,
+                )
             }
 
             Light::Point {
                 position,
                 color,
-                size: _,
+                size,
+            } if *size > 0. => {
+                let to_center = position - hit.get_position();
+                let dist = to_center.magnitude();
+                if dist <= *size {
+                    // The hit point is inside the light
+                    return (Zero::zero(), -to_center, None);
+                }
+
+                // Sample a direction uniformly in the cone subtended by the sphere
+                let sin2_theta_max = size * size / (dist * dist);
+                let cos_theta_max = (1. - sin2_theta_max).max(0.).sqrt();
+                let one_minus_cos_max = sin2_theta_max / (1. + cos_theta_max);
+                let (r1, r2) = sampler.next_2d();
+                let one_minus_cos = r1 * one_minus_cos_max;
+                let cos_theta = 1. - one_minus_cos;
+                let sin_theta = (one_minus_cos * (2. - one_minus_cos)).max(0.).sqrt();
+                let phi = 2. * PI * r2;
+                let local_dir =
+                    Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                let shadow_ray_dir = transform_to_world(local_dir, to_center / dist).normalize();
+                let shadow_ray = Ray::new(Self::offset_origin(hit, shadow_ray_dir), shadow_ray_dir);
+
+                // Distance to the sphere surface along the sampled direction
+                let surface_dist = dist * cos_theta
+                    - (size * size - dist * dist * sin_theta * sin_theta)
+                        .max(0.)
+                        .sqrt();
+
+                let pdf = Light::cone_pdf(one_minus_cos_max);
+                let transmittance =
+                    Self::shadow_transmittance(scene, &shadow_ray, surface_dist * 0.999);
+                let medium_transmittance =
+                    Self::medium_transmittance(scene, medium, spectrum, &shadow_ray, surface_dist);
+                let radiance = spectrum
+                    .unbounded(Light::sphere_radiance(*color, *size))
+                    .mul_element_wise(medium_transmittance)
+                    * transmittance
+                    / pdf;
+                (radiance, -shadow_ray_dir, Some(pdf))
+            }
+
+            Light::Spot {
+                position,
+                direction,
+                color,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                let light_direction = hit.get_position() - position;
+                let dist = light_direction.magnitude();
+                let light_direction = light_direction.normalize();
+
+                let falloff = Light::spot_falloff(
+                    *direction,
+                    light_direction,
+                    *inner_cone_angle,
+                    *outer_cone_angle,
+                );
+                if falloff == 0. {
+                    return (Zero::zero(), light_direction, None);
+                }
+
+                let shadow_ray_dir = -1. * light_direction;
+                let shadow_ray = Ray::new(Self::offset_origin(hit, shadow_ray_dir), shadow_ray_dir);
+                let transmittance = Self::shadow_transmittance(scene, &shadow_ray, dist);
+                let medium_transmittance =
+                    Self::medium_transmittance(scene, medium, spectrum, &shadow_ray, dist);
+
+                let dissipation = 4. * PI * dist * dist; // 4πr^2
+                (
+                    spectrum
+                        .unbounded(*color)
+                        .mul_element_wise(medium_transmittance)
+                        * (falloff * transmittance / dissipation),
+                    light_direction,
+                    None,
+                )
+            }
+
+            Light::Point {
+                position, color, ..
             } => {
                 let direction = hit.get_position() - position;
                 let dist = direction.magnitude();
                 let direction = direction.normalize();
 
-                let shadow_ray_ori =
-                    hit.get_position() + hit.get_geometric_normal() * Self::NORMAL_BIAS;
                 let shadow_ray_dir = -1. * direction;
-                let shadow_ray = Ray::new(shadow_ray_ori, shadow_ray_dir);
+                let shadow_ray = Ray::new(Self::offset_origin(hit, shadow_ray_dir), shadow_ray_dir);
 
-                let dissipation = 4. * PI * dist * dist; // 4πr^2
+                let transmittance = Self::shadow_transmittance(scene, &shadow_ray, dist);
+                let medium_transmittance =
+                    Self::medium_transmittance(scene, medium, spectrum, &shadow_ray, dist);
 
-                // Atenuate the light by the opacity of occluders objects
-                let mut light_dissipated = color / dissipation;
-                for (shadow_hit, shadow_model) in ray_cast(scene, &shadow_ray) {
-                    if (shadow_hit.get_position() - hit.get_position()).magnitude() > dist {
-                        // The intersected object is behind the light
-                        break;
-                    }
-                    let material_sample = hit.get_material_sample(shadow_model);
-                    light_dissipated *= 1. - material_sample.opacity;
-                    if light_dissipated.sum() == 0. {
-                        break;
-                    }
-                }
-                (light_dissipated, direction)
+                let dissipation = 4. * PI * dist * dist; // 4πr^2
+                (
+                    spectrum
+                        .unbounded(*color)
+                        .mul_element_wise(medium_transmittance)
+                        * (transmittance / dissipation),
+                    direction,
+                    None,
+                )
             }
         }
     }
 
-    fn post_processing(profile: &Profile, color: Vector3<f32>) -> Rgb<u8> {
+    fn post_processing(&self, to_display: &Matrix3<f32>, color: Vector3<f32>) -> Rgb<u8> {
+        // Display primaries, clipping the colors out of their gamut
+        let color = (to_display * color).map(|x| x.max(0.));
+
         // HDR
-        let color = tonemap(profile.tonemap, color);
-
-        // Gamma correction
-        let gamma = 2.2;
-        let color = Vector3::new(
-            color.x.powf(1. / gamma),
-            color.y.powf(1. / gamma),
-            color.z.powf(1. / gamma),
-        );
+        let color = tonemap(&self.profile, color);
+
+        // Transfer function of the display
+        let display = self.profile.display;
+        let color = color.map(|x| display.encode(x.clamp(0., 1.)));
+
+        // Look
+        let color = match &self.lut {
+            Some(lut) => lut.apply(color),
+            None => color,
+        };
 
         // Convert Vector3 into Rgb
         Rgb::from([
@@ -360,3 +1351,304 @@ impl Drop for Renderer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::load_internal;
+
+    /// Render a scene and return the state of its pixels
+    fn render_pixels(name: &str, profile: Profile) -> Vec<PixelAccumulator> {
+        let path = format!("tests/scenes/{name}/scene.isf");
+        let config = RenderConfig {
+            input: path.clone().into(),
+            quiet: true,
+            ..Default::default()
+        };
+        let scene = load_internal(&path, profile.working_space).unwrap();
+        Renderer::new(&config, profile)
+            .unwrap()
+            .render_pixels(&scene)
+            .0
+    }
+
+    #[test]
+    fn adaptive_sampling() {
+        let adaptive = AdaptiveSampling {
+            threshold: 0.05,
+            min_samples: 4,
+            max_samples: 64,
+        };
+        let profile = |samples| Profile {
+            resolution: Resolution {
+                width: 100,
+                height: 75,
+            },
+            samples,
+            adaptive: Some(adaptive),
+            ..Default::default()
+        };
+
+        // With enough budget, pixels are sampled until they converge
+        let pixels = render_pixels("spheres", profile(64));
+        let counts: Vec<_> = pixels.iter().map(PixelAccumulator::count).collect();
+        assert!(counts.contains(&adaptive.min_samples));
+        assert!(counts.contains(&adaptive.max_samples));
+        for pixel in &pixels {
+            assert!(pixel.count() >= adaptive.min_samples);
+            assert!(
+                pixel.count() == adaptive.max_samples
+                    || pixel.relative_error() <= adaptive.threshold
+            );
+        }
+
+        // Otherwise the samples are spent on the noisiest pixels, within the budget
+        let samples = 8;
+        let pixels = render_pixels("spheres", profile(samples));
+        let spent: usize = pixels.iter().map(PixelAccumulator::count).sum();
+        assert!(spent >= samples * pixels.len());
+        // The last pass may exceed it by a sample per pixel
+        assert!(spent <= (samples + 1) * pixels.len());
+        assert!(pixels
+            .iter()
+            .all(|pixel| pixel.count() >= adaptive.min_samples));
+    }
+
+    #[test]
+    fn target_noise() {
+        let target_noise = 0.05;
+        let samples = 256;
+        let profile = Profile {
+            resolution: Resolution {
+                width: 100,
+                height: 75,
+            },
+            samples,
+            target_noise: Some(target_noise),
+            ..Default::default()
+        };
+        let pixels = render_pixels("spheres", profile);
+
+        // Stopped as soon as the average relative error got under the target
+        let noise = pixels
+            .iter()
+            .map(PixelAccumulator::relative_error)
+            .sum::<f32>()
+            / pixels.len() as f32;
+        assert!(noise <= target_noise);
+        assert!(pixels.iter().all(|pixel| pixel.count() < samples));
+    }
+
+    #[test]
+    fn light_passes() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 100,
+                height: 75,
+            },
+            bounces: 4,
+            samples: 16,
+            passes: passes::Pass::ALL.to_vec().into(),
+            ..Default::default()
+        };
+        let pixels = render_pixels("environment", profile);
+
+        // The light passes split the light of the image
+        for (i, pixel) in pixels.iter().enumerate() {
+            let color = pixel.film().average();
+            let sum: Vector3<f32> = passes::Pass::ALL
+                .iter()
+                .zip(pixel.passes())
+                .filter(|(pass, _)| pass.is_light())
+                .map(|(_, film)| film.average())
+                .sum();
+            for c in 0..3 {
+                assert!(
+                    (sum[c] - color[c]).abs() <= 1e-4 + 1e-3 * color[c].abs(),
+                    "pixel {i}: the light passes sum to {sum:?} instead of {color:?}"
+                );
+            }
+        }
+    }
+
+    /// Mean color of the blocks of `size` pixels of an image, row by row
+    fn block_means(
+        colors: &[Vector3<f32>],
+        resolution: Resolution,
+        size: u32,
+    ) -> Vec<Vector3<f32>> {
+        let (columns, rows) = (resolution.width / size, resolution.height / size);
+        (0..columns * rows)
+            .map(|block| {
+                let (bx, by) = (block % columns * size, block / columns * size);
+                (0..size * size)
+                    .map(|j| colors[(bx + j % size + (by + j / size) * resolution.width) as usize])
+                    .sum::<Vector3<f32>>()
+                    / (size * size) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bdpt_converges_to_path_tracing() {
+        // Scenes with media or subsurface scattering are rendered with path tracing instead
+        let excluded = ["fog", "white_furnace_subsurface"];
+        let scenes = [
+            "alpha_transparency",
+            "cube",
+            "dispersion",
+            "emissive",
+            "environment",
+            "glass",
+            "head",
+            "plane",
+            "reflection",
+            "spheres",
+            "spot",
+            "white_furnace_direct",
+            "white_furnace_indirect",
+        ];
+        let bdpt = get_integrator(IntegratorType::Bdpt);
+        for name in excluded.iter().chain(&scenes) {
+            let scene = load_internal(format!("tests/scenes/{name}/scene.isf"), Default::default())
+                .unwrap();
+            assert_eq!(bdpt.supports(&scene), scenes.contains(name), "{name}");
+        }
+
+        // Both integrators estimate the same image, the mean colors of its blocks agree up to
+        // the noise
+        let resolution = Resolution {
+            width: 40,
+            height: 30,
+        };
+        let block_size = 10;
+        for name in scenes {
+            let blocks = |integrator| {
+                let profile = Profile {
+                    resolution,
+                    bounces: 4,
+                    samples: 1024,
+                    integrator,
+                    ..Default::default()
+                };
+                let colors: Vec<_> = render_pixels(name, profile)
+                    .iter()
+                    .map(PixelAccumulator::color)
+                    .collect();
+                block_means(&colors, resolution, block_size)
+            };
+            let path = blocks(IntegratorType::Path);
+            let bdpt = blocks(IntegratorType::Bdpt);
+            // Nearly black blocks are compared to the brightness of the whole image
+            let floor = path.iter().map(|block| block.sum()).sum::<f32>() / path.len() as f32 / 3.;
+            for (block, (bdpt, path)) in bdpt.iter().zip(&path).enumerate() {
+                let difference = (bdpt - path).map(f32::abs);
+                let scale = path.map(|x| x.max(0.1 * floor));
+                let difference = difference.div_element_wise(scale);
+                assert!(
+                    difference.x.max(difference.y).max(difference.z) < 0.1,
+                    "{name}, block {block}: {bdpt:?} with BDPT, {path:?} with path tracing"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pssmlt_converges_to_path_tracing() {
+        // The chains are normalized by the brightness of the bootstrap paths, so the mean color
+        // of the image matches the one of path tracing up to the noise of both estimates.
+        // The mirror of reflection is left out, its rare bright paths need far more samples
+        // for the means to agree.
+        let scenes = [
+            "alpha_transparency",
+            "cube",
+            "dispersion",
+            "emissive",
+            "environment",
+            "fog",
+            "glass",
+            "head",
+            "plane",
+            "spheres",
+            "spot",
+            "white_furnace_direct",
+            "white_furnace_indirect",
+            "white_furnace_subsurface",
+        ];
+        let mean =
+            |colors: &[Vector3<f32>]| colors.iter().sum::<Vector3<f32>>() / colors.len() as f32;
+        for name in scenes {
+            let profile = |integrator| Profile {
+                resolution: Resolution {
+                    width: 40,
+                    height: 30,
+                },
+                bounces: 4,
+                samples: 256,
+                mutations: 64,
+                integrator,
+                ..Default::default()
+            };
+            let colors: Vec<_> = render_pixels(name, profile(IntegratorType::Path))
+                .iter()
+                .map(PixelAccumulator::color)
+                .collect();
+            let path = mean(&colors);
+
+            let profile = profile(IntegratorType::Pssmlt);
+            let scene = load_internal(
+                format!("tests/scenes/{name}/scene.isf"),
+                profile.working_space,
+            )
+            .unwrap();
+            let pssmlt = get_integrator(IntegratorType::Pssmlt);
+            assert!(pssmlt.supports(&scene), "{name}");
+            let pssmlt = mean(&pssmlt.render_frame(&profile, &scene, &mut |_| {}));
+
+            let difference = (pssmlt - path).div_element_wise(path).map(f32::abs);
+            assert!(
+                difference.x.max(difference.y).max(difference.z) < 0.1,
+                "{name}: {pssmlt:?} with PSSMLT, {path:?} with path tracing"
+            );
+        }
+    }
+
+    #[test]
+    fn white_furnace_subsurface() {
+        let profile = Profile {
+            resolution: Resolution {
+                width: 100,
+                height: 75,
+            },
+            bounces: 64,
+            samples: 64,
+            ..Default::default()
+        };
+        let pixels = render_pixels("white_furnace_subsurface", profile);
+        let background = load_internal(
+            "tests/scenes/white_furnace_subsurface/scene.isf",
+            Default::default(),
+        )
+        .unwrap()
+        .background;
+        let colors: Vec<_> = pixels.iter().map(PixelAccumulator::color).collect();
+        // Average relative difference between the mean color and the background
+        let error = |colors: &[Vector3<f32>]| {
+            let mean = colors.iter().sum::<Vector3<f32>>() / colors.len() as f32;
+            mean.div_element_wise(background)
+                .map(|x| (x - 1.).abs())
+                .sum()
+                / 3.
+        };
+
+        // White spheres in a uniform environment reflect and transmit all the light they receive
+        assert!(error(&colors) < 0.01);
+        // Within the light lost by the rough interfaces between their microfacets
+        let spheres: Vec<_> = colors
+            .into_iter()
+            .filter(|color| (color - background).magnitude() > 1e-4)
+            .collect();
+        assert!(!spheres.is_empty());
+        assert!(error(&spheres) < 0.04);
+    }
+}
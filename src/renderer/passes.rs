@@ -0,0 +1,397 @@
+use super::accumulator::{FilmPixel, PixelAccumulator};
+use super::{Hit, Intersectable, Ray};
+use crate::config::Resolution;
+use crate::scene::internal::Scene;
+use cgmath::*;
+use exr::prelude::*;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+/// Render pass (AOV), accumulated with the same samples and filter as the image
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum Pass {
+    /// Distance to the camera plane
+    #[serde(rename = "DEPTH")]
+    Depth,
+    /// World position
+    #[serde(rename = "POSITION")]
+    Position,
+    /// World shading normal
+    #[serde(rename = "NORMAL")]
+    Normal,
+    #[serde(rename = "ALBEDO")]
+    Albedo,
+    /// Light reaching the camera without scattering (emitters, lights and background)
+    #[serde(rename = "EMISSION")]
+    Emission,
+    /// Light from the lights scattered once by a diffuse lobe
+    #[serde(rename = "DIFFUSE_DIRECT")]
+    DiffuseDirect,
+    /// Light scattered by a diffuse lobe, then more times
+    #[serde(rename = "DIFFUSE_INDIRECT")]
+    DiffuseIndirect,
+    /// Light from the lights scattered once by a specular lobe
+    #[serde(rename = "SPECULAR_DIRECT")]
+    SpecularDirect,
+    /// Light scattered by a specular lobe, then more times
+    #[serde(rename = "SPECULAR_INDIRECT")]
+    SpecularIndirect,
+    /// Index of the model, starting from 1
+    #[serde(rename = "OBJECT_ID")]
+    ObjectId,
+    /// Index of the material (shared by the models using it), starting from 1
+    #[serde(rename = "MATERIAL_ID")]
+    MaterialId,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 11] = [
+        Pass::Depth,
+        Pass::Position,
+        Pass::Normal,
+        Pass::Albedo,
+        Pass::Emission,
+        Pass::DiffuseDirect,
+        Pass::DiffuseIndirect,
+        Pass::SpecularDirect,
+        Pass::SpecularIndirect,
+        Pass::ObjectId,
+        Pass::MaterialId,
+    ];
+
+    /// Name of the layer or file of the pass
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Depth => "depth",
+            Pass::Position => "position",
+            Pass::Normal => "normal",
+            Pass::Albedo => "albedo",
+            Pass::Emission => "emission",
+            Pass::DiffuseDirect => "diffuse_direct",
+            Pass::DiffuseIndirect => "diffuse_indirect",
+            Pass::SpecularDirect => "specular_direct",
+            Pass::SpecularIndirect => "specular_indirect",
+            Pass::ObjectId => "object_id",
+            Pass::MaterialId => "material_id",
+        }
+    }
+
+    /// Names of the channels, filled with the components of the pass in order
+    fn channels(self) -> &'static [&'static str] {
+        match self {
+            Pass::Depth => &["Z"],
+            Pass::ObjectId | Pass::MaterialId => &["Y"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Whether the pass splits the light paths, which the integrator must support
+    pub fn is_light(self) -> bool {
+        matches!(
+            self,
+            Pass::Emission
+                | Pass::DiffuseDirect
+                | Pass::DiffuseIndirect
+                | Pass::SpecularDirect
+                | Pass::SpecularIndirect
+        )
+    }
+
+    /// Identifiers can't be averaged, pixels keep the one of their heaviest sample
+    fn is_id(self) -> bool {
+        matches!(self, Pass::ObjectId | Pass::MaterialId)
+    }
+}
+
+/// Set of render passes, listed in the profile
+#[derive(Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "Vec<Pass>")]
+pub struct Passes(u16);
+
+impl From<Vec<Pass>> for Passes {
+    fn from(passes: Vec<Pass>) -> Self {
+        Self(
+            passes
+                .into_iter()
+                .fold(0, |set, pass| set | 1 << pass as u16),
+        )
+    }
+}
+
+impl fmt::Debug for Passes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Passes {
+    /// Passes of the set, in a fixed order
+    pub fn iter(self) -> impl Iterator<Item = Pass> {
+        Pass::ALL
+            .into_iter()
+            .filter(move |&pass| self.0 & 1 << pass as u16 != 0)
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether any pass splits the light paths
+    pub fn has_light(self) -> bool {
+        self.iter().any(Pass::is_light)
+    }
+}
+
+/// Light of a camera sample split by the way it reached the camera
+#[derive(Clone, Copy)]
+pub struct LightPasses {
+    emission: Vector3<f32>,
+    diffuse_direct: Vector3<f32>,
+    diffuse_indirect: Vector3<f32>,
+    specular_direct: Vector3<f32>,
+    specular_indirect: Vector3<f32>,
+}
+
+impl Default for LightPasses {
+    fn default() -> Self {
+        Self {
+            emission: Vector3::zero(),
+            diffuse_direct: Vector3::zero(),
+            diffuse_indirect: Vector3::zero(),
+            specular_direct: Vector3::zero(),
+            specular_indirect: Vector3::zero(),
+        }
+    }
+}
+
+/// Light gathered at a scattering event of a path, weighted by the throughput of the path
+#[derive(Clone, Copy)]
+pub struct VertexLight {
+    /// Emission of the surface
+    pub emitted: Vector3<f32>,
+    /// Light sampled from the light sources
+    pub direct: Vector3<f32>,
+    /// Part of `direct` reflected by diffuse lobes
+    pub direct_diffuse: Vector3<f32>,
+}
+
+impl Default for VertexLight {
+    fn default() -> Self {
+        Self {
+            emitted: Vector3::zero(),
+            direct: Vector3::zero(),
+            direct_diffuse: Vector3::zero(),
+        }
+    }
+}
+
+impl LightPasses {
+    /// Light gathered at the scattering event following `scatterings` others
+    pub fn add_vertex(&mut self, vertex: &VertexLight, scatterings: usize, first_diffuse: bool) {
+        self.add_emitted(vertex.emitted, scatterings, first_diffuse);
+        self.add_direct(
+            vertex.direct,
+            vertex.direct_diffuse,
+            scatterings,
+            first_diffuse,
+        );
+    }
+
+    /// Light of an emitter reached after `scatterings` scattering events, the first one through a
+    /// diffuse lobe or not
+    pub fn add_emitted(&mut self, color: Vector3<f32>, scatterings: usize, diffuse: bool) {
+        match scatterings {
+            0 => self.emission += color,
+            1 if diffuse => self.diffuse_direct += color,
+            1 => self.specular_direct += color,
+            _ => self.add_indirect(color, diffuse),
+        }
+    }
+
+    /// Light sampled from the light sources at the scattering event following `scatterings`
+    /// others, `diffuse` being its part reflected by diffuse lobes
+    pub fn add_direct(
+        &mut self,
+        color: Vector3<f32>,
+        diffuse: Vector3<f32>,
+        scatterings: usize,
+        first_diffuse: bool,
+    ) {
+        if scatterings == 0 {
+            self.diffuse_direct += diffuse;
+            self.specular_direct += color - diffuse;
+        } else {
+            self.add_indirect(color, first_diffuse);
+        }
+    }
+
+    /// Light that went through several scattering events
+    pub fn add_indirect(&mut self, color: Vector3<f32>, diffuse: bool) {
+        if diffuse {
+            self.diffuse_indirect += color;
+        } else {
+            self.specular_indirect += color;
+        }
+    }
+
+    /// Light of other passes, converted to other colors
+    pub fn add_converted<F>(&mut self, other: &LightPasses, convert: F)
+    where
+        F: Fn(Vector3<f32>) -> Vector3<f32>,
+    {
+        self.emission += convert(other.emission);
+        self.diffuse_direct += convert(other.diffuse_direct);
+        self.diffuse_indirect += convert(other.diffuse_indirect);
+        self.specular_direct += convert(other.specular_direct);
+        self.specular_indirect += convert(other.specular_indirect);
+    }
+}
+
+/// Values of the passes for a camera ray, `None` for the passes the sample doesn't reach (the
+/// geometry passes of rays hitting nothing). Surfaces mostly transparent are seen through.
+pub fn sample_passes(
+    passes: Passes,
+    scene: &Scene,
+    ray: &Ray,
+    light: &LightPasses,
+) -> Vec<Option<Vector3<f32>>> {
+    let hit = scene
+        .kdtree
+        .intersect(&ray.origin, &ray.direction)
+        .into_iter()
+        .flat_map(|index| {
+            let model = &scene.models[index];
+            model
+                .intersect(ray)
+                .into_iter()
+                .map(move |hit| (index, hit))
+        })
+        .filter(|(index, hit)| hit.get_material_sample(&scene.models[*index]).opacity >= 0.5)
+        .min_by(|(_, hit1), (_, hit2)| hit1.get_dist().total_cmp(&hit2.get_dist()));
+    let surface = |value: &dyn Fn(usize, &Hit) -> Vector3<f32>| {
+        hit.as_ref().map(|(index, hit)| value(*index, hit))
+    };
+    let scalar = |value: f32| Vector3::new(value, value, value);
+
+    passes
+        .iter()
+        .map(|pass| match pass {
+            Pass::Depth => surface(&|_, hit| {
+                let forward = scene
+                    .camera
+                    .apply_transform_vector(&Vector3::new(0., 0., -1.));
+                scalar((hit.get_position() - scene.camera.position()).dot(forward))
+            }),
+            Pass::Position => surface(&|_, hit| hit.get_position()),
+            Pass::Normal => {
+                surface(&|index, hit| hit.get_normal(scene.models[index].get_material()))
+            }
+            Pass::Albedo => {
+                surface(&|index, hit| hit.get_material_sample(&scene.models[index]).albedo)
+            }
+            Pass::Emission => Some(light.emission),
+            Pass::DiffuseDirect => Some(light.diffuse_direct),
+            Pass::DiffuseIndirect => Some(light.diffuse_indirect),
+            Pass::SpecularDirect => Some(light.specular_direct),
+            Pass::SpecularIndirect => Some(light.specular_indirect),
+            Pass::ObjectId => surface(&|index, _| scalar((index + 1) as f32)),
+            Pass::MaterialId => surface(&|index, _| scalar((scene.material_ids[index] + 1) as f32)),
+        })
+        .collect()
+}
+
+/// Splat the values of the passes of a sample on the passes of a pixel
+pub fn splat(
+    passes: Passes,
+    films: &mut [FilmPixel],
+    values: &[Option<Vector3<f32>>],
+    weight: f32,
+) {
+    for ((pass, film), value) in passes.iter().zip(films).zip(values) {
+        let Some(value) = value else {
+            continue;
+        };
+        if pass.is_id() {
+            film.keep_heaviest(*value, weight);
+        } else {
+            film.splat(*value, weight);
+        }
+    }
+}
+
+/// Add the splats of other samples to the passes of a pixel
+pub fn merge(passes: Passes, films: &mut [FilmPixel], others: &[FilmPixel]) {
+    for ((pass, film), other) in passes.iter().zip(films).zip(others) {
+        if pass.is_id() {
+            film.keep_heaviest(other.average(), other.weight());
+        } else {
+            *film += *other;
+        }
+    }
+}
+
+/// Save the passes of the pixels as the layers of an OpenEXR file, or as one OpenEXR file per
+/// pass in a directory when the path has another extension
+pub fn save<P: AsRef<Path>>(
+    passes: Passes,
+    resolution: Resolution,
+    buffer: &[PixelAccumulator],
+    path: P,
+) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
+    let size = (resolution.width as usize, resolution.height as usize);
+    let layers: Vec<_> = passes
+        .iter()
+        .enumerate()
+        .map(|(index, pass)| {
+            let channels = pass
+                .channels()
+                .iter()
+                .enumerate()
+                .map(|(component, name)| {
+                    let samples = buffer
+                        .iter()
+                        .map(|pixel| {
+                            let value = pixel.passes()[index].average()[component];
+                            if pass.is_id() {
+                                value.round()
+                            } else {
+                                value
+                            }
+                        })
+                        .collect();
+                    AnyChannel::new(*name, FlatSamples::F32(samples))
+                })
+                .collect();
+            let layer = Layer::new(
+                size,
+                LayerAttributes::named(pass.name()),
+                Encoding::FAST_LOSSLESS,
+                AnyChannels::sort(channels),
+            );
+            (pass, layer)
+        })
+        .collect();
+
+    let path = path.as_ref();
+    if path.extension().is_some_and(|extension| extension == "exr") {
+        let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+        let layers: Vec<_> = layers.into_iter().map(|(_, layer)| layer).collect();
+        Image::from_layers(attributes, layers)
+            .write()
+            .to_file(path)?;
+    } else {
+        create_dir_all(path)?;
+        for (pass, layer) in layers {
+            let file = path.join(format!("{}.exr", pass.name()));
+            Image::from_layer(layer).write().to_file(file)?;
+        }
+    }
+    Ok(())
+}
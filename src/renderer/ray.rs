@@ -1,6 +1,6 @@
 use cgmath::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ray {
     /// Origin of the ray
     pub origin: Vector3<f32>,
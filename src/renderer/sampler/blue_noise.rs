@@ -0,0 +1,151 @@
+use super::sobol::{nested_uniform_scramble, sobol_0, sobol_1};
+use super::{hash, to_unit, PixelSample, Sampler};
+use once_cell::sync::Lazy;
+
+/// Side of the tiled blue noise mask
+const SIZE: usize = 64;
+/// Standard deviation of the energy function of the void and cluster algorithm (in pixels)
+const SIGMA: f32 = 1.5;
+
+/// Threshold in [0, 1) for each pixel of the mask, generated with the void and cluster method
+static MASK: Lazy<Vec<f32>> = Lazy::new(void_and_cluster);
+
+/// Sobol sequence scrambled identically in every pixel, each pixel shifting it by the blue noise
+/// mask (at a different offset per dimension). Neighbouring pixels get well spread values, so the
+/// remaining error is high frequency noise which is less visible and easier to filter.
+pub struct BlueNoiseSampler {
+    pixel: PixelSample,
+    dimension: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(pixel: PixelSample) -> Self {
+        Self {
+            pixel,
+            dimension: 0,
+        }
+    }
+
+    /// Value of the mask for the pixel, moved around for each dimension
+    fn shift(&self, dimension: u32) -> f32 {
+        let offset = hash(&[dimension]) as usize;
+        let x = (self.pixel.x as usize + offset) % SIZE;
+        let y = (self.pixel.y as usize + (offset >> 16)) % SIZE;
+        MASK[x + y * SIZE]
+    }
+
+    fn rotate(&self, value: u32, dimension: u32) -> f32 {
+        let value = to_unit(value) + self.shift(dimension);
+        (value - value.floor()).min(1. - f32::EPSILON)
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let seed = hash(&[dimension]);
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed);
+        self.rotate(
+            nested_uniform_scramble(sobol_0(index), hash(&[seed, 0])),
+            2 * dimension,
+        )
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let seed = hash(&[dimension]);
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed);
+        (
+            self.rotate(
+                nested_uniform_scramble(sobol_0(index), hash(&[seed, 0])),
+                2 * dimension,
+            ),
+            self.rotate(
+                nested_uniform_scramble(sobol_1(index), hash(&[seed, 1])),
+                2 * dimension + 1,
+            ),
+        )
+    }
+}
+
+/// Ulichney's void and cluster method: points are ranked by adding them one at a time where the
+/// pattern is the least dense (measured by a Gaussian energy on the torus).
+fn void_and_cluster() -> Vec<f32> {
+    let count = SIZE * SIZE;
+
+    // Energy of a point at each toroidal offset
+    let kernel: Vec<f32> = (0..count)
+        .map(|i| {
+            let distance = |d: usize| d.min(SIZE - d) as f32;
+            let (dx, dy) = (distance(i % SIZE), distance(i / SIZE));
+            (-(dx * dx + dy * dy) / (2. * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+    let toggle = |energy: &mut [f32], points: &mut [bool], point: usize, add: bool| {
+        points[point] = add;
+        let sign = if add { 1. } else { -1. };
+        let (px, py) = (point % SIZE, point / SIZE);
+        for (i, energy) in energy.iter_mut().enumerate() {
+            let dx = (i % SIZE + SIZE - px) % SIZE;
+            let dy = (i / SIZE + SIZE - py) % SIZE;
+            *energy += sign * kernel[dx + dy * SIZE];
+        }
+    };
+    // Densest point and emptiest spot of the pattern
+    let tightest_cluster = |energy: &[f32], points: &[bool]| {
+        (0..count)
+            .filter(|&i| points[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |energy: &[f32], points: &[bool]| {
+        (0..count)
+            .filter(|&i| !points[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // Initial pattern of random points, relaxed by moving the densest point to the emptiest spot
+    let mut points = vec![false; count];
+    let mut energy = vec![0.; count];
+    for i in 0..count {
+        if hash(&[i as u32]).is_multiple_of(10) {
+            toggle(&mut energy, &mut points, i, true);
+        }
+    }
+    for _ in 0..count {
+        let cluster = tightest_cluster(&energy, &points);
+        toggle(&mut energy, &mut points, cluster, false);
+        let void = largest_void(&energy, &points);
+        toggle(&mut energy, &mut points, void, true);
+        if void == cluster {
+            break;
+        }
+    }
+    let initial_points = points.clone();
+    let initial_energy = energy.clone();
+    let initial_count = points.iter().filter(|&&point| point).count();
+
+    // Rank the initial points by removing the densest ones first
+    let mut ranks = vec![0; count];
+    for rank in (0..initial_count).rev() {
+        let cluster = tightest_cluster(&energy, &points);
+        toggle(&mut energy, &mut points, cluster, false);
+        ranks[cluster] = rank;
+    }
+
+    // Then fill the voids
+    let (mut points, mut energy) = (initial_points, initial_energy);
+    for rank in initial_count..count {
+        let void = largest_void(&energy, &points);
+        toggle(&mut energy, &mut points, void, true);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / count as f32)
+        .collect()
+}
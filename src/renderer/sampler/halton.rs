@@ -0,0 +1,61 @@
+use super::{hash, pixel_seed, to_unit, PixelSample, Sampler};
+use once_cell::sync::Lazy;
+
+/// Number of dimensions with a prime base, the next ones are drawn from the hash
+const DIMENSIONS: usize = 256;
+
+static PRIMES: Lazy<Vec<u32>> = Lazy::new(|| {
+    let mut primes: Vec<u32> = Vec::with_capacity(DIMENSIONS);
+    let mut candidate = 2;
+    while primes.len() < DIMENSIONS {
+        if primes.iter().all(|prime| candidate % prime != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+});
+
+/// Halton sequence (radical inverse in the n-th prime base for the n-th dimension), shifted by a
+/// random offset in each pixel (Cranley-Patterson rotation)
+pub struct HaltonSampler {
+    pixel: PixelSample,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(pixel: PixelSample) -> Self {
+        Self {
+            pixel,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn next_1d(&mut self) -> f32 {
+        let seed = pixel_seed(&self.pixel, self.dimension);
+        let offset = to_unit(seed);
+        let value = match PRIMES.get(self.dimension as usize) {
+            Some(&base) => radical_inverse(base, self.pixel.index as u64),
+            None => to_unit(hash(&[seed, self.pixel.index as u32])),
+        };
+        self.dimension += 1;
+        let value = value + offset;
+        (value - value.floor()).min(1. - f32::EPSILON)
+    }
+}
+
+/// Digits of the index in the base mirrored around the radix point
+fn radical_inverse(base: u32, mut index: u64) -> f32 {
+    let base = u64::from(base);
+    let inverse_base = 1. / base as f64;
+    let mut reversed = 0;
+    let mut inverse_base_power = 1.;
+    while index > 0 {
+        reversed = reversed * base + index % base;
+        inverse_base_power *= inverse_base;
+        index /= base;
+    }
+    (reversed as f64 * inverse_base_power) as f32
+}
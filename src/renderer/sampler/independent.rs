@@ -0,0 +1,32 @@
+use super::{PixelSample, Sampler};
+use crate::config::Resolution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Uniform random numbers
+pub struct IndependentSampler {
+    rand_gen: StdRng,
+}
+
+impl IndependentSampler {
+    // Distance between the seeds of two pixels, so that no sample is seeded twice whatever the
+    // number of samples
+    const SEED_STRIDE: u64 = 1 << 32;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rand_gen: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn for_pixel(resolution: Resolution, pixel: PixelSample) -> Self {
+        let i = u64::from(pixel.x + pixel.y * resolution.width);
+        Self::new(pixel.index as u64 + 1 + i * Self::SEED_STRIDE)
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn next_1d(&mut self) -> f32 {
+        self.rand_gen.gen()
+    }
+}
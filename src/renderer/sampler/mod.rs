@@ -0,0 +1,149 @@
+mod blue_noise;
+mod halton;
+mod independent;
+mod sobol;
+mod stratified;
+
+use crate::config::Profile;
+pub use blue_noise::BlueNoiseSampler;
+pub use halton::HaltonSampler;
+pub use independent::IndependentSampler;
+use serde::Deserialize;
+pub use sobol::SobolSampler;
+pub use stratified::StratifiedSampler;
+
+/// Source of the sample values of a path. Each request consumes the next dimensions of the
+/// sample, so low discrepancy samplers can correlate them across the samples of a pixel.
+pub trait Sampler {
+    /// Next dimension of the sample, in [0, 1)
+    fn next_1d(&mut self) -> f32;
+
+    /// Next two dimensions of the sample (used together, e.g. for a direction), in [0, 1)²
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+#[derive(Default, Copy, Debug, Clone, Deserialize, PartialEq, Eq)]
+pub enum SamplerType {
+    /// Uniform random numbers
+    #[serde(rename = "INDEPENDENT")]
+    #[default]
+    Independent,
+    /// Jittered strata shuffled independently for each dimension (Latin hypercube)
+    #[serde(rename = "STRATIFIED")]
+    Stratified,
+    /// Halton sequence, randomly shifted in each pixel
+    #[serde(rename = "HALTON")]
+    Halton,
+    /// Owen scrambled Sobol sequence
+    #[serde(rename = "SOBOL")]
+    Sobol,
+    /// Sobol sequence shared by the pixels, shifted by a blue noise mask so the error looks like
+    /// high frequency noise
+    #[serde(rename = "BLUE_NOISE")]
+    BlueNoise,
+}
+
+/// Sample of a pixel the dimensions are generated for
+#[derive(Copy, Clone, Debug)]
+pub struct PixelSample {
+    pub x: u32,
+    pub y: u32,
+    /// Index of the sample in the pixel (starting at 0)
+    pub index: usize,
+}
+
+pub fn get_sampler(profile: &Profile, pixel: PixelSample) -> Box<dyn Sampler> {
+    match profile.sampler {
+        SamplerType::Independent => {
+            Box::new(IndependentSampler::for_pixel(profile.resolution, pixel))
+        }
+        SamplerType::Stratified => Box::new(StratifiedSampler::new(pixel, profile.samples)),
+        SamplerType::Halton => Box::new(HaltonSampler::new(pixel)),
+        SamplerType::Sobol => Box::new(SobolSampler::new(pixel)),
+        SamplerType::BlueNoise => Box::new(BlueNoiseSampler::new(pixel)),
+    }
+}
+
+/// Hash of a few integers (used to seed the scrambling of each pixel and dimension)
+fn hash(values: &[u32]) -> u32 {
+    values.iter().fold(0x9e37_79b9, |hash, &value| {
+        // lowbias32 mixer
+        let mut x = hash ^ value.wrapping_add(0x7f4a_7c15).wrapping_add(hash << 6);
+        x ^= x >> 16;
+        x = x.wrapping_mul(0x21f0_aaad);
+        x ^= x >> 15;
+        x = x.wrapping_mul(0x735a_2d97);
+        x ^ (x >> 15)
+    })
+}
+
+/// Map 32 random bits to [0, 1)
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1. / (1u32 << 24) as f32)
+}
+
+/// Seed of a pixel, dimension pair
+fn pixel_seed(pixel: &PixelSample, dimension: u32) -> u32 {
+    hash(&[pixel.x, pixel.y, dimension])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First two dimensions of the samples of a pixel
+    fn first_2d<F>(samples: usize, sampler: F) -> Vec<(f32, f32)>
+    where
+        F: Fn(PixelSample) -> Box<dyn Sampler>,
+    {
+        (0..samples)
+            .map(|index| sampler(PixelSample { x: 3, y: 5, index }).next_2d())
+            .collect()
+    }
+
+    /// Whether each of the strata of [0, 1) holds exactly one of the values
+    fn one_per_stratum(values: impl Iterator<Item = f32>, strata: usize) -> bool {
+        let mut counts = vec![0; strata];
+        for value in values {
+            assert!((0. ..1.).contains(&value));
+            counts[(value * strata as f32) as usize] += 1;
+        }
+        counts.iter().all(|&count| count == 1)
+    }
+
+    #[test]
+    fn stratified() {
+        for samples in [10, 16] {
+            let points = first_2d(samples, |pixel| {
+                Box::new(StratifiedSampler::new(pixel, samples))
+            });
+            assert!(one_per_stratum(points.iter().map(|p| p.0), samples));
+            assert!(one_per_stratum(points.iter().map(|p| p.1), samples));
+        }
+    }
+
+    #[test]
+    fn halton() {
+        // The radical inverse stratifies the powers of the base of the dimension
+        let points = first_2d(16, |pixel| Box::new(HaltonSampler::new(pixel)));
+        assert!(one_per_stratum(points.iter().map(|p| p.0), 16));
+        let points = first_2d(27, |pixel| Box::new(HaltonSampler::new(pixel)));
+        assert!(one_per_stratum(points.iter().map(|p| p.1), 27));
+    }
+
+    #[test]
+    fn sobol() {
+        let samples = 16;
+        let points = first_2d(samples, |pixel| Box::new(SobolSampler::new(pixel)));
+        assert!(one_per_stratum(points.iter().map(|p| p.0), samples));
+        assert!(one_per_stratum(points.iter().map(|p| p.1), samples));
+        // The points also stratify the 4x4 grid of the square
+        let mut cells = [[0; 4]; 4];
+        for (u, v) in points {
+            cells[(u * 4.) as usize][(v * 4.) as usize] += 1;
+        }
+        assert!(cells.iter().flatten().all(|&count| count == 1));
+    }
+}
@@ -0,0 +1,75 @@
+use super::{hash, pixel_seed, to_unit, PixelSample, Sampler};
+
+/// Owen scrambled Sobol sequence. Every request takes the first two Sobol dimensions with its own
+/// scrambling and shuffled order, which decorrelates the requests while keeping each 1D or 2D
+/// projection well stratified (Burley, "Practical Hash-based Owen Scrambling").
+pub struct SobolSampler {
+    pixel: PixelSample,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(pixel: PixelSample) -> Self {
+        Self {
+            pixel,
+            dimension: 0,
+        }
+    }
+
+    fn next_seed(&mut self) -> u32 {
+        let seed = pixel_seed(&self.pixel, self.dimension);
+        self.dimension += 1;
+        seed
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn next_1d(&mut self) -> f32 {
+        let seed = self.next_seed();
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed);
+        to_unit(nested_uniform_scramble(sobol_0(index), hash(&[seed, 0])))
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let seed = self.next_seed();
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed);
+        (
+            to_unit(nested_uniform_scramble(sobol_0(index), hash(&[seed, 0]))),
+            to_unit(nested_uniform_scramble(sobol_1(index), hash(&[seed, 1]))),
+        )
+    }
+}
+
+/// First Sobol dimension (van der Corput sequence)
+pub(super) fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+/// Second Sobol dimension
+pub(super) fn sobol_1(mut index: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut value = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    value
+}
+
+/// Owen scrambling of the bits of a value, from the most significant one
+pub(super) fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+/// Hash where each bit only depends on the lower ones
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
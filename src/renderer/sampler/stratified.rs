@@ -0,0 +1,71 @@
+use super::{hash, pixel_seed, to_unit, PixelSample, Sampler};
+
+/// Splits each dimension in one stratum per sample of the pixel, jittered in the stratum. The
+/// strata are shuffled independently in every dimension, so any projection stays stratified.
+pub struct StratifiedSampler {
+    pixel: PixelSample,
+    /// Number of strata (samples per pixel)
+    strata: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(pixel: PixelSample, samples: usize) -> Self {
+        Self {
+            pixel,
+            strata: samples.clamp(1, u32::MAX as usize) as u32,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn next_1d(&mut self) -> f32 {
+        // Samples past the count start a new set of strata
+        let round = (self.pixel.index / self.strata as usize) as u32;
+        let index = (self.pixel.index % self.strata as usize) as u32;
+        let seed = hash(&[pixel_seed(&self.pixel, self.dimension), round]);
+        self.dimension += 1;
+
+        let stratum = permute(index, self.strata, seed);
+        let jitter = to_unit(hash(&[seed, index]));
+        ((stratum as f32 + jitter) / self.strata as f32).min(1. - f32::EPSILON)
+    }
+}
+
+/// Element of a random permutation of [0, length) chosen by the seed (Kensler's hashed
+/// permutation, cycling back into range)
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    if length <= 1 {
+        return 0;
+    }
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            return index.wrapping_add(seed) % length;
+        }
+    }
+}
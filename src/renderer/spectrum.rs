@@ -0,0 +1,469 @@
+use super::color::{chromatic_adaptation, WorkingSpace};
+use super::material_sample::MaterialSample;
+use super::sampler::Sampler;
+use crate::config::Profile;
+use crate::scene::internal::{Medium, Subsurface};
+use cgmath::*;
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+
+/// Range of the sampled wavelengths, in nanometers
+const LAMBDA_MIN: f32 = 360.;
+const LAMBDA_MAX: f32 = 830.;
+/// Number of wavelengths carried by a path
+const WAVELENGTHS: usize = 3;
+/// Spacing of the wavelengths the color matching functions are integrated over
+const INTEGRATION_STEP: f64 = 5.;
+/// Number of entries along each axis of the coefficient tables
+const TABLE_SIZE: usize = 32;
+/// Bound of the fitted coefficients
+const MAX_COEFFICIENT: f64 = 1e4;
+
+static REC709_UPLIFT: Lazy<Uplift> = Lazy::new(|| Uplift::new(WorkingSpace::Rec709));
+static ACESCG_UPLIFT: Lazy<Uplift> = Lazy::new(|| Uplift::new(WorkingSpace::AcesCg));
+
+/// Colors of the light carried along a path: RGB, or a few wavelengths in spectral rendering
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Spectrum {
+    #[default]
+    Rgb,
+    /// Hero wavelength followed by others evenly spread over the range, with their densities
+    Wavelengths {
+        lambda: Vector3<f32>,
+        pdf: Vector3<f32>,
+        working_space: WorkingSpace,
+        /// Whether only the hero wavelength is still carried, after a dispersive surface
+        hero_only: bool,
+    },
+}
+
+impl Spectrum {
+    /// RGB, or wavelengths importance sampled over the visible range in spectral rendering
+    pub fn new(profile: &Profile, sampler: &mut dyn Sampler) -> Self {
+        if !profile.spectral {
+            return Spectrum::Rgb;
+        }
+        let u = sampler.next_1d();
+        let mut lambda = Vector3::zero();
+        let mut pdf = Vector3::zero();
+        for i in 0..WAVELENGTHS {
+            let u = (u + i as f32 / WAVELENGTHS as f32).fract();
+            lambda[i] = sample_visible(u);
+            pdf[i] = visible_pdf(lambda[i]);
+        }
+        Spectrum::Wavelengths {
+            lambda,
+            pdf,
+            working_space: profile.working_space,
+            hero_only: false,
+        }
+    }
+
+    /// Values of a reflectance at the wavelengths, its components being between 0 and 1
+    pub fn reflectance(&self, color: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            Spectrum::Rgb => color,
+            Spectrum::Wavelengths {
+                lambda,
+                working_space,
+                ..
+            } => uplift(*working_space).reflectance(color, *lambda),
+        }
+    }
+
+    /// Values of an emission or a coefficient at the wavelengths
+    pub fn unbounded(&self, color: Vector3<f32>) -> Vector3<f32> {
+        let max = color.x.max(color.y).max(color.z);
+        match self {
+            Spectrum::Rgb => color,
+            _ if max <= 0. => Vector3::zero(),
+            // Halved so the spectrum doesn't saturate, its shape staying smooth
+            _ => self.reflectance(color / (2. * max)) * (2. * max),
+        }
+    }
+
+    /// Medium with its coefficients at the wavelengths
+    pub fn medium(&self, medium: &Medium) -> Medium {
+        Medium {
+            absorption: self.unbounded(medium.absorption),
+            scattering: self.unbounded(medium.scattering),
+            anisotropy: medium.anisotropy,
+        }
+    }
+
+    /// Convert the colors of a material sample to the wavelengths, the index of refraction of
+    /// dispersive materials being the one of the hero wavelength. The other wavelengths are then
+    /// dropped from the path, their refracted directions being different.
+    pub fn material(&mut self, material: &mut MaterialSample, throughput: &mut Vector3<f32>) {
+        material.albedo = self.reflectance(material.albedo);
+        material.emissive = self.unbounded(material.emissive);
+        material.subsurface = material.subsurface.map(|subsurface| Subsurface {
+            albedo: self.reflectance(subsurface.albedo),
+            radius: self.unbounded(subsurface.radius),
+        });
+        if let (
+            Spectrum::Wavelengths {
+                lambda, hero_only, ..
+            },
+            Some(dispersion),
+        ) = (self, material.dispersion)
+        {
+            material.ior = dispersion.ior(lambda.x);
+            if !*hero_only {
+                *hero_only = true;
+                *throughput = Vector3::new(throughput.x * WAVELENGTHS as f32, 0., 0.);
+            }
+        }
+    }
+
+    /// Linear RGB color in the working space of the light at the wavelengths
+    pub fn to_rgb(self, values: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            Spectrum::Rgb => values,
+            Spectrum::Wavelengths {
+                lambda,
+                pdf,
+                working_space,
+                ..
+            } => {
+                let xyz: Vector3<f64> = (0..WAVELENGTHS)
+                    .filter(|&i| pdf[i] > 0.)
+                    .map(|i| color_matching(lambda[i] as f64) * (values[i] / pdf[i]) as f64)
+                    .sum();
+                let xyz = xyz / (WAVELENGTHS as f64 * INTEGRALS.y);
+                uplift(working_space).xyz_to_rgb * xyz.cast::<f32>().unwrap()
+            }
+        }
+    }
+}
+
+/// Density of sampling the wavelengths proportionally to the sensitivity of the eye
+fn visible_pdf(lambda: f32) -> f32 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.;
+    }
+    0.003_939_804 / (0.0072 * (lambda - 538.)).cosh().powi(2)
+}
+
+/// Wavelength sampled proportionally to the sensitivity of the eye (Radziszewski et al.)
+fn sample_visible(u: f32) -> f32 {
+    538. - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh()
+}
+
+/// CIE 1931 color matching functions, fitted with piecewise Gaussians (Wyman et al.)
+fn color_matching(lambda: f64) -> Vector3<f64> {
+    let gaussian = |mu: f64, sigma_low: f64, sigma_high: f64| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vector3::new(
+        1.056 * gaussian(599.8, 37.9, 31.0) + 0.362 * gaussian(442.0, 16.0, 26.7)
+            - 0.065 * gaussian(501.1, 20.4, 26.2),
+        0.821 * gaussian(568.8, 46.9, 40.5) + 0.286 * gaussian(530.9, 16.3, 31.1),
+        1.217 * gaussian(437.0, 11.8, 36.0) + 0.681 * gaussian(459.0, 26.0, 13.8),
+    )
+}
+
+/// Wavelengths the color matching functions are integrated over, with their weights
+fn integration_wavelengths() -> impl Iterator<Item = (f64, Vector3<f64>)> {
+    let count = ((LAMBDA_MAX - LAMBDA_MIN) as f64 / INTEGRATION_STEP) as usize;
+    (0..count).map(|i| {
+        let lambda = LAMBDA_MIN as f64 + (i as f64 + 0.5) * INTEGRATION_STEP;
+        (lambda, color_matching(lambda) * INTEGRATION_STEP)
+    })
+}
+
+/// Integrals of the color matching functions over the range
+static INTEGRALS: Lazy<Vector3<f64>> =
+    Lazy::new(|| integration_wavelengths().map(|(_, weight)| weight).sum());
+
+/// Logistic-like function of Jakob and Hanika, mapping the reals between 0 and 1
+fn sigmoid(x: f32) -> f32 {
+    let root = (1. + x * x).sqrt();
+    if x >= 0. {
+        0.5 + x / (2. * root)
+    } else {
+        // Without the cancellation of 0.5 - |x| / (2 * root)
+        0.5 / (root * (root - x))
+    }
+}
+
+fn sigmoid_f64(x: f64) -> f64 {
+    0.5 + x / (2. * (1. + x * x).sqrt())
+}
+
+/// Wavelength mapped between 0 and 1 over the range, as the polynomials are fitted
+fn normalized(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN as f64) / (LAMBDA_MAX - LAMBDA_MIN) as f64
+}
+
+/// Smooth reflectance spectra of the colors of a working space (Jakob and Hanika, "A
+/// Low-Dimensional Function Space for Efficient Spectral Upsampling"). Each spectrum is the
+/// sigmoid of a quadratic polynomial, whose coefficients are fitted for a grid of colors.
+struct Uplift {
+    /// CIE XYZ (relative to the equal energy white) to linear RGB of the working space
+    xyz_to_rgb: Matrix3<f32>,
+    /// Values of the largest component the grid is fitted for, denser near 0 and 1
+    scales: Vec<f32>,
+    /// Coefficients for each largest component, its value, and the ratios of the two others
+    table: Vec<[f32; 3]>,
+}
+
+fn uplift(working_space: WorkingSpace) -> &'static Uplift {
+    match working_space {
+        WorkingSpace::Rec709 => &REC709_UPLIFT,
+        WorkingSpace::AcesCg => &ACESCG_UPLIFT,
+    }
+}
+
+impl Uplift {
+    fn new(working_space: WorkingSpace) -> Self {
+        // The equal energy spectrum is the white of the working space
+        let white = (*INTEGRALS / INTEGRALS.y).cast::<f32>().unwrap();
+        let xyz_to_rgb = working_space.rgb_to_xyz().invert().unwrap()
+            * chromatic_adaptation(white, working_space.white());
+        let to_rgb = xyz_to_rgb.cast::<f64>().unwrap() / INTEGRALS.y;
+        // Contribution of each integration wavelength to the color of a spectrum
+        let weights: Vec<_> = integration_wavelengths()
+            .map(|(lambda, weight)| (normalized(lambda), to_rgb * weight))
+            .collect();
+
+        let smoothstep = |x: f32| x * x * (3. - 2. * x);
+        let last = (TABLE_SIZE - 1) as f32;
+        let scales: Vec<_> = (0..TABLE_SIZE)
+            .map(|k| smoothstep(smoothstep(k as f32 / last)))
+            .collect();
+
+        // The fits start from the brightest colors, whose spectra are the smoothest, each one
+        // starting from the coefficients of the brighter neighbor
+        let chains: Vec<Vec<Vector3<f64>>> = (0..3 * TABLE_SIZE * TABLE_SIZE)
+            .into_par_iter()
+            .map(|index| {
+                let (largest, j, i) = (
+                    index / (TABLE_SIZE * TABLE_SIZE),
+                    (index / TABLE_SIZE) % TABLE_SIZE,
+                    index % TABLE_SIZE,
+                );
+                let color = |k: usize| {
+                    let z = scales[k] as f64;
+                    let mut color = Vector3::zero();
+                    color[largest] = z;
+                    color[(largest + 1) % 3] = i as f64 / last as f64 * z;
+                    color[(largest + 2) % 3] = j as f64 / last as f64 * z;
+                    color
+                };
+                let mut chain = vec![Vector3::zero(); TABLE_SIZE];
+                let mut coefficients = Vector3::zero();
+                for (k, entry) in chain.iter_mut().enumerate().rev() {
+                    coefficients = fit(&weights, color(k), coefficients);
+                    *entry = coefficients;
+                }
+                chain
+            })
+            .collect();
+
+        // Laid out by largest component, scale, then ratios
+        let mut table = vec![[0.; 3]; 3 * TABLE_SIZE * TABLE_SIZE * TABLE_SIZE];
+        for (index, chain) in chains.into_iter().enumerate() {
+            let (largest, ratios) = (
+                index / (TABLE_SIZE * TABLE_SIZE),
+                index % (TABLE_SIZE * TABLE_SIZE),
+            );
+            for (k, coefficients) in chain.into_iter().enumerate() {
+                table[(largest * TABLE_SIZE + k) * TABLE_SIZE * TABLE_SIZE + ratios] =
+                    coefficients.cast::<f32>().unwrap().into();
+            }
+        }
+        Self {
+            xyz_to_rgb,
+            scales,
+            table,
+        }
+    }
+
+    /// Values at the wavelengths of the spectrum of a color between 0 and 1
+    fn reflectance(&self, color: Vector3<f32>, lambda: Vector3<f32>) -> Vector3<f32> {
+        let color = color.map(|x| x.clamp(0., 1.));
+        if color.x == color.y && color.y == color.z {
+            return color;
+        }
+        let largest = if color.x >= color.y && color.x >= color.z {
+            0
+        } else if color.y >= color.z {
+            1
+        } else {
+            2
+        };
+        let z = color[largest];
+        let last = (TABLE_SIZE - 1) as f32;
+        let x = color[(largest + 1) % 3] / z * last;
+        let y = color[(largest + 2) % 3] / z * last;
+        let k = self
+            .scales
+            .partition_point(|&scale| scale <= z)
+            .clamp(1, TABLE_SIZE - 1)
+            - 1;
+        let (i, j) = (
+            (x as usize).min(TABLE_SIZE - 2),
+            (y as usize).min(TABLE_SIZE - 2),
+        );
+        let fraction = Vector3::new(
+            x - i as f32,
+            y - j as f32,
+            (z - self.scales[k]) / (self.scales[k + 1] - self.scales[k]),
+        );
+
+        let entry = |di: usize, dj: usize, dk: usize| {
+            let index =
+                ((largest * TABLE_SIZE + k + dk) * TABLE_SIZE + j + dj) * TABLE_SIZE + i + di;
+            Vector3::from(self.table[index])
+        };
+        let lerp = |a: Vector3<f32>, b: Vector3<f32>, t: f32| a + (b - a) * t;
+        let lower = lerp(
+            lerp(entry(0, 0, 0), entry(1, 0, 0), fraction.x),
+            lerp(entry(0, 1, 0), entry(1, 1, 0), fraction.x),
+            fraction.y,
+        );
+        let upper = lerp(
+            lerp(entry(0, 0, 1), entry(1, 0, 1), fraction.x),
+            lerp(entry(0, 1, 1), entry(1, 1, 1), fraction.x),
+            fraction.y,
+        );
+        let coefficients = lerp(lower, upper, fraction.z);
+        lambda.map(|lambda| {
+            let t = normalized(lambda as f64) as f32;
+            sigmoid((coefficients.x * t + coefficients.y) * t + coefficients.z)
+        })
+    }
+}
+
+/// Coefficients of the spectrum whose color is closest to a target, refined with Gauss-Newton
+/// iterations from an initial guess
+fn fit(
+    weights: &[(f64, Vector3<f64>)],
+    target: Vector3<f64>,
+    mut coefficients: Vector3<f64>,
+) -> Vector3<f64> {
+    // Color of the spectrum and its derivatives with respect to the coefficients
+    let evaluate = |coefficients: Vector3<f64>| {
+        let mut color = Vector3::zero();
+        let mut jacobian = Matrix3::zero();
+        for (t, weight) in weights {
+            let x = (coefficients.x * t + coefficients.y) * t + coefficients.z;
+            let value = sigmoid_f64(x);
+            let derivative = 0.5 / (1. + x * x).powf(1.5);
+            color += weight * value;
+            jacobian += Matrix3::from_cols(
+                weight * (derivative * t * t),
+                weight * (derivative * t),
+                weight * derivative,
+            );
+        }
+        (color, jacobian)
+    };
+
+    let (mut color, mut jacobian) = evaluate(coefficients);
+    let mut error = (color - target).magnitude2();
+    for _ in 0..50 {
+        // Relative to the brightness, dark colors being fitted as precisely as bright ones
+        if error.sqrt() < 1e-6 * target.magnitude().max(1e-9) {
+            break;
+        }
+        let Some(inverse) = jacobian.invert() else {
+            break;
+        };
+        let step = inverse * (color - target);
+        // Halve the step until the error decreases
+        let mut scale = 1.;
+        let mut improved = false;
+        for _ in 0..16 {
+            let candidate = coefficients - step * scale;
+            // The spectra of colors out of reach only get steeper, NaN steps being dropped too
+            let bounded = [candidate.x, candidate.y, candidate.z]
+                .iter()
+                .all(|c| c.abs() <= MAX_COEFFICIENT);
+            if !bounded {
+                scale *= 0.5;
+                continue;
+            }
+            let (candidate_color, candidate_jacobian) = evaluate(candidate);
+            let candidate_error = (candidate_color - target).magnitude2();
+            if candidate_error < error {
+                (coefficients, color, jacobian, error) = (
+                    candidate,
+                    candidate_color,
+                    candidate_jacobian,
+                    candidate_error,
+                );
+                improved = true;
+                break;
+            }
+            scale *= 0.5;
+        }
+        if !improved {
+            break;
+        }
+    }
+    coefficients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::internal::Dispersion;
+    use std::f32::consts::FRAC_PI_4;
+
+    /// Dense flint glass (Schott SF11)
+    const SF11: Dispersion = Dispersion::Sellmeier {
+        b: Vector3::new(1.737_597, 0.313_747_4, 1.898_781),
+        c: Vector3::new(0.013_188_707, 0.062_306_814, 155.236_3),
+    };
+
+    /// Spectrum carrying a hero wavelength and two others
+    fn wavelengths(hero: f32) -> Spectrum {
+        Spectrum::Wavelengths {
+            lambda: Vector3::new(hero, 550., 700.),
+            pdf: Vector3::new(1., 1., 1.),
+            working_space: WorkingSpace::Rec709,
+            hero_only: false,
+        }
+    }
+
+    /// Index of refraction of a dispersive material sample seen by a spectrum, with the
+    /// throughput left to the path
+    fn ior(mut spectrum: Spectrum) -> (f32, Vector3<f32>) {
+        let mut material = MaterialSample {
+            ior: 1.5,
+            dispersion: Some(SF11),
+            ..Default::default()
+        };
+        let mut throughput = Vector3::new(1., 1., 1.);
+        spectrum.material(&mut material, &mut throughput);
+        (material.ior, throughput)
+    }
+
+    #[test]
+    fn sellmeier() {
+        assert!((SF11.ior(587.56) - 1.784_72).abs() < 1e-4);
+        assert!((SF11.ior(486.13) - 1.806_44).abs() < 1e-4);
+        assert!((SF11.ior(656.27) - 1.775_99).abs() < 1e-4);
+    }
+
+    #[test]
+    fn dispersion() {
+        // RGB paths keep the index of the material
+        assert_eq!(ior(Spectrum::Rgb), (1.5, Vector3::new(1., 1., 1.)));
+
+        let (blue, throughput) = ior(wavelengths(450.));
+        let (red, _) = ior(wavelengths(650.));
+        assert!(blue > red);
+        // Only the hero wavelength goes on, carrying the light of the others
+        assert_eq!(throughput, Vector3::new(WAVELENGTHS as f32, 0., 0.));
+
+        // Light entering the glass at 45° is spread by about 0.6° between blue and red
+        let refraction = |ior: f32| (FRAC_PI_4.sin() / ior).asin();
+        let spread = (refraction(red) - refraction(blue)).to_degrees();
+        assert!(spread > 0.5 && spread < 0.7, "{spread}°");
+    }
+}
@@ -0,0 +1,87 @@
+use super::brdf::fresnel;
+use super::sampler::Sampler;
+use super::utils::uniform_sphere;
+use super::{Hit, Intersectable, Ray};
+use crate::scene::internal::{Model, Subsurface};
+use cgmath::*;
+
+/// Maximum number of scattering events inside a medium before giving up the path
+const MAX_SCATTERING_EVENTS: usize = 256;
+
+/// Minimum distance to the boundary to avoid self-intersection
+const BOUNDARY_BIAS: f32 = 0.00001;
+
+/// Random walk of a ray that entered a subsurface medium of index of refraction `ior`.
+/// At the boundary, the walk is reflected back inside with the Fresnel reflectance.
+/// Returns the hit where the walk leaves the model and the throughput of the walk,
+/// or None if the path got absorbed or lost (non closed mesh).
+pub fn random_walk(
+    model: &Model,
+    subsurface: &Subsurface,
+    ior: f32,
+    ray: &Ray,
+    sampler: &mut dyn Sampler,
+) -> Option<(Hit, Vector3<f32>)> {
+    let sigma_t = Vector3::new(
+        1. / subsurface.radius.x.max(0.0001),
+        1. / subsurface.radius.y.max(0.0001),
+        1. / subsurface.radius.z.max(0.0001),
+    );
+    let sigma_s = sigma_t.mul_element_wise(subsurface.albedo);
+
+    let mut ray = Ray::new(ray.origin, ray.direction);
+    let mut throughput = Vector3::new(1., 1., 1.);
+
+    for _ in 0..MAX_SCATTERING_EVENTS {
+        let boundary = model
+            .intersect(&ray)
+            .into_iter()
+            .filter(|hit| hit.get_dist() > BOUNDARY_BIAS)
+            .min_by(|hit1, hit2| hit1.get_dist().partial_cmp(&hit2.get_dist()).unwrap())?;
+
+        // Sample a distance using a randomly chosen channel (pdf is averaged over channels)
+        let channel = ((sampler.next_1d() * 3.) as usize).min(2);
+        let dist = -(1. - sampler.next_1d()).ln() / sigma_t[channel];
+
+        if dist < boundary.get_dist() {
+            // Scattering event
+            let transmittance = transmittance(sigma_t, dist);
+            let pdf = sigma_t.mul_element_wise(transmittance).sum() / 3.;
+            throughput = throughput.mul_element_wise(sigma_s.mul_element_wise(transmittance)) / pdf;
+            ray = Ray::new(ray.origin + ray.direction * dist, uniform_sphere(sampler));
+        } else {
+            // Reaching the boundary
+            let transmittance = transmittance(sigma_t, boundary.get_dist());
+            let pdf = transmittance.sum() / 3.;
+            throughput = throughput.mul_element_wise(transmittance) / pdf;
+
+            let normal = boundary.get_geometric_normal();
+            let cos_i = ray.direction.dot(normal);
+            if sampler.next_1d() >= fresnel(cos_i, ior, 1.) {
+                // Leaving the medium
+                return Some((boundary, throughput));
+            }
+            // Internal reflection
+            let direction = ray.direction - 2. * cos_i * normal;
+            ray = Ray::new(boundary.get_position(), direction);
+        }
+
+        // Russian roulette on absorbing media
+        let max_throughput = throughput.x.max(throughput.y).max(throughput.z);
+        if max_throughput < 1. {
+            if sampler.next_1d() > max_throughput {
+                return None;
+            }
+            throughput /= max_throughput;
+        }
+    }
+    None
+}
+
+fn transmittance(sigma_t: Vector3<f32>, dist: f32) -> Vector3<f32> {
+    Vector3::new(
+        (-sigma_t.x * dist).exp(),
+        (-sigma_t.y * dist).exp(),
+        (-sigma_t.z * dist).exp(),
+    )
+}
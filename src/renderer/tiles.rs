@@ -0,0 +1,196 @@
+use crate::config::Resolution;
+use serde::Deserialize;
+
+/// Order the tiles are rendered in
+#[derive(Default, Copy, Debug, Clone, Deserialize)]
+pub enum TileOrder {
+    /// Row by row from the top left corner
+    #[serde(rename = "SCANLINE")]
+    Scanline,
+    /// From the center of the image outwards
+    #[serde(rename = "SPIRAL")]
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other
+    #[serde(rename = "HILBERT")]
+    Hilbert,
+}
+
+/// Rectangular bucket of pixels rendered to completion at once
+#[derive(Copy, Debug, Clone)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Position in the image of the pixel at an index of the tile (row by row)
+    pub fn pixel(&self, index: usize) -> (u32, u32) {
+        let index = index as u32;
+        (self.x + index % self.width, self.y + index / self.width)
+    }
+
+    /// Number of pixels in the tile
+    pub fn len(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    /// Index in the tile (row by row) of a pixel of the image inside it
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (x - self.x + (y - self.y) * self.width) as usize
+    }
+
+    /// Tile grown by a margin on each side, cropped to the image
+    pub fn expand(&self, margin: u32, resolution: Resolution) -> Tile {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(resolution.width) - x,
+            height: (self.y + self.height + margin).min(resolution.height) - y,
+        }
+    }
+}
+
+/// Split the image in square tiles (cropped at the borders) sorted in the given order
+pub fn tiles(resolution: Resolution, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = resolution.width.div_ceil(size);
+    let rows = resolution.height.div_ceil(size);
+
+    let positions = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            let mut positions: Vec<_> = (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect();
+            positions.sort_by_key(|&(column, row)| hilbert_index(n, column, row));
+            positions
+        }
+    };
+
+    positions
+        .into_iter()
+        .map(|(column, row)| {
+            let x = column * size;
+            let y = row * size;
+            Tile {
+                x,
+                y,
+                width: size.min(resolution.width - x),
+                height: size.min(resolution.height - y),
+            }
+        })
+        .collect()
+}
+
+/// Walk a square spiral from the center of the grid, keeping the cells inside it
+fn spiral(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let count = (columns * rows) as usize;
+    let mut positions = Vec::with_capacity(count);
+    if count == 0 {
+        return positions;
+    }
+    let (mut x, mut y) = (((columns - 1) / 2) as i64, ((rows - 1) / 2) as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 1;
+    let mut direction = 0;
+    while positions.len() < count {
+        // Each step length is walked twice before growing
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction % 4];
+            for _ in 0..step {
+                if (0..columns as i64).contains(&x) && (0..rows as i64).contains(&y) {
+                    positions.push((x as u32, y as u32));
+                }
+                x += dx;
+                y += dy;
+            }
+            direction += 1;
+        }
+        step += 1;
+    }
+    positions
+}
+
+/// Distance along the Hilbert curve filling a `n`×`n` grid (`n` being a power of two)
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        index += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_cells(resolution: Resolution, size: u32, order: TileOrder) -> Vec<(u32, u32)> {
+        tiles(resolution, size, order)
+            .iter()
+            .map(|tile| (tile.x / size, tile.y / size))
+            .collect()
+    }
+
+    #[test]
+    fn every_cell_once() {
+        let orders = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+        let resolutions = [(64, 64), (100, 30), (30, 100), (17, 1), (1, 1)];
+        for order in orders {
+            for (width, height) in resolutions {
+                let resolution = Resolution { width, height };
+                let mut cells = grid_cells(resolution, 8, order);
+                cells.sort_unstable();
+                let expected: Vec<_> = (0..width.div_ceil(8))
+                    .flat_map(|column| (0..height.div_ceil(8)).map(move |row| (column, row)))
+                    .collect();
+                assert_eq!(cells, expected, "{order:?} {width}x{height}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_resolution() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let resolution = Resolution {
+                width: 0,
+                height: 10,
+            };
+            assert!(tiles(resolution, 8, order).is_empty());
+        }
+    }
+
+    #[test]
+    fn hilbert_adjacency() {
+        // On a square grid of a power of two, each tile is next to the previous one
+        let resolution = Resolution {
+            width: 128,
+            height: 128,
+        };
+        let cells = grid_cells(resolution, 8, TileOrder::Hilbert);
+        for pair in cells.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            assert_eq!(x0.abs_diff(x1) + y0.abs_diff(y1), 1, "{pair:?}");
+        }
+    }
+}
@@ -1,6 +1,11 @@
+use crate::config::{Hable, Profile};
 use cgmath::*;
 use serde::Deserialize;
 
+/// Darkest and brightest exposures of the AgX log encoding, in stops around middle grey
+const AGX_MIN_EV: f32 = -12.473_93;
+const AGX_MAX_EV: f32 = 4.026_069;
+
 #[derive(Default, Copy, Debug, Clone, Deserialize)]
 pub enum TonemapType {
     #[serde(rename = "REINHARD")]
@@ -10,13 +15,20 @@ pub enum TonemapType {
     Filmic,
     #[serde(rename = "ACES")]
     Aces,
+    #[serde(rename = "AGX")]
+    Agx,
+    /// John Hable's filmic curve, with the parameters of the profile
+    #[serde(rename = "HABLE", alias = "UNCHARTED2")]
+    Hable,
 }
 
-pub fn tonemap(tonemap_type: TonemapType, color: Vector3<f32>) -> Vector3<f32> {
-    match tonemap_type {
+pub fn tonemap(profile: &Profile, color: Vector3<f32>) -> Vector3<f32> {
+    match profile.tonemap {
         TonemapType::Reinhard => reinhard(color),
         TonemapType::Filmic => filmic(color),
         TonemapType::Aces => aces(color),
+        TonemapType::Agx => agx(color),
+        TonemapType::Hable => hable(&profile.hable, color),
     }
 }
 
@@ -52,3 +64,61 @@ fn aces(color: Vector3<f32>) -> Vector3<f32> {
         res.z.clamp(0., 1.),
     )
 }
+
+/// AgX (Troy Sobotka), as fitted by Benjamin Wrensch: the colors are desaturated towards the
+/// achromatic axis, so bright saturated lights go to white instead of clipping per channel, then
+/// log encoded and passed through a sigmoid
+fn agx(color: Vector3<f32>) -> Vector3<f32> {
+    let inset = Matrix3::new(
+        0.842_479_06,
+        0.042_328_24,
+        0.042_375_654,
+        0.078_433_6,
+        0.878_468_6,
+        0.078_433_6,
+        0.079_223_745,
+        0.079_166_13,
+        0.879_143,
+    );
+    let outset = Matrix3::new(
+        1.196_879,
+        -0.052_896_85,
+        -0.052_971_635,
+        -0.098_020_88,
+        1.151_903_1,
+        -0.098_043_45,
+        -0.099_029_74,
+        -0.098_961_18,
+        1.151_073_7,
+    );
+    let encode = |x: f32| {
+        let x = (x.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV)
+            / (AGX_MAX_EV - AGX_MIN_EV);
+        // Polynomial fit of the default contrast sigmoid
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    let color = (inset * color).map(encode);
+    // The sigmoid outputs display values, decoded back to linear
+    (outset * color).map(|x| x.max(0.).powf(2.2))
+}
+
+/// Filmic curve of Uncharted 2 (John Hable), with white mapped to 1
+fn hable(settings: &Hable, color: Vector3<f32>) -> Vector3<f32> {
+    let curve = |x: f32| {
+        let (a, b, c) = (
+            settings.shoulder_strength,
+            settings.linear_strength,
+            settings.linear_angle,
+        );
+        let (d, e, f) = (
+            settings.toe_strength,
+            settings.toe_numerator,
+            settings.toe_denominator,
+        );
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    };
+    let white_scale = 1. / curve(settings.white);
+    color.map(|x| (curve(x.max(0.) * settings.exposure_bias) * white_scale).max(0.))
+}
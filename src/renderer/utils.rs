@@ -1,11 +1,13 @@
+use super::brdf::transform_to_world;
+use super::color::WorkingSpace;
+use super::sampler::Sampler;
 use super::Hit;
 use super::Intersectable;
 use super::Ray;
 use crate::scene::internal::Model;
 use crate::Scene;
 use cgmath::*;
-use rand::rngs::StdRng;
-use rand::Rng;
+use kdtree_ray::AABB;
 
 /// Return all the hits of a ray in a scene sorted by distance
 pub fn ray_cast<'a>(scene: &'a Scene, ray: &Ray) -> Vec<(Hit, &'a Model)> {
@@ -20,17 +22,75 @@ pub fn ray_cast<'a>(scene: &'a Scene, ray: &Ray) -> Vec<(Hit, &'a Model)> {
     res
 }
 
-pub fn russian_roulette(throughput: &mut Vector3<f32>, rand_gen: &mut StdRng) -> bool {
+/// Range of distances along a ray inside a bounding box, limited to `max_dist`
+pub fn clip_to_bounds(bounds: &AABB, ray: &Ray, max_dist: f32) -> Option<(f32, f32)> {
+    let mut t_min = 0f32;
+    let mut t_max = max_dist;
+    for axis in 0..3 {
+        let inv_direction = 1. / ray.direction[axis];
+        let t0 = (bounds.min[axis] - ray.origin[axis]) * inv_direction;
+        let t1 = (bounds.max[axis] - ray.origin[axis]) * inv_direction;
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+        if t_min > t_max {
+            return None;
+        }
+    }
+    Some((t_min, t_max))
+}
+
+pub fn russian_roulette(throughput: &mut Vector3<f32>, sampler: &mut dyn Sampler) -> bool {
     // Randomly terminate a path with a probability inversely equal to the throughput
     let rr_proba = throughput.x.max(throughput.y).max(throughput.z);
 
     // Add the energy we 'lose' by randomly terminating paths
     *throughput *= 1. / rr_proba;
 
-    rand_gen.gen::<f32>() > rr_proba
+    sampler.next_1d() > rr_proba
 }
 
 /// Compute reflection vector given incident and normal vectors
 pub fn reflection(i: &Vector3<f32>, n: &Vector3<f32>) -> Vector3<f32> {
     2. * i.dot(*n).max(0.) * n - i
 }
+
+/// Relative luminance of a linear RGB color of the working space
+pub fn luminance(color: &Vector3<f32>, working_space: WorkingSpace) -> f32 {
+    working_space.luminance_weights().dot(*color)
+}
+
+/// Multiple importance sampling weight using the power heuristic (beta = 2)
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    if pdf.is_infinite() {
+        return 1.;
+    }
+    let pdf2 = pdf * pdf;
+    let other_pdf2 = other_pdf * other_pdf;
+    if pdf2 + other_pdf2 == 0. {
+        0.
+    } else {
+        pdf2 / (pdf2 + other_pdf2)
+    }
+}
+
+/// Uniformly sample a direction on the unit sphere
+pub fn uniform_sphere(sampler: &mut dyn Sampler) -> Vector3<f32> {
+    let (r1, r2) = sampler.next_2d();
+    let z = 1. - 2. * r1;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * std::f32::consts::PI * r2;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Sample a direction on the hemisphere around `normal` with a density proportional to the cosine
+pub fn cosine_hemisphere(normal: Vector3<f32>, sampler: &mut dyn Sampler) -> Vector3<f32> {
+    let (r1, r2) = sampler.next_2d();
+    let sin_theta = r1.sqrt();
+    let phi = 2. * std::f32::consts::PI * r2;
+    let direction = Vector3::new(
+        sin_theta * phi.cos(),
+        (1. - r1).max(0.).sqrt(),
+        sin_theta * phi.sin(),
+    );
+    transform_to_world(direction, normal).normalize()
+}
@@ -8,7 +8,7 @@ use std::{
 use image::{DynamicImage, GrayImage, Luma, RgbImage, RgbaImage};
 use serde_json::to_writer;
 
-use crate::scene::isf::Roughness;
+use crate::scene::isf::{Roughness, Transmission};
 
 use super::isf::{
     Albedo, Camera, Emissive, Light, Material, Metalness, Model, Opacity, Scene, Triangle, Vertex,
@@ -78,9 +78,11 @@ impl ReverseTextureBank {
 }
 fn convert_material(
     material: Arc<easy_gltf::Material>,
+    id: usize,
     reverse_texture: &mut ReverseTextureBank,
 ) -> Material {
     Material {
+        id: Some(id),
         albedo: Albedo {
             factor: material.pbr.base_color_factor.truncate().into(),
             texture: material
@@ -121,7 +123,11 @@ fn convert_material(
                 .clone()
                 .map(|tex| reverse_texture.get_gray_path(tex)),
         },
+        transmission: Transmission::default(),
+        subsurface: None,
+        medium: None,
         ior: 1.0,
+        dispersion: None,
         normal_texture: material
             .normal
             .clone()
@@ -129,14 +135,27 @@ fn convert_material(
     }
 }
 
-fn convert_model(model: easy_gltf::Model, reverse_texture: &mut ReverseTextureBank) -> Model {
+fn convert_model(
+    model: easy_gltf::Model,
+    materials: &mut Vec<Arc<easy_gltf::Material>>,
+    reverse_texture: &mut ReverseTextureBank,
+) -> Model {
     let triangles = model
         .triangles()
         .unwrap()
         .into_iter()
         .map(|t| t.into())
         .collect();
-    let material = convert_material(model.material(), reverse_texture);
+    // Models using the same glTF material share it
+    let material = model.material();
+    let id = materials
+        .iter()
+        .position(|other| Arc::ptr_eq(other, &material))
+        .unwrap_or_else(|| {
+            materials.push(material.clone());
+            materials.len() - 1
+        });
+    let material = convert_material(material, id, reverse_texture);
     Model::Mesh {
         triangles,
         material,
@@ -174,11 +193,12 @@ pub fn convert_gltf_to_isf<P: AsRef<Path>>(
         .collect();
 
     let mut reverse_texture = Default::default();
+    let mut materials = vec![];
     let models = scenes[0]
         .models
         .clone()
         .into_iter()
-        .map(|m| convert_model(m, &mut reverse_texture))
+        .map(|m| convert_model(m, &mut materials, &mut reverse_texture))
         .collect();
 
     let scene = Scene {
@@ -252,13 +272,18 @@ impl From<easy_gltf::Light> for Light {
             },
             easy_gltf::Light::Spot {
                 position,
+                direction,
                 color,
                 intensity,
+                inner_cone_angle,
+                outer_cone_angle,
                 ..
-            } => Self::Point {
+            } => Self::Spot {
                 position: position.into(),
+                direction: direction.into(),
                 color: (color * intensity).into(),
-                size: 0.1,
+                inner_cone_angle,
+                outer_cone_angle,
             },
         }
     }
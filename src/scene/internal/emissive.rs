@@ -0,0 +1,146 @@
+use super::Model;
+use crate::renderer::distribution::Distribution1D;
+use crate::renderer::sampler::Sampler;
+use crate::renderer::utils::luminance;
+use crate::renderer::Hit;
+use cgmath::*;
+use std::f32::consts::PI;
+
+/// Primitive of an emissive model that can be sampled for direct lighting
+#[derive(Debug, Clone)]
+enum EmissivePrimitive {
+    Triangle { model: usize, triangle: usize },
+    Sphere { model: usize },
+}
+
+/// Light list gathering every emissive primitive of the scene
+#[derive(Debug, Clone, Default)]
+pub struct EmissiveLights {
+    primitives: Vec<EmissivePrimitive>,
+    /// Primitives are selected proportionally to their power
+    distribution: Distribution1D,
+    /// Sum of the power of all the primitives
+    total_power: f32,
+}
+
+/// Point sampled on an emissive primitive
+pub struct EmissiveSample {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub radiance: Vector3<f32>,
+    /// Probability density with respect to surface area
+    pub pdf: f32,
+}
+
+impl EmissiveLights {
+    pub fn build(models: &[Model]) -> Self {
+        let mut primitives = vec![];
+        let mut powers = vec![];
+
+        for (model_index, model) in models.iter().enumerate() {
+            let radiance = emitted_luminance(model);
+            if radiance <= 0. {
+                continue;
+            }
+            match model {
+                Model::Mesh { triangles, .. } => {
+                    for (triangle_index, triangle) in triangles.iter().enumerate() {
+                        primitives.push(EmissivePrimitive::Triangle {
+                            model: model_index,
+                            triangle: triangle_index,
+                        });
+                        powers.push(radiance * triangle.area());
+                    }
+                }
+                Model::Sphere { radius, .. } => {
+                    primitives.push(EmissivePrimitive::Sphere { model: model_index });
+                    powers.push(radiance * 4. * PI * radius * radius);
+                }
+            }
+        }
+
+        Self {
+            primitives,
+            total_power: powers.iter().sum(),
+            distribution: Distribution1D::new(&powers),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    /// Area density of sampling any point of an emissive model.
+    /// Since primitives are selected according to their power it only depends on the model.
+    pub fn pdf(&self, model: &Model) -> f32 {
+        if self.total_power <= 0. {
+            return 0.;
+        }
+        emitted_luminance(model).max(0.) / self.total_power
+    }
+
+    /// Sample a point on an emissive primitive
+    pub fn sample(&self, models: &[Model], sampler: &mut dyn Sampler) -> Option<EmissiveSample> {
+        if self.is_empty() || self.total_power <= 0. {
+            return None;
+        }
+
+        let (index, _) = self.distribution.sample_discrete(sampler.next_1d());
+        let (r1, r2) = sampler.next_2d();
+
+        let (model, position, normal, radiance) = match self.primitives[index] {
+            EmissivePrimitive::Triangle { model, triangle } => {
+                let model = &models[model];
+                let triangle = match model {
+                    Model::Mesh { triangles, .. } => &triangles[triangle],
+                    _ => unreachable!("Emissive triangle must belong to a mesh"),
+                };
+
+                // Uniform sampling of barycentric coordinates
+                let sqrt_r1 = r1.sqrt();
+                let uv = Vector2::new(r2 * sqrt_r1, 1. - sqrt_r1);
+                let position = (1. - uv.x - uv.y) * triangle[0].position
+                    + uv.x * triangle[1].position
+                    + uv.y * triangle[2].position;
+                let hit = Hit::new_triangle(triangle.clone(), 0., position, &uv, false);
+                let radiance = hit.get_material_sample(model).emissive;
+                (model, position, triangle.face_normal(), radiance)
+            }
+            EmissivePrimitive::Sphere { model } => {
+                let model = &models[model];
+                let (radius, center) = match model {
+                    Model::Sphere { radius, center, .. } => (*radius, *center),
+                    _ => unreachable!("Emissive sphere must be a sphere model"),
+                };
+
+                // Uniform sampling of the sphere surface
+                let z = 1. - 2. * r1;
+                let r = (1. - z * z).max(0.).sqrt();
+                let phi = 2. * PI * r2;
+                let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+                let position = center + radius * normal;
+                let hit = Hit::Sphere {
+                    dist: 0.,
+                    position,
+                    normal,
+                    is_backface: false,
+                };
+                let radiance = hit.get_material_sample(model).emissive;
+                (model, position, normal, radiance)
+            }
+        };
+
+        Some(EmissiveSample {
+            position,
+            normal,
+            radiance,
+            pdf: self.pdf(model),
+        })
+    }
+}
+
+/// Luminance emitted by the material of a model, ignoring its texture
+fn emitted_luminance(model: &Model) -> f32 {
+    let material = model.get_material();
+    luminance(&material.get_simple_emissive(), material.working_space())
+}
@@ -0,0 +1,110 @@
+use super::texture_bank::TextureBank;
+use crate::renderer::color::WorkingSpace;
+use crate::renderer::distribution::Distribution2D;
+use crate::renderer::sampler::Sampler;
+use crate::renderer::utils::luminance;
+use crate::scene::isf;
+use cgmath::*;
+use image::Rgb32FImage;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Equirectangular HDR environment lighting the scene
+#[derive(Clone, Debug)]
+pub struct Environment {
+    texture: Arc<Rgb32FImage>,
+    /// Rotation around the up axis (radians)
+    rotation: f32,
+    intensity: f32,
+    /// Luminance based distribution over the texture used for importance sampling
+    distribution: Distribution2D,
+}
+
+impl Environment {
+    pub fn load(
+        environment: isf::Environment,
+        texture_bank: &mut TextureBank,
+        working_space: WorkingSpace,
+    ) -> Self {
+        let mut texture = texture_bank.get_hdr(environment.texture);
+        if working_space != WorkingSpace::Rec709 {
+            let mut converted = (*texture).clone();
+            for pixel in converted.pixels_mut() {
+                pixel.0 = working_space.convert_rec709(pixel.0.into()).into();
+            }
+            texture = Arc::new(converted);
+        }
+        let (width, height) = texture.dimensions();
+
+        // Weight texels by sin(theta) to account for the distortion of the poles
+        let func: Vec<_> = texture
+            .enumerate_pixels()
+            .map(|(_, y, pixel)| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                luminance(&Vector3::from(pixel.0), working_space).max(0.) * sin_theta
+            })
+            .collect();
+        let distribution = Distribution2D::new(&func, width as usize, height as usize);
+
+        Self {
+            texture,
+            rotation: environment.rotation,
+            intensity: environment.intensity,
+            distribution,
+        }
+    }
+
+    /// Convert a world direction into texture coordinates
+    fn direction_to_uv(&self, direction: Vector3<f32>) -> Vector2<f32> {
+        let direction = Matrix3::from_angle_y(Rad(-self.rotation)) * direction.normalize();
+        let u = 0.5 + direction.x.atan2(-direction.z) / (2. * PI);
+        let v = direction.y.clamp(-1., 1.).acos() / PI;
+        Vector2::new(u.rem_euclid(1.), v)
+    }
+
+    /// Convert texture coordinates into a world direction
+    fn uv_to_direction(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let phi = (uv.x - 0.5) * 2. * PI;
+        let theta = uv.y * PI;
+        let direction = Vector3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+        Matrix3::from_angle_y(Rad(self.rotation)) * direction
+    }
+
+    /// Radiance coming from a direction
+    pub fn radiance(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let uv = self.direction_to_uv(direction);
+        let (width, height) = self.texture.dimensions();
+        let x = ((uv.x * width as f32) as u32).min(width - 1);
+        let y = ((uv.y * height as f32) as u32).min(height - 1);
+        Vector3::from(self.texture[(x, y)].0) * self.intensity
+    }
+
+    /// Solid angle density of sampling a direction
+    pub fn pdf(&self, direction: Vector3<f32>) -> f32 {
+        let uv = self.direction_to_uv(direction);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        self.distribution.pdf(uv.x, uv.y) / (2. * PI * PI * sin_theta)
+    }
+
+    /// Importance sample a direction. Returns the direction, its radiance and solid angle pdf.
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> (Vector3<f32>, Vector3<f32>, f32) {
+        let (u, v) = sampler.next_2d();
+        let ((u, v), uv_pdf) = self.distribution.sample_continuous(u, v);
+        let uv = Vector2::new(u, v);
+        let direction = self.uv_to_direction(uv);
+        let sin_theta = (v * PI).sin();
+        let pdf = if sin_theta <= 0. {
+            0.
+        } else {
+            uv_pdf / (2. * PI * PI * sin_theta)
+        };
+        (direction, self.radiance(direction), pdf)
+    }
+}
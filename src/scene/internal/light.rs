@@ -1,5 +1,8 @@
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3};
+use std::f32::consts::PI;
 
+use crate::renderer::color::WorkingSpace;
+use crate::renderer::Ray;
 use crate::scene::isf;
 
 #[derive(Debug, Clone)]
@@ -7,17 +10,26 @@ pub enum Light {
     Point {
         position: Vector3<f32>,
         color: Vector3<f32>,
-        #[allow(dead_code)]
+        /// Radius of the light sphere (0 for a perfect point light)
         size: f32,
     },
+    Spot {
+        position: Vector3<f32>,
+        direction: Vector3<f32>,
+        color: Vector3<f32>,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
     Directional {
         direction: Vector3<f32>,
         color: Vector3<f32>,
     },
 }
 
-impl From<isf::Light> for Light {
-    fn from(l: isf::Light) -> Self {
+impl Light {
+    /// Light of the scene, its color converted to the working space
+    pub fn load(l: isf::Light, working_space: WorkingSpace) -> Self {
+        let convert = |color: [f32; 3]| working_space.convert_rec709(color.into());
         match l {
             isf::Light::Point {
                 position,
@@ -25,13 +37,105 @@ impl From<isf::Light> for Light {
                 size,
             } => Light::Point {
                 position: position.into(),
-                color: color.into(),
+                color: convert(color),
                 size,
             },
+            isf::Light::Spot {
+                position,
+                direction,
+                color,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Light::Spot {
+                position: position.into(),
+                direction: Vector3::from(direction).normalize(),
+                color: convert(color),
+                inner_cone_angle,
+                outer_cone_angle,
+            },
             isf::Light::Directional { direction, color } => Light::Directional {
                 direction: direction.into(),
-                color: color.into(),
+                color: convert(color),
             },
         }
     }
+
+    /// Position of the light (None for directional lights)
+    pub fn position(&self) -> Option<Vector3<f32>> {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => Some(*position),
+            Light::Directional { .. } => None,
+        }
+    }
+
+    /// Radiance emitted by the surface of a spherical light of a given power
+    pub fn sphere_radiance(color: Vector3<f32>, radius: f32) -> Vector3<f32> {
+        color / (4. * PI * PI * radius * radius)
+    }
+
+    /// Smooth attenuation of a spot light given the direction from the light to the lit point
+    pub fn spot_falloff(
+        direction: Vector3<f32>,
+        light_to_point: Vector3<f32>,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> f32 {
+        let cos_angle = direction.dot(light_to_point);
+        let cos_inner = inner_cone_angle.cos();
+        let cos_outer = outer_cone_angle.cos();
+        if cos_inner - cos_outer <= 0. {
+            return if cos_angle >= cos_outer { 1. } else { 0. };
+        }
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    }
+
+    /// Radiance seen when looking at the light surface
+    pub fn surface_radiance(&self) -> Vector3<f32> {
+        match self {
+            Light::Point { color, size, .. } if *size > 0. => Self::sphere_radiance(*color, *size),
+            _ => Vector3::new(0., 0., 0.),
+        }
+    }
+
+    /// Solid angle pdf of uniformly sampling a cone given `1 - cos(theta_max)`
+    pub fn cone_pdf(one_minus_cos_max: f32) -> f32 {
+        1. / (2. * PI * one_minus_cos_max)
+    }
+
+    /// Distance along the ray to the surface of a spherical light, if hit
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let (position, size) = match self {
+            Light::Point { position, size, .. } if *size > 0. => (position, *size),
+            _ => return None,
+        };
+
+        let ray_to_center = ray.origin - position;
+        let b = ray_to_center.dot(ray.direction);
+        let c = ray_to_center.magnitude2() - size * size;
+        let discriminant = b * b - c;
+        if discriminant < 0. {
+            return None;
+        }
+        let sqrt_discriminant = discriminant.sqrt();
+        [-b - sqrt_discriminant, -b + sqrt_discriminant]
+            .into_iter()
+            .find(|&dist| dist > 0.)
+    }
+
+    /// Solid angle pdf of sampling the light sphere from a given point
+    pub fn pdf_from(&self, origin: Vector3<f32>) -> f32 {
+        match self {
+            Light::Point { position, size, .. } if *size > 0. => {
+                let dist2 = (position - origin).magnitude2();
+                if dist2 <= size * size {
+                    return 0.;
+                }
+                let sin2_theta_max = size * size / dist2;
+                let cos_theta_max = (1. - sin2_theta_max).max(0.).sqrt();
+                Self::cone_pdf(sin2_theta_max / (1. + cos_theta_max))
+            }
+            _ => 0.,
+        }
+    }
 }
@@ -1,11 +1,13 @@
 use std::{ops::Deref, sync::Arc};
 
-use cgmath::{ElementWise, Vector2, Vector3};
-use image::{GrayImage, ImageBuffer, Pixel, RgbImage};
+use cgmath::{Array, ElementWise, Vector2, Vector3};
+use image::{GrayImage, ImageBuffer, Pixel, Rgb, RgbImage};
 
+use crate::renderer::color::{srgb_to_linear, WorkingSpace};
 use crate::scene::isf;
 
 use super::texture_bank::TextureBank;
+use super::Medium;
 
 #[derive(Clone, Debug)]
 pub struct Material {
@@ -19,10 +21,20 @@ pub struct Material {
     pub metalness: Metalness,
     /// Roughness
     pub roughness: Roughness,
+    /// Transmission
+    pub transmission: Transmission,
+    /// Subsurface scattering
+    pub subsurface: Option<Subsurface>,
+    /// Medium inside the model
+    pub medium: Option<Medium>,
     /// Index of refraction
     pub ior: f32,
+    /// Index of refraction varying with the wavelength
+    pub dispersion: Option<Dispersion>,
     /// Normal texture
     pub normal_texture: Option<Arc<RgbImage>>,
+    /// Space the colors are converted to
+    working_space: WorkingSpace,
 }
 
 #[derive(Clone, Debug)]
@@ -99,19 +111,114 @@ impl Roughness {
         }
     }
 }
+#[derive(Clone, Debug)]
+pub struct Transmission {
+    factor: f32,
+    texture: Option<Arc<GrayImage>>,
+}
+
+impl Transmission {
+    fn load(transmission: isf::Transmission, texture_bank: &mut TextureBank) -> Self {
+        Self {
+            factor: transmission.factor,
+            texture: transmission.texture.map(|path| texture_bank.get_gray(path)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Subsurface {
+    /// Single scattering albedo of the medium
+    pub albedo: Vector3<f32>,
+    /// Mean free path for each channel
+    pub radius: Vector3<f32>,
+}
+
+impl Subsurface {
+    fn load(subsurface: isf::Subsurface, working_space: WorkingSpace) -> Self {
+        Self {
+            albedo: working_space.convert_rec709(subsurface.albedo.into()),
+            radius: working_space.convert_rec709_inverse(subsurface.radius.into()),
+        }
+    }
+}
+
+/// Index of refraction as a function of the wavelength
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    Cauchy { a: f32, b: f32 },
+    Sellmeier { b: Vector3<f32>, c: Vector3<f32> },
+}
+
+impl From<isf::Dispersion> for Dispersion {
+    fn from(dispersion: isf::Dispersion) -> Self {
+        match dispersion {
+            isf::Dispersion::Cauchy { a, b } => Dispersion::Cauchy { a, b },
+            isf::Dispersion::Sellmeier { b, c } => Dispersion::Sellmeier {
+                b: b.into(),
+                c: c.into(),
+            },
+        }
+    }
+}
+
+impl Dispersion {
+    /// Wavelength (in nanometers) of the Fraunhofer d line, where glasses give their index
+    const D_LINE: f32 = 587.56;
+
+    /// Index of refraction at a wavelength in nanometers
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let lambda = wavelength / 1000.;
+        let lambda2 = lambda * lambda;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / lambda2,
+            Dispersion::Sellmeier { b, c } => {
+                let terms = (b * lambda2).div_element_wise(c.map(|c| lambda2 - c));
+                (1. + terms.sum()).max(1.).sqrt()
+            }
+        }
+    }
+}
+
 impl Material {
-    pub fn load(material: isf::Material, texture_bank: &mut TextureBank) -> Material {
+    /// Material of the scene, its colors and the coefficients of its media converted to the
+    /// working space
+    pub fn load(
+        material: isf::Material,
+        texture_bank: &mut TextureBank,
+        working_space: WorkingSpace,
+    ) -> Material {
         Self {
             albedo: Albedo::load(material.albedo, texture_bank),
             emissive: Emissive::load(material.emissive, texture_bank),
             opacity: Opacity::load(material.opacity, texture_bank),
             metalness: Metalness::load(material.metalness, texture_bank),
             roughness: Roughness::load(material.roughness, texture_bank),
-            ior: material.ior,
+            transmission: Transmission::load(material.transmission, texture_bank),
+            subsurface: material
+                .subsurface
+                .map(|s| Subsurface::load(s, working_space)),
+            medium: material.medium.map(|m| Medium::load(m, working_space)),
+            // Without dispersion, the light has the color of the d line
+            ior: material.dispersion.map_or(material.ior, |d| {
+                Dispersion::from(d).ior(Dispersion::D_LINE)
+            }),
+            dispersion: material.dispersion.map(Dispersion::from),
             normal_texture: material.normal_texture.map(|t| texture_bank.get_rgb(t)),
+            working_space,
         }
     }
 
+    /// Linear Rec.709 color of a pixel of an sRGB texture
+    fn decode_color(pixel: Rgb<u8>) -> Vector3<f32> {
+        Vector3::from(pixel.0).map(|value| srgb_to_linear(value as f32 / 255.))
+    }
+
+    /// Space the colors of the material are given in
+    pub fn working_space(&self) -> WorkingSpace {
+        self.working_space
+    }
+
     fn get_pixel<P, Container>(tex_coords: &Vector2<f32>, texture: &ImageBuffer<P, Container>) -> P
     where
         P: Pixel + 'static,
@@ -130,23 +237,17 @@ impl Material {
     }
 
     pub fn get_albedo(&self, uv: &Vector2<f32>) -> Vector3<f32> {
-        if let Some(texture) = &self.albedo.texture {
+        let albedo = if let Some(texture) = &self.albedo.texture {
             let pixel = Self::get_pixel(uv, texture);
-            // Convert sRGB to linear
-
-            Vector3::new(
-                (pixel[0] as f32 / 255.0).powf(2.2),
-                (pixel[1] as f32 / 255.0).powf(2.2),
-                (pixel[2] as f32 / 255.0).powf(2.2),
-            )
-            .mul_element_wise(self.albedo.factor)
+            Self::decode_color(pixel).mul_element_wise(self.albedo.factor)
         } else {
             self.albedo.factor
-        }
+        };
+        self.working_space.convert_rec709(albedo)
     }
 
     pub fn get_simple_albedo(&self) -> Vector3<f32> {
-        self.albedo.factor
+        self.working_space.convert_rec709(self.albedo.factor)
     }
 
     pub fn get_metalness(&self, uv: &Vector2<f32>) -> f32 {
@@ -187,21 +288,17 @@ impl Material {
     }
 
     pub fn get_emissive(&self, uv: &Vector2<f32>) -> Vector3<f32> {
-        if let Some(texture) = &self.emissive.texture {
+        let emissive = if let Some(texture) = &self.emissive.texture {
             let pixel = Self::get_pixel(uv, texture);
-            Vector3::new(
-                pixel[0] as f32 / 255.0,
-                pixel[1] as f32 / 255.0,
-                pixel[2] as f32 / 255.0,
-            )
-            .mul_element_wise(self.emissive.factor)
+            Self::decode_color(pixel).mul_element_wise(self.emissive.factor)
         } else {
             self.emissive.factor
-        }
+        };
+        self.working_space.convert_rec709(emissive)
     }
 
     pub fn get_simple_emissive(&self) -> Vector3<f32> {
-        self.emissive.factor
+        self.working_space.convert_rec709(self.emissive.factor)
     }
 
     pub fn get_opacity(&self, uv: &Vector2<f32>) -> f32 {
@@ -216,4 +313,17 @@ impl Material {
     pub fn get_simple_opacity(&self) -> f32 {
         self.opacity.factor
     }
+
+    pub fn get_transmission(&self, uv: &Vector2<f32>) -> f32 {
+        if let Some(texture) = &self.transmission.texture {
+            let pixel = Self::get_pixel(uv, texture);
+            pixel[0] as f32 / 255. * self.transmission.factor
+        } else {
+            self.transmission.factor
+        }
+    }
+
+    pub fn get_simple_transmission(&self) -> f32 {
+        self.transmission.factor
+    }
 }
@@ -0,0 +1,69 @@
+use crate::renderer::color::WorkingSpace;
+use crate::renderer::sampler::Sampler;
+use crate::scene::isf;
+use cgmath::*;
+
+/// Homogeneous participating medium
+#[derive(Clone, Copy, Debug)]
+pub struct Medium {
+    /// Absorption coefficient (per meter)
+    pub absorption: Vector3<f32>,
+    /// Scattering coefficient (per meter)
+    pub scattering: Vector3<f32>,
+    /// Henyey-Greenstein asymmetry parameter
+    pub anisotropy: f32,
+}
+
+impl Medium {
+    /// Medium of the scene, its coefficients converted to the working space like the colors
+    pub fn load(medium: isf::Medium, working_space: WorkingSpace) -> Self {
+        Self {
+            absorption: working_space.convert_rec709(medium.absorption.into()),
+            scattering: working_space.convert_rec709(medium.scattering.into()),
+            anisotropy: medium.anisotropy.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Extinction coefficient (absorption + scattering)
+    pub fn extinction(&self) -> Vector3<f32> {
+        self.absorption + self.scattering
+    }
+
+    /// Fraction of the light going through a given distance in the medium
+    pub fn transmittance(&self, dist: f32) -> Vector3<f32> {
+        let extinction = self.extinction();
+        Vector3::new(
+            (-extinction.x * dist).exp(),
+            (-extinction.y * dist).exp(),
+            (-extinction.z * dist).exp(),
+        )
+    }
+
+    /// Sample the distance of a scattering event along a segment of the given length.
+    /// Returns the distance (None if the segment was crossed) and the weight of the path.
+    pub fn sample_distance(
+        &self,
+        max_dist: f32,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<f32>, Vector3<f32>) {
+        let extinction = self.extinction();
+        if extinction == Zero::zero() {
+            return (None, Vector3::new(1., 1., 1.));
+        }
+
+        // The distance is sampled using a random channel, the pdf is averaged over channels
+        let channel = ((sampler.next_1d() * 3.) as usize).min(2);
+        let dist = -(1. - sampler.next_1d()).ln() / extinction[channel];
+
+        if dist < max_dist {
+            let transmittance = self.transmittance(dist);
+            let pdf = extinction.mul_element_wise(transmittance).sum() / 3.;
+            let weight = self.scattering.mul_element_wise(transmittance) / pdf;
+            (Some(dist), weight)
+        } else {
+            let transmittance = self.transmittance(max_dist);
+            let pdf = transmittance.sum() / 3.;
+            (None, transmittance / pdf)
+        }
+    }
+}
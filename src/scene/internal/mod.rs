@@ -1,6 +1,9 @@
 mod camera;
+mod emissive;
+mod environment;
 mod light;
 mod material;
+mod medium;
 mod model;
 mod texture_bank;
 mod triangle;
@@ -9,16 +12,20 @@ mod vertex;
 use std::path::PathBuf;
 
 use cgmath::Vector3;
-use kdtree_ray::KDTree;
+use kdtree_ray::{Bounded, KDTree, AABB};
 
 pub use camera::Camera;
+pub use emissive::{EmissiveLights, EmissiveSample};
+pub use environment::Environment;
 pub use light::Light;
-pub use material::Material;
+pub use material::{Dispersion, Material, Subsurface};
+pub use medium::Medium;
 pub use model::Model;
 pub use triangle::Triangle;
 pub use vertex::Vertex;
 
 use self::texture_bank::TextureBank;
+use crate::renderer::color::WorkingSpace;
 
 use super::isf;
 
@@ -28,25 +35,87 @@ pub struct Scene {
     pub kdtree: KDTree,
     pub camera: Camera,
     pub lights: Vec<Light>,
+    pub emissive_lights: EmissiveLights,
     pub background: Vector3<f32>,
+    pub environment: Option<Environment>,
+    /// Global medium filling the scene bounds
+    pub medium: Option<Medium>,
+    /// Whether any medium must be tracked along the rays
+    pub has_media: bool,
+    /// Bounding box of the models, lights and camera
+    pub bounds: AABB,
+    /// Index of the material of each model, shared by the models using the same material
+    pub material_ids: Vec<usize>,
 }
 
 impl Scene {
-    pub fn load(isf: isf::Scene, root_path: PathBuf) -> Self {
+    pub fn load(isf: isf::Scene, root_path: PathBuf, working_space: WorkingSpace) -> Self {
         let mut texture_bank = TextureBank::new(root_path);
-        let models = isf
+        let material = |model: &isf::Model| {
+            let (isf::Model::Sphere { material, .. } | isf::Model::Mesh { material, .. }) = model;
+            material.id
+        };
+        // Models without a material index get one of their own, after the indices of the file
+        let mut next_id = isf
+            .models
+            .iter()
+            .filter_map(material)
+            .max()
+            .map_or(0, |id| id + 1);
+        let material_ids = isf
+            .models
+            .iter()
+            .map(|model| {
+                material(model).unwrap_or_else(|| {
+                    next_id += 1;
+                    next_id - 1
+                })
+            })
+            .collect();
+        let models: Vec<_> = isf
             .models
             .into_iter()
-            .map(|m| Model::load(m, &mut texture_bank))
+            .map(|m| Model::load(m, &mut texture_bank, working_space))
             .collect();
         let kdtree = KDTree::build(&models);
+        let emissive_lights = EmissiveLights::build(&models);
+        let environment = isf
+            .environment
+            .map(|e| Environment::load(e, &mut texture_bank, working_space));
+        let camera: Camera = isf.camera.into();
+        let lights: Vec<Light> = isf
+            .lights
+            .into_iter()
+            .map(|l| Light::load(l, working_space))
+            .collect();
+        let medium: Option<Medium> = isf.medium.map(|m| Medium::load(m, working_space));
+        let has_media = medium.is_some()
+            || models
+                .iter()
+                .any(|model| model.get_material().medium.is_some());
+
+        // Bounds of the scene (limiting the global medium)
+        let mut bounds = kdtree.bound();
+        for position in lights
+            .iter()
+            .filter_map(|light| light.position())
+            .chain([camera.position()])
+        {
+            bounds.merge(&AABB::new(position, position));
+        }
 
         Self {
             kdtree,
             models,
-            camera: isf.camera.into(),
-            lights: isf.lights.into_iter().map(|l| l.into()).collect(),
-            background: isf.background.into(),
+            camera,
+            lights,
+            emissive_lights,
+            background: working_space.convert_rec709(isf.background.into()),
+            environment,
+            medium,
+            has_media,
+            bounds,
+            material_ids,
         }
     }
 }
@@ -1,5 +1,6 @@
 use super::texture_bank::TextureBank;
 use super::{Material, Triangle};
+use crate::renderer::color::WorkingSpace;
 use crate::renderer::{Hit, Intersectable, Ray};
 use crate::scene::isf;
 use cgmath::InnerSpace;
@@ -46,6 +47,7 @@ impl Intersectable<Vec<Hit>> for Model {
                     dist: (hit_point - ray.origin).magnitude(),
                     position: hit_point,
                     normal,
+                    is_backface: true,
                 };
                 if t1 < 0.0 {
                     // We are inside the sphere
@@ -58,6 +60,7 @@ impl Intersectable<Vec<Hit>> for Model {
                         dist: (hit_point - ray.origin).magnitude(),
                         position: hit_point,
                         normal,
+                        is_backface: false,
                     };
                     vec![hit_t1, hit_t2]
                 }
@@ -86,18 +89,22 @@ impl Bounded for Model {
 }
 
 impl Model {
-    pub fn load(isf: isf::Model, texture_bank: &mut TextureBank) -> Self {
+    pub fn load(
+        isf: isf::Model,
+        texture_bank: &mut TextureBank,
+        working_space: WorkingSpace,
+    ) -> Self {
         match isf {
             isf::Model::Mesh {
                 triangles,
                 material,
             } => {
-                let triangles = triangles.into_iter().map(|t| t.into()).collect();
+                let triangles: Vec<_> = triangles.into_iter().map(|t| t.into()).collect();
                 let kdtree = KDTree::build(&triangles);
                 Model::Mesh {
                     triangles,
                     kdtree,
-                    material: Material::load(material, texture_bank),
+                    material: Material::load(material, texture_bank, working_space),
                 }
             }
             isf::Model::Sphere {
@@ -107,7 +114,7 @@ impl Model {
             } => Model::Sphere {
                 radius,
                 center: center.into(),
-                material: Material::load(material, texture_bank),
+                material: Material::load(material, texture_bank, working_space),
             },
         }
     }
@@ -1,12 +1,13 @@
 use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
-use image::{GrayImage, RgbImage};
+use image::{GrayImage, Rgb32FImage, RgbImage};
 
 #[derive(Debug)]
 pub struct TextureBank {
     pub root_path: PathBuf,
     pub rgb_textures: HashMap<String, Arc<RgbImage>>,
     pub gray_textures: HashMap<String, Arc<GrayImage>>,
+    pub hdr_textures: HashMap<String, Arc<Rgb32FImage>>,
 }
 
 impl TextureBank {
@@ -15,6 +16,7 @@ impl TextureBank {
             root_path,
             rgb_textures: Default::default(),
             gray_textures: Default::default(),
+            hdr_textures: Default::default(),
         }
     }
 
@@ -49,4 +51,20 @@ impl TextureBank {
             .or_insert_with(|| Arc::new(image::open(&path).unwrap().into_luma8()))
             .clone()
     }
+
+    pub fn get_hdr(&mut self, path: String) -> Arc<Rgb32FImage> {
+        let path: String = self
+            .root_path
+            .join(path)
+            .canonicalize()
+            .expect("Invalid path")
+            .to_str()
+            .unwrap()
+            .into();
+
+        self.hdr_textures
+            .entry(path.clone())
+            .or_insert_with(|| Arc::new(image::open(&path).unwrap().into_rgb32f()))
+            .clone()
+    }
 }
@@ -34,6 +34,22 @@ impl IndexMut<usize> for Triangle {
     }
 }
 
+impl Triangle {
+    /// Surface area of the triangle
+    pub fn area(&self) -> f32 {
+        let v0v1 = self[1].position - self[0].position;
+        let v0v2 = self[2].position - self[0].position;
+        v0v1.cross(v0v2).magnitude() / 2.
+    }
+
+    /// Normal of the triangle plane (ignoring vertex normals)
+    pub fn face_normal(&self) -> Vector3<f32> {
+        let v0v1 = self[1].position - self[0].position;
+        let v0v2 = self[2].position - self[0].position;
+        v0v1.cross(v0v2).normalize()
+    }
+}
+
 impl Intersectable<Option<Hit>> for Triangle {
     fn intersect(&self, ray: &Ray) -> Option<Hit> {
         // -----------------
@@ -13,6 +13,53 @@ pub struct Scene {
     pub lights: Vec<Light>,
     /// Background color of the scene
     pub background: [f32; 3],
+    /// HDR environment replacing the background color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+    /// Global medium (fog) filling the bounding box of the scene
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<Medium>,
+}
+
+impl Scene {
+    /// Paths of the textures used by the scene (relative to the scene file), without duplicates
+    pub fn textures(&self) -> Vec<&str> {
+        let mut textures = vec![];
+        let environment = self.environment.iter().map(|e| &e.texture);
+        let materials = self.models.iter().flat_map(|model| {
+            let (Model::Sphere { material, .. } | Model::Mesh { material, .. }) = model;
+            [
+                &material.albedo.texture,
+                &material.emissive.texture,
+                &material.opacity.texture,
+                &material.metalness.texture,
+                &material.roughness.texture,
+                &material.transmission.texture,
+                &material.normal_texture,
+            ]
+            .into_iter()
+            .flatten()
+        });
+        for texture in environment.chain(materials) {
+            if !textures.contains(&texture.as_str()) {
+                textures.push(texture.as_str());
+            }
+        }
+        textures
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Custom format of an equirectangular environment map
+pub struct Environment {
+    /// Path of the HDR texture (Radiance `.hdr` or OpenEXR)
+    pub texture: String,
+    /// Rotation around the up axis in radians
+    #[serde(default)]
+    pub rotation: f32,
+    /// Multiplier applied to the texture radiance
+    #[serde(default = "One::one")]
+    pub intensity: f32,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
@@ -63,9 +110,21 @@ pub enum Light {
         position: [f32; 3],
         /// RGB color
         color: [f32; 3],
-        /// Size of the light in meters
+        /// Radius of the light sphere in meters (0 for a perfect point light)
         size: f32,
     },
+    Spot {
+        /// Position of the light
+        position: [f32; 3],
+        /// Direction the light is pointing to
+        direction: [f32; 3],
+        /// RGB color
+        color: [f32; 3],
+        /// Angle in radians from the direction where the falloff begins
+        inner_cone_angle: f32,
+        /// Angle in radians from the direction where the falloff ends
+        outer_cone_angle: f32,
+    },
     Directional {
         /// Direction of the light
         direction: [f32; 3],
@@ -77,6 +136,10 @@ pub enum Light {
 /// Custom format of a material
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Material {
+    /// Index of the material, shared by the models using it (the glTF material when converted).
+    /// Models without one get an index of their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
     /// Albedo
     pub albedo: Albedo,
     /// Emissive
@@ -91,9 +154,23 @@ pub struct Material {
     /// Roughness
     #[serde(default)]
     pub roughness: Roughness,
+    /// Transmission (probability of the light to go through the surface)
+    #[serde(default)]
+    pub transmission: Transmission,
+    /// Subsurface scattering inside a closed mesh or a sphere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsurface: Option<Subsurface>,
+    /// Homogeneous medium filling the inside of a closed mesh or a sphere.
+    /// An invisible container can be made using a null opacity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<Medium>,
     /// Index of refraction
     #[serde(default = "One::one")]
     pub ior: f32,
+    /// Index of refraction varying with the wavelength, replacing `ior`. It splits the light in
+    /// spectral rendering.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispersion: Option<Dispersion>,
     /// Normal texture
     pub normal_texture: Option<String>,
 }
@@ -137,6 +214,46 @@ pub struct Roughness {
     pub texture: Option<String>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct Transmission {
+    #[serde(default)]
+    pub factor: f32,
+    pub texture: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Subsurface {
+    /// Single scattering albedo of the medium
+    #[serde(default = "one")]
+    pub albedo: [f32; 3],
+    /// Mean free path in meters for each channel
+    pub radius: [f32; 3],
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+/// Index of refraction as a function of the wavelength (in micrometers)
+pub enum Dispersion {
+    /// n = a + b / λ²
+    Cauchy { a: f32, b: f32 },
+    /// n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Homogeneous participating medium
+pub struct Medium {
+    /// Absorption coefficient (per meter) for each channel
+    #[serde(default)]
+    pub absorption: [f32; 3],
+    /// Scattering coefficient (per meter) for each channel
+    #[serde(default)]
+    pub scattering: [f32; 3],
+    /// Henyey-Greenstein asymmetry between -1 (backward) and 1 (forward scattering)
+    #[serde(default)]
+    pub anisotropy: f32,
+}
+
 fn one() -> [f32; 3] {
     [1.0, 1.0, 1.0]
 }
@@ -11,12 +11,33 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use crate::renderer::color::WorkingSpace;
 use internal::Scene;
 
-pub fn load_internal<P: AsRef<Path>>(path: P) -> Result<Scene, Box<dyn Error + Send + Sync>> {
+/// Load an ISF scene, its colors converted to the working space
+pub fn load_internal<P: AsRef<Path>>(
+    path: P,
+    working_space: WorkingSpace,
+) -> Result<Scene, Box<dyn Error + Send + Sync>> {
     let file = File::open(&path)?;
     let reader = BufReader::new(file);
     let isf_scene = serde_json::from_reader(reader)?;
     let root_path = PathBuf::from(path.as_ref()).parent().unwrap().to_path_buf();
-    Ok(Scene::load(isf_scene, root_path))
+    Ok(Scene::load(isf_scene, root_path, working_space))
+}
+
+/// Files an ISF scene is made of: the scene file, then the textures it uses
+pub fn scene_files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let file = File::open(&path)?;
+    let reader = BufReader::new(file);
+    let isf_scene: isf::Scene = serde_json::from_reader(reader)?;
+    let root_path = PathBuf::from(path.as_ref()).parent().unwrap().to_path_buf();
+    let textures = isf_scene
+        .textures()
+        .into_iter()
+        .map(|texture| root_path.join(texture));
+    Ok([path.as_ref().to_path_buf()]
+        .into_iter()
+        .chain(textures)
+        .collect())
 }